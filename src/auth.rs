@@ -0,0 +1,437 @@
+//! Finds the token to authenticate with a Github host. The sources are tried
+//! in order, and the first one that has a token for the host is used:
+//! 1. `GH_TOKEN` or `GITHUB_TOKEN` for github.com, and `GH_ENTERPRISE_TOKEN` or
+//!    `GITHUB_ENTERPRISE_TOKEN` for other hosts, the same as the `gh` CLI.
+//! 2. The token or Github App configured for the host, or for github.com only,
+//!    the top level `token` or `GH_PR_TOKEN`. Fine grained personal access
+//!    tokens are used the same as any other token.
+//! 3. The token saved by `git ghpr auth login`.
+//! 4. The `hosts.yml` of the `gh` CLI, for people already logged in with it.
+//! 5. `git credential fill`, the same credentials git uses for HTTPS remotes.
+use std::collections::HashMap;
+use std::fmt::Formatter;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use directories::BaseDirs;
+use git2::Repository;
+use serde::Deserialize;
+use tracing::{debug, info};
+
+use crate::configuration::Configuration;
+use crate::github_app::App;
+use crate::login::stored_token;
+use crate::remote::get_github_repository;
+use crate::result::Error;
+use crate::result::Message;
+use crate::result::Result;
+
+#[derive(Debug)]
+pub struct Credential {
+    pub token: String,
+    pub source: Source,
+}
+
+/// Where a token was found.
+#[derive(Debug, PartialEq)]
+pub enum Source {
+    Environment(&'static str),
+    HostConfiguration,
+    GithubApp { app_id: u64, installation_id: u64 },
+    Configuration,
+    Login(PathBuf),
+    GhCli(PathBuf),
+    GitCredential,
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Self::Environment(v) => write!(f, "{v} environment variable"),
+            Self::HostConfiguration => {
+                write!(f, "host token in the configuration file")
+            }
+            Self::GithubApp {
+                app_id,
+                installation_id,
+            } => write!(
+                f,
+                "Github App {app_id} (installation {installation_id})"
+            ),
+            Self::Configuration => write!(f, "token in the configuration file"),
+            Self::Login(p) => write!(f, "auth login ({})", p.display()),
+            Self::GhCli(p) => write!(f, "gh CLI ({})", p.display()),
+            Self::GitCredential => write!(f, "git credential fill"),
+        }
+    }
+}
+
+impl Credential {
+    /// The token with all but the first few characters hidden, enough to tell
+    /// which kind of token it is, without giving it away.
+    pub fn redacted(&self) -> String {
+        redact(&self.token)
+    }
+}
+
+/// Gets the token for `host`, or fails with an error explaining how to
+/// configure one.
+pub async fn get_credential(
+    options: &Configuration,
+    host: &str,
+) -> Result<Credential> {
+    find_credential(options, host)
+        .await?
+        .ok_or_else(|| Error::NoToken(host.to_string()))
+}
+
+/// Shows where the token for a host comes from, for `auth status`.
+pub async fn status(
+    options: &Configuration,
+    hostname: &Option<String>,
+) -> Result<Message> {
+    let host = match hostname {
+        Some(h) => h.to_lowercase(),
+        None => default_host(options),
+    };
+
+    let credential = get_credential(options, &host).await?;
+
+    Ok(Message::AuthStatus {
+        host,
+        source: credential.source.to_string(),
+        token: credential.redacted(),
+    })
+}
+
+async fn find_credential(
+    options: &Configuration,
+    host: &str,
+) -> Result<Option<Credential>> {
+    let mut credential = from_environment(host);
+    if credential.is_none() {
+        credential = from_configuration(options, host).await?;
+    }
+    let credential = credential
+        .or_else(|| from_login(host))
+        .or_else(|| from_gh_cli(host))
+        .or_else(|| from_git_credential(host));
+
+    if let Some(c) = &credential {
+        info!("Using the Github token for {host} from the {}.", c.source);
+    }
+    Ok(credential)
+}
+
+/// The host of the pull request remote, when in a repository where that can be
+/// worked out, otherwise github.com.
+pub fn default_host(options: &Configuration) -> String {
+    Repository::discover(".")
+        .ok()
+        .and_then(|repo| get_github_repository(&repo, &options.pr_remote).ok())
+        .map(|r| r.host)
+        .unwrap_or_else(|| "github.com".to_string())
+}
+
+fn from_environment(host: &str) -> Option<Credential> {
+    environment_variables(host).iter().find_map(|v| {
+        let token = non_empty(std::env::var(v).ok())?;
+        Some(Credential {
+            token,
+            source: Source::Environment(v),
+        })
+    })
+}
+
+/// The environment variables that hold the token for `host`, in the order the
+/// `gh` CLI reads them.
+fn environment_variables(host: &str) -> &'static [&'static str] {
+    if host.eq_ignore_ascii_case("github.com") {
+        &["GH_TOKEN", "GITHUB_TOKEN"]
+    } else {
+        &["GH_ENTERPRISE_TOKEN", "GITHUB_ENTERPRISE_TOKEN"]
+    }
+}
+
+async fn from_configuration(
+    options: &Configuration,
+    host: &str,
+) -> Result<Option<Credential>> {
+    if let Some(host_options) = options.hosts.get(host) {
+        if let Some(token) = non_empty(host_options.token.clone()) {
+            return Ok(Some(Credential {
+                token,
+                source: Source::HostConfiguration,
+            }));
+        }
+
+        if let Some(app) = App::from_options(host, host_options)? {
+            let token = app.installation_token(&options.host(host)).await?;
+            return Ok(Some(Credential {
+                token,
+                source: Source::GithubApp {
+                    app_id: app.app_id,
+                    installation_id: app.installation_id,
+                },
+            }));
+        }
+    }
+
+    // A github.com token mustn't be sent to whoever runs some other host, so
+    // an Enterprise host only gets a token configured for it.
+    if !host.eq_ignore_ascii_case("github.com") {
+        return Ok(None);
+    }
+    let token = match non_empty(options.token.clone()) {
+        Some(t) => t,
+        None => return Ok(None),
+    };
+    // The top level `token` can come from the file or the environment, and
+    // which one is worth knowing when trying to work out why a token is used.
+    let source = if std::env::var("GH_PR_TOKEN").ok().as_ref() == Some(&token) {
+        Source::Environment("GH_PR_TOKEN")
+    } else {
+        Source::Configuration
+    };
+    Ok(Some(Credential { token, source }))
+}
+
+fn from_login(host: &str) -> Option<Credential> {
+    let (token, path) = stored_token(host)?;
+    Some(Credential {
+        token: non_empty(Some(token))?,
+        source: Source::Login(path),
+    })
+}
+
+/// The parts of an entry in the `gh` CLI `hosts.yml` that matter here.
+#[derive(Debug, Deserialize)]
+struct GhHost {
+    oauth_token: Option<String>,
+    user: Option<String>,
+    /// Newer versions of `gh` support several accounts per host, with the
+    /// tokens stored per user.
+    #[serde(default)]
+    users: HashMap<String, GhUser>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GhUser {
+    oauth_token: Option<String>,
+}
+
+fn from_gh_cli(host: &str) -> Option<Credential> {
+    let path = gh_config_dir()?.join("hosts.yml");
+    let text = match std::fs::read_to_string(&path) {
+        Ok(t) => t,
+        Err(e) => {
+            debug!("Could not read {:?}: {}", path, e);
+            return None;
+        }
+    };
+
+    let token = gh_token(&text, host)?;
+    Some(Credential {
+        token,
+        source: Source::GhCli(path),
+    })
+}
+
+/// Finds the token for `host` in the text of a `gh` CLI `hosts.yml`. When `gh`
+/// keeps its tokens in the system keyring there is no token in the file.
+fn gh_token(hosts_yml: &str, host: &str) -> Option<String> {
+    let hosts: HashMap<String, GhHost> = match serde_yaml::from_str(hosts_yml) {
+        Ok(h) => h,
+        Err(e) => {
+            debug!("Could not parse the gh hosts.yml: {}", e);
+            return None;
+        }
+    };
+
+    let entry = hosts
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(host))
+        .map(|(_, entry)| entry)?;
+
+    non_empty(entry.oauth_token.clone()).or_else(|| {
+        let user = entry.users.get(entry.user.as_ref()?)?;
+        non_empty(user.oauth_token.clone())
+    })
+}
+
+/// Where the `gh` CLI keeps its configuration, following the same rules it
+/// does.
+fn gh_config_dir() -> Option<PathBuf> {
+    if let Some(dir) = non_empty(std::env::var("GH_CONFIG_DIR").ok()) {
+        return Some(PathBuf::from(dir));
+    }
+    if let Some(dir) = non_empty(std::env::var("XDG_CONFIG_HOME").ok()) {
+        return Some(PathBuf::from(dir).join("gh"));
+    }
+    let base_dirs = BaseDirs::new()?;
+    if cfg!(windows) {
+        Some(base_dirs.config_dir().join("GitHub CLI"))
+    } else {
+        Some(base_dirs.home_dir().join(".config").join("gh"))
+    }
+}
+
+fn from_git_credential(host: &str) -> Option<Credential> {
+    let token = match git_credential_fill(host) {
+        Ok(t) => t?,
+        Err(e) => {
+            debug!("git credential fill failed: {}", e);
+            return None;
+        }
+    };
+    Some(Credential {
+        token,
+        source: Source::GitCredential,
+    })
+}
+
+/// Asks git for the HTTPS password it has for `host`. Prompting is turned
+/// off, only stored credentials are wanted.
+fn git_credential_fill(host: &str) -> std::io::Result<Option<String>> {
+    let mut child = Command::new("git")
+        .args(["credential", "fill"])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        write!(stdin, "protocol=https\nhost={host}\n\n")?;
+    }
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let password = stdout
+        .lines()
+        .find_map(|l| l.strip_prefix("password="))
+        .map(String::from);
+    Ok(non_empty(password))
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.filter(|v| !v.trim().is_empty())
+}
+
+fn redact(token: &str) -> String {
+    const VISIBLE: usize = 4;
+    if token.chars().count() <= VISIBLE * 2 {
+        return "*".repeat(8);
+    }
+    let visible: String = token.chars().take(VISIBLE).collect();
+    format!("{visible}{}", "*".repeat(8))
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn environment_variables_for_host() {
+        assert_that!(environment_variables("github.com"))
+            .is_equal_to(&["GH_TOKEN", "GITHUB_TOKEN"][..]);
+        assert_that!(environment_variables("GitHub.com"))
+            .is_equal_to(&["GH_TOKEN", "GITHUB_TOKEN"][..]);
+        assert_that!(environment_variables("github.example.com")).is_equal_to(
+            &["GH_ENTERPRISE_TOKEN", "GITHUB_ENTERPRISE_TOKEN"][..],
+        );
+    }
+
+    #[tokio::test]
+    async fn top_level_token_only_for_github() {
+        let options = Configuration {
+            token: Some("github-token".to_string()),
+            ..Configuration::for_test()
+        };
+
+        let github = from_configuration(&options, "github.com").await;
+        let enterprise =
+            from_configuration(&options, "github.example.com").await;
+
+        assert_that!(github.unwrap().map(|c| c.token))
+            .is_equal_to(Some("github-token".to_string()));
+        assert_that!(enterprise.unwrap().map(|c| c.token)).is_none();
+    }
+
+    #[test]
+    fn gh_token_for_host() {
+        let hosts_yml = r#"
+github.com:
+    user: octocat
+    oauth_token: gho_github
+    git_protocol: https
+github.example.com:
+    user: octocat
+    oauth_token: gho_enterprise
+"#;
+
+        assert_that!(gh_token(hosts_yml, "github.com"))
+            .is_equal_to(Some("gho_github".to_string()));
+        assert_that!(gh_token(hosts_yml, "github.example.com"))
+            .is_equal_to(Some("gho_enterprise".to_string()));
+        assert_that!(gh_token(hosts_yml, "GitHub.com"))
+            .is_equal_to(Some("gho_github".to_string()));
+    }
+
+    #[test]
+    fn gh_token_for_unknown_host() {
+        let hosts_yml = "github.com:\n    oauth_token: gho_github\n";
+
+        assert_that!(gh_token(hosts_yml, "github.example.com")).is_none();
+    }
+
+    #[test]
+    fn gh_token_for_multiple_accounts() {
+        let hosts_yml = r#"
+github.com:
+    git_protocol: ssh
+    users:
+        octocat:
+            oauth_token: gho_octocat
+        hubot:
+            oauth_token: gho_hubot
+    user: hubot
+"#;
+
+        assert_that!(gh_token(hosts_yml, "github.com"))
+            .is_equal_to(Some("gho_hubot".to_string()));
+    }
+
+    #[test]
+    fn gh_token_in_keyring() {
+        let hosts_yml = r#"
+github.com:
+    git_protocol: https
+    users:
+        octocat:
+    user: octocat
+"#;
+
+        assert_that!(gh_token(hosts_yml, "github.com")).is_none();
+    }
+
+    #[test]
+    fn gh_token_from_invalid_file() {
+        assert_that!(gh_token("not: [valid", "github.com")).is_none();
+        assert_that!(gh_token("", "github.com")).is_none();
+    }
+
+    #[test]
+    fn redacted_token() {
+        assert_that!(redact("ghp_0123456789abcdef"))
+            .is_equal_to("ghp_********".to_string());
+        assert_that!(redact("short")).is_equal_to("********".to_string());
+    }
+}
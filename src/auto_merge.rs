@@ -0,0 +1,139 @@
+//! Has Github merge a pull request once it can be, either by adding it to the
+//! merge queue of its base branch, or by turning on auto-merge when there is
+//! no merge queue. Neither is available through the REST API, so this uses
+//! GraphQL.
+use std::collections::HashMap;
+
+use git2::Repository;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::info;
+
+use crate::auth;
+use crate::common::{
+    branches_with_open_pull_requests, get_branch_for_commit,
+    get_remote_branch_name, get_selected_commit,
+};
+use crate::configuration::{Configuration, MergeMethod};
+use crate::github::{Client, PullRequest};
+use crate::remote::{get_github_repository, GithubRepository};
+use crate::result::Error;
+use crate::result::Message;
+use crate::result::Result;
+
+const REPOSITORY_QUERY: &str = r#"query($owner: String!, $name: String!, $branch: String!) {
+  repository(owner: $owner, name: $name) {
+    autoMergeAllowed
+    mergeQueue(branch: $branch) { id }
+  }
+}"#;
+
+const ENQUEUE_MUTATION: &str = r#"mutation($id: ID!) {
+  enqueuePullRequest(input: {pullRequestId: $id}) { clientMutationId }
+}"#;
+
+const AUTO_MERGE_MUTATION: &str = r#"mutation($id: ID!, $method: PullRequestMergeMethod!) {
+  enablePullRequestAutoMerge(input: {pullRequestId: $id, mergeMethod: $method}) { clientMutationId }
+}"#;
+
+#[derive(Debug, Deserialize)]
+struct RepositoryData {
+    repository: RepositorySettings,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RepositorySettings {
+    auto_merge_allowed: bool,
+    merge_queue: Option<serde_json::Value>,
+}
+
+/// Turns on auto-merge for the pull request of the selected commit.
+pub async fn automerge(
+    options: &Configuration,
+    method: MergeMethod,
+) -> Result<Message> {
+    info!("Opening the local git repository.");
+    let repo = Repository::discover(".")?;
+
+    let commit = get_selected_commit(&repo)?;
+    let open_pull_requests =
+        branches_with_open_pull_requests(&repo, options).await?;
+    let branch = get_branch_for_commit(
+        &repo,
+        &commit,
+        &options.branch_name_template,
+        &HashMap::new(),
+        &open_pull_requests,
+    )?
+    .ok_or_else(|| Error::NoBranch(commit.id().to_string()))?;
+    let remote_name = get_remote_branch_name(&repo, &branch)?;
+
+    let push_repository = get_github_repository(&repo, &options.push_remote)?;
+    let pr_repository = get_github_repository(&repo, &options.pr_remote)?;
+    let credential = auth::get_credential(options, &pr_repository.host).await?;
+    let client =
+        Client::new(&options.host(&pr_repository.host), &credential.token)?;
+
+    let head = format!("{}:{}", push_repository.owner, remote_name);
+    let pr = client
+        .find_pull_request(&pr_repository, &head)
+        .await?
+        .ok_or_else(|| Error::NoPullRequest(remote_name.clone()))?;
+
+    let queued =
+        enable(&client, &pr_repository, &pr, &pr.base.name, method).await?;
+    Ok(Message::AutoMergeEnabled {
+        url: pr.html_url,
+        queued,
+    })
+}
+
+/// Adds `pr` to the merge queue for `base` when there is one, and otherwise
+/// turns on auto-merge with `method`. Returns whether it was queued.
+pub async fn enable(
+    client: &Client,
+    repo: &GithubRepository,
+    pr: &PullRequest,
+    base: &str,
+    method: MergeMethod,
+) -> Result<bool> {
+    let settings: RepositoryData = client
+        .graphql(
+            REPOSITORY_QUERY,
+            json!({"owner": repo.owner, "name": repo.name, "branch": base}),
+        )
+        .await?;
+
+    if settings.repository.merge_queue.is_some() {
+        info!("Adding pull request #{} to the merge queue.", pr.number);
+        client
+            .graphql::<serde_json::Value>(
+                ENQUEUE_MUTATION,
+                json!({"id": pr.node_id}),
+            )
+            .await?;
+        return Ok(true);
+    }
+
+    if !settings.repository.auto_merge_allowed {
+        return Err(Error::AutoMergeNotAllowed(repo.to_string()));
+    }
+
+    info!("Turning on auto-merge for pull request #{}.", pr.number);
+    client
+        .graphql::<serde_json::Value>(
+            AUTO_MERGE_MUTATION,
+            json!({"id": pr.node_id, "method": graphql_merge_method(method)}),
+        )
+        .await?;
+    Ok(false)
+}
+
+fn graphql_merge_method(method: MergeMethod) -> &'static str {
+    match method {
+        MergeMethod::Merge => "MERGE",
+        MergeMethod::Squash => "SQUASH",
+        MergeMethod::Rebase => "REBASE",
+    }
+}
@@ -0,0 +1,100 @@
+//! Checks out the stack of someone else's pull request for review. Each pull
+//! request in the stack gets a local branch, so the whole stack shows in the
+//! git-branchless smartlog.
+use git2::{BranchType, Repository};
+use tracing::info;
+
+use crate::auth;
+use crate::configuration::Configuration;
+use crate::git;
+use crate::github::Client;
+use crate::remote::{get_github_repository, parse_pull_request_url};
+use crate::result::Error;
+use crate::result::Message;
+use crate::result::Result;
+
+/// The branch configuration that marks a branch as made by `checkout`, and so
+/// safe to move wherever its pull request has gone.
+const MADE_BY_CHECKOUT: &str = "ghprCheckout";
+
+/// Checks out the stack ending in `pull_request`, either a number or a URL.
+/// * Follow the base of each pull request down, to the pull request for that
+///   branch, until the base has no pull request, which is usually the main
+///   branch.
+/// * Fetch the head of each pull request into the local branch `pr/<number>`,
+///   from the bottom of the stack up. The pull request's own branch name could
+///   be one of the user's branches, such as `main` from a fork. A branch this
+///   made before is updated to wherever the pull request is now, even when
+///   the stack has been rebased, but a branch the user made is only moved
+///   forward.
+/// * Check out the top of the stack, detached.
+pub async fn checkout(
+    options: &Configuration,
+    pull_request: &str,
+) -> Result<Message> {
+    info!("Opening the local git repository.");
+    let repo = Repository::discover(".")?;
+
+    let pr_repository = get_github_repository(&repo, &options.pr_remote)?;
+    let number = match pull_request.parse::<u64>() {
+        Ok(n) => n,
+        Err(_) => match parse_pull_request_url(pull_request) {
+            Some((r, n)) if r == pr_repository => n,
+            Some(_) => {
+                return Err(Error::BadParameter(format!(
+                    "{pull_request} isn't in {pr_repository}, the repository of {}.",
+                    options.pr_remote
+                )))
+            }
+            None => {
+                return Err(Error::BadParameter(format!(
+                    "{pull_request} isn't a pull request number or URL."
+                )))
+            }
+        },
+    };
+
+    let credential = auth::get_credential(options, &pr_repository.host).await?;
+    let client =
+        Client::new(&options.host(&pr_repository.host), &credential.token)?;
+
+    let mut stack =
+        vec![client.get_pull_request(&pr_repository, number).await?];
+    loop {
+        let base = &stack[stack.len() - 1].base.name;
+        // Stacked pull requests are onto branches in the same repository.
+        let head = format!("{}:{base}", pr_repository.owner);
+        match client.find_pull_request(&pr_repository, &head).await? {
+            Some(pr) if !stack.iter().any(|p| p.number == pr.number) => {
+                stack.push(pr)
+            }
+            _ => break,
+        }
+    }
+    stack.reverse();
+
+    let mut branches = Vec::new();
+    for pr in stack {
+        let branch = format!("pr/{}", pr.number);
+        let made_here = format!("branch.{branch}.{MADE_BY_CHECKOUT}");
+        let exists = repo.find_branch(&branch, BranchType::Local).is_ok();
+        let ours =
+            !exists || repo.config()?.get_bool(&made_here).unwrap_or(false);
+        git::fetch_pull_request(
+            &repo,
+            &options.pr_remote,
+            pr.number,
+            &branch,
+            ours,
+        )?;
+        if ours {
+            repo.config()?.set_bool(&made_here, true)?;
+        }
+        branches.push((pr.html_url, branch));
+    }
+    if let Some((_, top)) = branches.last() {
+        git::checkout_detached(&repo, top)?;
+    }
+
+    Ok(Message::CheckedOut { branches })
+}
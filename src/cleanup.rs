@@ -0,0 +1,174 @@
+//! Deletes the branches left over once their pull requests are done with. A
+//! branch is created for every commit a pull request is opened for, so they
+//! pile up quickly.
+use git2::{Branch, BranchType, Oid, Repository};
+use tracing::info;
+
+use crate::auth;
+use crate::common::{
+    branch_name, get_main_branch_commit, get_remote_branch_name,
+    upstream_remote,
+};
+use crate::configuration::Configuration;
+use crate::git;
+use crate::github::Client;
+use crate::patch_id;
+use crate::remote::{get_github_repository, GithubRepository};
+use crate::result::Message;
+use crate::result::Result;
+
+/// A branch that can be cleaned up, and why.
+#[derive(Debug, PartialEq)]
+pub struct Finished {
+    pub branch: String,
+    pub reason: String,
+}
+
+/// Deletes, locally and on the remote, the branches whose commits are in the
+/// main branch, or whose pull request was merged or closed. The branch that
+/// is checked out is left alone. Pull requests onto a branch are moved onto
+/// its base before it is deleted from the remote, or Github closes them. With
+/// `dry_run`, nothing is deleted.
+pub async fn cleanup(
+    options: &Configuration,
+    dry_run: bool,
+) -> Result<Message> {
+    info!("Opening the local git repository.");
+    let repo = Repository::discover(".")?;
+
+    let (main_commit, main_branch) = get_main_branch_commit(&repo)?;
+    let main_name = branch_name(&main_branch)?;
+
+    let push_repository = get_github_repository(&repo, &options.push_remote)?;
+    let pr_repository = get_github_repository(&repo, &options.pr_remote)?;
+    let credential = auth::get_credential(options, &pr_repository.host).await?;
+    let client =
+        Client::new(&options.host(&pr_repository.host), &credential.token)?;
+
+    let mut branches = Vec::new();
+    for entry in repo.branches(Some(BranchType::Local))? {
+        let (branch, _) = entry?;
+        let name = branch_name(&branch)?;
+        if name != main_name {
+            branches.push((name, branch));
+        }
+    }
+    // `repo.branches()` has no particular order.
+    branches.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut deleted = Vec::new();
+    let mut kept = Vec::new();
+    let mut retargeted = Vec::new();
+    for (name, mut branch) in branches {
+        let (reason, base) = match finished(
+            &repo,
+            &client,
+            &pr_repository,
+            &push_repository.owner,
+            &branch,
+            main_commit.id(),
+            &main_name,
+        )
+        .await?
+        {
+            Some(r) => r,
+            None => continue,
+        };
+
+        if branch.is_head() {
+            kept.push(Finished {
+                branch: name,
+                reason: format!("{reason}, but it is checked out"),
+            });
+            continue;
+        }
+
+        if !dry_run {
+            if let Some(remote) = upstream_remote(&repo, &name) {
+                let remote_name = get_remote_branch_name(&repo, &branch)?;
+                for child in client
+                    .find_pull_requests_with_base(&pr_repository, &remote_name)
+                    .await?
+                {
+                    client
+                        .update_pull_request_base(
+                            &pr_repository,
+                            child.number,
+                            &base,
+                        )
+                        .await?;
+                    retargeted.push((child.html_url, base.clone()));
+                }
+                git::delete_remote_branch(&repo, &remote, &remote_name)?;
+            }
+            info!("Deleting {name}.");
+            branch.delete()?;
+        }
+        deleted.push(Finished {
+            branch: name,
+            reason,
+        });
+    }
+
+    Ok(Message::CleanedUp {
+        dry_run,
+        deleted,
+        kept,
+        retargeted,
+    })
+}
+
+/// Why `branch` is finished with, if it is, and the branch it went onto, which
+/// is the base of its pull request, or else the main branch. Looking on Github
+/// is slowest, so it is left until last.
+async fn finished(
+    repo: &Repository,
+    client: &Client,
+    pr_repository: &GithubRepository,
+    push_owner: &str,
+    branch: &Branch<'_>,
+    main: Oid,
+    main_name: &str,
+) -> Result<Option<(String, String)>> {
+    let tip = branch.get().peel_to_commit()?.id();
+    if tip == main || repo.graph_descendant_of(main, tip)? {
+        return Ok(Some((format!("it is in {main_name}"), main_name.into())));
+    }
+    if let Some(commit) = patch_id::find_squash_merge(repo, main, tip)? {
+        info!("{tip} is in {main_name} as {commit}.");
+        return Ok(Some((
+            format!("it was squash merged into {main_name}"),
+            main_name.into(),
+        )));
+    }
+
+    let remote_name = match get_remote_branch_name(repo, branch) {
+        Ok(n) => n,
+        Err(_) => return Ok(None),
+    };
+    let head = format!("{push_owner}:{remote_name}");
+    let pr = match client
+        .find_latest_pull_request(pr_repository, &head)
+        .await?
+    {
+        Some(pr) => pr,
+        None => return Ok(None),
+    };
+    let base = match pr.base.name.as_str() {
+        "" => main_name.to_string(),
+        name => name.to_string(),
+    };
+    if pr.merged_at.is_some() {
+        Ok(Some((
+            format!("pull request #{} was merged", pr.number),
+            base,
+        )))
+    } else if pr.state == "closed" {
+        Ok(Some((
+            format!("pull request #{} was closed", pr.number),
+            base,
+        )))
+    } else {
+        Ok(None)
+    }
+}
@@ -0,0 +1,160 @@
+//! Closes the pull request of a commit that was dropped from a stack, which
+//! otherwise stays open forever. The pull requests stacked on it are moved
+//! onto its base, so the rest of the stack can still be reviewed.
+use std::collections::{HashMap, HashSet};
+
+use git2::{Branch, BranchType, Repository};
+use tracing::info;
+
+use crate::auth;
+use crate::common::{
+    branch_name, branches_with_open_pull_requests, get_branch_for_commit,
+    get_remote_branch_name, get_selected_commit,
+};
+use crate::configuration::Configuration;
+use crate::git;
+use crate::github::Client;
+use crate::notes;
+use crate::remote::get_github_repository;
+use crate::result::Error;
+use crate::result::Message;
+use crate::result::Result;
+
+/// What to close the pull request of.
+#[derive(Debug)]
+pub enum Target<'a> {
+    /// The selected commit.
+    Selected,
+    Commit(&'a str),
+    Branch(&'a str),
+}
+
+/// Closes the pull request for the branch of `target`.
+/// * Add `comment` to the pull request, if there is one, and close it.
+/// * Move the pull requests based on its branch onto its own base. This has to
+///   happen before the branch is deleted, or Github closes them too.
+/// * Delete the branch from the remote, and locally if `delete_local` is set.
+pub async fn close(
+    options: &Configuration,
+    target: Target<'_>,
+    comment: Option<&str>,
+    delete_local: bool,
+) -> Result<Message> {
+    info!("Opening the local git repository.");
+    let repo = Repository::discover(".")?;
+
+    // A branch given by name needs no choosing between branches.
+    let open_pull_requests = match target {
+        Target::Branch(_) => HashSet::new(),
+        _ => branches_with_open_pull_requests(&repo, options).await?,
+    };
+    let (branch, remote_name) =
+        find_branch(&repo, options, target, &open_pull_requests)?;
+    let name = match &branch {
+        Some(b) => branch_name(b)?,
+        None => remote_name.clone(),
+    };
+    let mut branch = branch.filter(|_| delete_local);
+    if branch.as_ref().is_some_and(|b| b.is_head()) {
+        return Err(Error::BranchCheckedOut(name));
+    }
+
+    let push_repository = get_github_repository(&repo, &options.push_remote)?;
+    let pr_repository = get_github_repository(&repo, &options.pr_remote)?;
+    let credential = auth::get_credential(options, &pr_repository.host).await?;
+    let client =
+        Client::new(&options.host(&pr_repository.host), &credential.token)?;
+
+    let head = format!("{}:{}", push_repository.owner, remote_name);
+    let pr = client
+        .find_pull_request(&pr_repository, &head)
+        .await?
+        .ok_or_else(|| Error::NoPullRequest(name.clone()))?;
+
+    if let Some(comment) = comment {
+        client
+            .add_comment(&pr_repository, pr.number, comment)
+            .await?;
+    }
+    client.close_pull_request(&pr_repository, pr.number).await?;
+
+    let mut retargeted = Vec::new();
+    for child in client
+        .find_pull_requests_with_base(&pr_repository, &remote_name)
+        .await?
+    {
+        client
+            .update_pull_request_base(
+                &pr_repository,
+                child.number,
+                &pr.base.name,
+            )
+            .await?;
+        retargeted.push(child.html_url);
+    }
+
+    git::delete_remote_branch(&repo, &options.push_remote, &remote_name)?;
+    let deleted = match &mut branch {
+        Some(b) => {
+            info!("Deleting {name}.");
+            b.delete()?;
+            Some(name)
+        }
+        None => None,
+    };
+
+    Ok(Message::Closed {
+        url: pr.html_url,
+        base: pr.base.name,
+        retargeted,
+        deleted,
+    })
+}
+
+/// The local branch of `target`, and the branch it was pushed to. A commit
+/// without a branch anymore can still have its pull request noted on it, with
+/// the branch that was pushed.
+fn find_branch<'a>(
+    repo: &'a Repository,
+    options: &Configuration,
+    target: Target,
+    open_pull_requests: &HashSet<String>,
+) -> Result<(Option<Branch<'a>>, String)> {
+    let commit = match target {
+        Target::Branch(name) => {
+            let branch = repo
+                .find_branch(name, BranchType::Local)
+                .map_err(|_| Error::UnknownBranch(name.to_string()))?;
+            let remote_name = get_remote_branch_name(repo, &branch)?;
+            return Ok((Some(branch), remote_name));
+        }
+        Target::Commit(rev) => repo
+            .revparse_single(rev)
+            .and_then(|o| o.peel_to_commit())
+            .map_err(|_| Error::UnknownCommit(rev.to_string()))?,
+        Target::Selected => get_selected_commit(repo)?,
+    };
+    match get_branch_for_commit(
+        repo,
+        &commit,
+        &options.branch_name_template,
+        &HashMap::new(),
+        open_pull_requests,
+    )? {
+        Some(branch) => {
+            let remote_name = get_remote_branch_name(repo, &branch)?;
+            Ok((Some(branch), remote_name))
+        }
+        None => match notes::read(repo, commit.id()) {
+            Some(note) => {
+                info!(
+                    "{} is noted as being for #{}.",
+                    commit.id(),
+                    note.number
+                );
+                Ok((None, note.branch))
+            }
+            None => Err(Error::NoBranch(commit.id().to_string())),
+        },
+    }
+}
@@ -0,0 +1,377 @@
+//! Reads the code owners of a repository from its `CODEOWNERS` file, so
+//! reviews can be requested from them. Github does this itself, but only for
+//! pull requests onto the default branch, which leaves out most of a stack.
+//!
+//! Like Github, the file is read from the base branch, from `.github`, the
+//! root of the repository, or `docs`, whichever has one first. Each line is a
+//! gitignore style pattern followed by owners, and the last pattern that
+//! matches a file decides its owners.
+//!
+//! https://docs.github.com/en/repositories/managing-your-repositorys-settings-and-features/customizing-your-repository/about-code-owners
+use std::collections::BTreeSet;
+
+use git2::{Commit, Repository, Tree};
+use tracing::{debug, info};
+
+use crate::result::Result;
+
+const LOCATIONS: [&str; 3] =
+    [".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS"];
+
+#[derive(Debug, Default, PartialEq)]
+pub struct CodeOwners {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Rule {
+    pattern: Pattern,
+    owners: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Pattern {
+    segments: Vec<String>,
+    /// Whether the pattern only matches from the root of the repository,
+    /// rather than at any depth.
+    anchored: bool,
+    /// Whether the pattern only matches directories, by ending with `/`.
+    directory: bool,
+}
+
+impl CodeOwners {
+    pub fn parse(text: &str) -> Self {
+        let rules = text
+            .lines()
+            .filter_map(|line| {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    return None;
+                }
+                // Everything after an unescaped `#` is a comment.
+                let line = match line.find(" #") {
+                    Some(i) => &line[..i],
+                    None => line,
+                };
+                let mut fields = line.split_whitespace();
+                let pattern = Pattern::parse(fields.next()?);
+                let owners = fields.map(String::from).collect();
+                Some(Rule { pattern, owners })
+            })
+            .collect();
+        Self { rules }
+    }
+
+    /// The owners of the file at `path`, relative to the root of the
+    /// repository. A pattern with no owners leaves the file without any.
+    pub fn owners(&self, path: &str) -> &[String] {
+        let segments: Vec<&str> = path.split('/').collect();
+        self.rules
+            .iter()
+            .rev()
+            .find(|r| r.pattern.matches(&segments))
+            .map(|r| r.owners.as_slice())
+            .unwrap_or_default()
+    }
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Self {
+        let directory = pattern.ends_with('/');
+        let trimmed = pattern.trim_end_matches('/');
+        // A slash anywhere but the end ties the pattern to the root.
+        let anchored = trimmed.contains('/');
+        let segments = trimmed
+            .trim_start_matches('/')
+            .split('/')
+            .map(String::from)
+            .collect();
+        Self {
+            segments,
+            anchored,
+            directory,
+        }
+    }
+
+    /// Whether the pattern matches the file, or one of the directories it is
+    /// in. Github makes an exception for a pattern ending in `/*`, which only
+    /// matches the files directly in that directory.
+    fn matches(&self, path: &[&str]) -> bool {
+        let in_directory =
+            self.segments.last().map(String::as_str) != Some("*");
+        let starts = if self.anchored { 0..1 } else { 0..path.len() };
+        for start in starts {
+            for end in start + 1..=path.len() {
+                let is_file = end == path.len();
+                if (is_file && self.directory) || (!is_file && !in_directory) {
+                    continue;
+                }
+                if match_segments(&self.segments, &path[start..end]) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+/// Matches the whole of `path` against `pattern`, where a `**` segment matches
+/// any number of segments.
+fn match_segments(pattern: &[String], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=path.len()).any(|i| match_segments(rest, &path[i..]))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((segment, path_rest)) => {
+                match_glob(first.as_bytes(), segment.as_bytes())
+                    && match_segments(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+/// Matches a single segment, where `*` matches any characters and `?` matches
+/// one.
+fn match_glob(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => {
+            (0..=text.len()).any(|i| match_glob(rest, &text[i..]))
+        }
+        Some((b'?', rest)) => !text.is_empty() && match_glob(rest, &text[1..]),
+        Some((b'\\', rest)) if !rest.is_empty() => {
+            text.first() == rest.first() && match_glob(&rest[1..], &text[1..])
+        }
+        Some((c, rest)) => {
+            text.first() == Some(c) && match_glob(rest, &text[1..])
+        }
+    }
+}
+
+/// Reads the `CODEOWNERS` file in `tree`, if there is one.
+pub fn find(repo: &Repository, tree: &Tree) -> Result<Option<CodeOwners>> {
+    for location in LOCATIONS {
+        let entry = match tree.get_path(std::path::Path::new(location)) {
+            Ok(e) => e,
+            Err(e) => {
+                debug!("No {location}: {e}");
+                continue;
+            }
+        };
+        let blob = entry.to_object(repo)?.peel_to_blob()?;
+        info!("Using the code owners in {location}.");
+        let text = String::from_utf8_lossy(blob.content());
+        return Ok(Some(CodeOwners::parse(&text)));
+    }
+    Ok(None)
+}
+
+/// The files `commit` changes, compared with its first parent. A file that is
+/// moved counts as changed in both places.
+pub fn changed_files(
+    repo: &Repository,
+    commit: &Commit,
+) -> Result<Vec<String>> {
+    let parent_tree = match commit.parents().next() {
+        Some(p) => Some(p.tree()?),
+        None => None,
+    };
+    let diff = repo.diff_tree_to_tree(
+        parent_tree.as_ref(),
+        Some(&commit.tree()?),
+        None,
+    )?;
+
+    let mut files = BTreeSet::new();
+    for delta in diff.deltas() {
+        for file in [delta.old_file(), delta.new_file()] {
+            if let Some(path) = file.path().and_then(|p| p.to_str()) {
+                files.insert(path.to_string());
+            }
+        }
+    }
+    Ok(files.into_iter().collect())
+}
+
+/// The reviewers to ask for the changes in `commit`, from the `CODEOWNERS`
+/// file on `base`. Owners are given as `@user` or `@org/team`, and are
+/// returned without the `@`. Owners given by email can't be asked for a
+/// review, so they are left out, as is `author`, who can't review their own
+/// pull request.
+pub fn reviewers(
+    repo: &Repository,
+    base: &Commit,
+    commit: &Commit,
+    author: Option<&str>,
+) -> Result<Vec<String>> {
+    let code_owners = match find(repo, &base.tree()?)? {
+        Some(c) => c,
+        None => {
+            info!("There is no CODEOWNERS file on the base branch.");
+            return Ok(Vec::new());
+        }
+    };
+
+    let mut reviewers = Vec::new();
+    for file in changed_files(repo, commit)? {
+        for owner in code_owners.owners(&file) {
+            let reviewer = match owner.strip_prefix('@') {
+                Some(r) => r.to_string(),
+                None => {
+                    debug!("Leaving out the code owner {owner}.");
+                    continue;
+                }
+            };
+            let is_author =
+                author.is_some_and(|a| a.eq_ignore_ascii_case(&reviewer));
+            if !is_author && !reviewers.contains(&reviewer) {
+                reviewers.push(reviewer);
+            }
+        }
+    }
+    Ok(reviewers)
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::{Oid, Signature};
+    use speculoos::prelude::*;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    const CODEOWNERS: &str = r#"# Everything, unless something below matches.
+*       @global-owner1 @global-owner2
+
+# JavaScript anywhere.
+*.js    @js-owner #This is an inline comment.
+
+**/logs @octocat
+/build/logs/ @doctocat
+docs/*  docs@example.com
+apps/   @octocat
+/scripts/ @doctocat @octocat
+/apps/github
+"#;
+
+    fn owners(path: &str) -> Vec<String> {
+        CodeOwners::parse(CODEOWNERS).owners(path).to_vec()
+    }
+
+    #[test]
+    fn last_match_wins() {
+        assert_that!(owners("README.md")).is_equal_to(vec![
+            "@global-owner1".to_string(),
+            "@global-owner2".to_string(),
+        ]);
+        assert_that!(owners("src/main.js"))
+            .is_equal_to(vec!["@js-owner".to_string()]);
+        assert_that!(owners("scripts/main.js"))
+            .is_equal_to(vec!["@doctocat".to_string(), "@octocat".to_string()]);
+    }
+
+    #[test]
+    fn anchored_directory() {
+        assert_that!(owners("build/logs/today.log"))
+            .is_equal_to(vec!["@doctocat".to_string()]);
+        assert_that!(owners("src/build/logs/today.txt"))
+            .is_equal_to(vec!["@octocat".to_string()]);
+        assert_that!(owners("build/other/today.txt"))
+            .is_equal_to(owners("README.md"));
+    }
+
+    #[test]
+    fn directory_anywhere() {
+        assert_that!(owners("apps/web/index.html"))
+            .is_equal_to(vec!["@octocat".to_string()]);
+        assert_that!(owners("src/apps/index.html"))
+            .is_equal_to(vec!["@octocat".to_string()]);
+        // A file named like the directory isn't matched.
+        assert_that!(owners("src/apps")).is_equal_to(owners("README.md"));
+    }
+
+    #[test]
+    fn direct_children_only() {
+        assert_that!(owners("docs/getting-started.md"))
+            .is_equal_to(vec!["docs@example.com".to_string()]);
+        assert_that!(owners("docs/build-app/troubleshooting.md"))
+            .is_equal_to(owners("README.md"));
+    }
+
+    #[test]
+    fn no_owners() {
+        assert_that!(owners("apps/github/index.html")).is_empty();
+    }
+
+    #[test]
+    fn globs() {
+        assert_that!(match_glob(b"*.js", b"main.js")).is_true();
+        assert_that!(match_glob(b"*.js", b"main.jsx")).is_false();
+        assert_that!(match_glob(b"ma?n.*", b"main.rs")).is_true();
+        assert_that!(match_glob(b"\\*", b"*")).is_true();
+        assert_that!(match_glob(b"\\*", b"a")).is_false();
+    }
+
+    fn commit(
+        repo: &Repository,
+        parent: Option<Oid>,
+        files: &[(&str, &str)],
+    ) -> Oid {
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let mut index = repo.index().unwrap();
+        for (path, content) in files {
+            let full = repo.workdir().unwrap().join(path);
+            std::fs::create_dir_all(full.parent().unwrap()).unwrap();
+            std::fs::write(full, content).unwrap();
+            index.add_path(std::path::Path::new(path)).unwrap();
+        }
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let parents: Vec<_> = parent
+            .map(|p| repo.find_commit(p).unwrap())
+            .into_iter()
+            .collect();
+        let parents: Vec<_> = parents.iter().collect();
+        repo.commit(None, &signature, &signature, "Commit", &tree, &parents)
+            .unwrap()
+    }
+
+    #[test]
+    fn reviewers_for_commit() {
+        let dir = tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let base = commit(
+            &repo,
+            None,
+            &[
+                (
+                    ".github/CODEOWNERS",
+                    "* @owner\n*.js @octocat @org/js\n*.md docs@example.com\n",
+                ),
+                ("README.md", "Text"),
+            ],
+        );
+        let change = commit(
+            &repo,
+            Some(base),
+            &[("src/main.js", "code"), ("README.md", "More text")],
+        );
+        let base = repo.find_commit(base).unwrap();
+        let change = repo.find_commit(change).unwrap();
+
+        assert_that!(changed_files(&repo, &change))
+            .is_ok()
+            .is_equal_to(vec![
+                "README.md".to_string(),
+                "src/main.js".to_string(),
+            ]);
+        assert_that!(reviewers(&repo, &base, &change, Some("OctoCat")))
+            .is_ok()
+            .is_equal_to(vec!["org/js".to_string()]);
+        assert_that!(reviewers(&repo, &base, &change, None))
+            .is_ok()
+            .is_equal_to(vec!["octocat".to_string(), "org/js".to_string()]);
+    }
+}
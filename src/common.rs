@@ -1,9 +1,19 @@
-use git2::{Commit, ObjectType, Repository};
-use tracing::info;
+use std::collections::{HashMap, HashSet};
 
+use git2::{
+    Branch, BranchType, Commit, ObjectType, Oid, Repository, Signature,
+};
+use tracing::{error, info};
+
+use crate::auth;
+use crate::configuration::Configuration;
+use crate::github::Client;
+use crate::patch_id;
+use crate::prompt;
+use crate::remote::get_github_repository;
 use crate::result::{Error, Result};
 
-pub fn get_selected_commit(repo: &Repository) -> Result<Commit> {
+pub fn get_selected_commit(repo: &Repository) -> Result<Commit<'_>> {
     let current_commit = repo
         .head()?
         .resolve()?
@@ -14,3 +24,492 @@ pub fn get_selected_commit(repo: &Repository) -> Result<Commit> {
     info!("Current commit = {}", current_commit.id());
     Ok(current_commit)
 }
+
+/// The name of `branch`, which git allows to be something other than UTF-8.
+pub fn branch_name(branch: &Branch) -> Result<String> {
+    match branch.name()? {
+        Some(n) => Ok(n.to_string()),
+        None => Err(Error::Generic),
+    }
+}
+
+/// The user's name and email from git configuration, for the objects this
+/// tool writes. Without them configured, the objects are still worth writing.
+pub fn signature(repo: &Repository) -> Result<Signature<'static>> {
+    Ok(repo
+        .signature()
+        .or_else(|_| Signature::now("git-ghpr", "git-ghpr"))?)
+}
+
+/// The remote the local branch `name` was pushed to, if it has been.
+pub fn upstream_remote(repo: &Repository, name: &str) -> Option<String> {
+    let remote = repo
+        .config()
+        .ok()?
+        .get_string(&format!("branch.{name}.remote"))
+        .ok()?;
+    // A branch tracking another local branch has `.` as its remote.
+    (remote != ".").then_some(remote)
+}
+
+/// The name of the branch on the remote that `branch` tracks, which is the
+/// name Github knows it by.
+pub fn get_remote_branch_name(
+    repo: &Repository,
+    branch: &Branch,
+) -> Result<String> {
+    let local_name = match branch.name()? {
+        Some(n) => n,
+        None => return Err(Error::Generic),
+    };
+    let merge = repo
+        .config()?
+        .get_string(&format!("branch.{local_name}.merge"))
+        .map_err(|_| Error::NoRemoteBranch(local_name.to_string()))?;
+    Ok(merge
+        .strip_prefix("refs/heads/")
+        .unwrap_or(&merge)
+        .to_string())
+}
+
+/// The local branches with an open pull request, among the ones where that
+/// helps choose between branches: pushed branches on the same commit as
+/// another pushed branch. There usually aren't any, and then Github isn't
+/// asked at all.
+pub async fn branches_with_open_pull_requests(
+    repo: &Repository,
+    options: &Configuration,
+) -> Result<HashSet<String>> {
+    let mut by_commit: HashMap<Oid, Vec<(String, String)>> = HashMap::new();
+    for entry in repo.branches(Some(BranchType::Local))? {
+        let (branch, _) = entry?;
+        let remote_name = match get_remote_branch_name(repo, &branch) {
+            Ok(n) => n,
+            Err(_) => continue,
+        };
+        by_commit
+            .entry(branch.get().peel_to_commit()?.id())
+            .or_default()
+            .push((branch_name(&branch)?, remote_name));
+    }
+    let shared: Vec<_> = by_commit
+        .into_values()
+        .filter(|branches| branches.len() > 1)
+        .flatten()
+        .collect();
+
+    let mut open = HashSet::new();
+    if shared.is_empty() {
+        return Ok(open);
+    }
+
+    let push_repository = get_github_repository(repo, &options.push_remote)?;
+    let pr_repository = get_github_repository(repo, &options.pr_remote)?;
+    let credential = auth::get_credential(options, &pr_repository.host).await?;
+    let client =
+        Client::new(&options.host(&pr_repository.host), &credential.token)?;
+    for (name, remote_name) in shared {
+        let head = format!("{}:{}", push_repository.owner, remote_name);
+        if client
+            .find_pull_request(&pr_repository, &head)
+            .await?
+            .is_some()
+        {
+            open.insert(name);
+        }
+    }
+    Ok(open)
+}
+
+/// Finds the local branch that points at `commit`. When there are several,
+/// the one most likely to be intended for the pull request is picked, and if
+/// that is still ambiguous, the user is asked to choose.
+pub fn get_branch_for_commit<'a>(
+    repo: &'a Repository,
+    commit: &Commit<'a>,
+    branch_name_template: &str,
+    branch_name_parameters: &HashMap<String, String>,
+    open_pull_requests: &HashSet<String>,
+) -> Result<Option<Branch<'a>>> {
+    let mut likely = likely_branches_for_commit(
+        repo,
+        commit,
+        branch_name_template,
+        branch_name_parameters,
+        open_pull_requests,
+    )?;
+    if likely.len() <= 1 {
+        return Ok(likely.pop());
+    }
+
+    let names = likely.iter().map(branch_name).collect::<Result<Vec<_>>>()?;
+    info!("Multiple branches for commit {}: {:?}", commit.id(), names);
+
+    if !prompt::is_interactive() {
+        return Err(Error::AmbiguousBranch {
+            commit: commit.id().to_string(),
+            branches: names,
+        });
+    }
+
+    let selected = prompt::choose(
+        &format!(
+            "Commit {} has multiple branches. Which one should be used?",
+            commit.id()
+        ),
+        &names,
+    )?;
+
+    Ok(Some(likely.swap_remove(selected)))
+}
+
+/// Finds the local branch that points at `commit` the same way as
+/// `get_branch_for_commit`, but without ever asking. A commit with several
+/// equally likely branches is treated as having none. This is for looking
+/// through the other branches in a stack, which shouldn't stop on a choice
+/// the user hasn't made about a branch they aren't working on.
+pub fn get_branch_for_commit_without_asking<'a>(
+    repo: &'a Repository,
+    commit: &Commit<'a>,
+    branch_name_template: &str,
+    branch_name_parameters: &HashMap<String, String>,
+    open_pull_requests: &HashSet<String>,
+) -> Result<Option<Branch<'a>>> {
+    let mut likely = likely_branches_for_commit(
+        repo,
+        commit,
+        branch_name_template,
+        branch_name_parameters,
+        open_pull_requests,
+    )?;
+    if likely.len() > 1 {
+        info!("Skipping commit {}, it has multiple branches.", commit.id());
+        return Ok(None);
+    }
+    Ok(likely.pop())
+}
+
+/// The local branches that point at `commit`, and among them the ones most
+/// likely to be intended for the pull request, in order of name.
+fn likely_branches_for_commit<'a>(
+    repo: &'a Repository,
+    commit: &Commit<'a>,
+    branch_name_template: &str,
+    branch_name_parameters: &HashMap<String, String>,
+    open_pull_requests: &HashSet<String>,
+) -> Result<Vec<Branch<'a>>> {
+    let branches = repo.branches(Some(BranchType::Local))?;
+
+    let mut candidates = Vec::new();
+    for branch in branches {
+        match branch {
+            Ok((branch, _branch_type)) => {
+                let branch_commit = branch.get().peel_to_commit()?;
+                if branch_commit.id() == commit.id() {
+                    candidates.push(branch);
+                }
+            }
+            Err(e) => error!("Couldn't list branch: {:?}", e),
+        };
+    }
+
+    if candidates.len() <= 1 {
+        return Ok(candidates);
+    }
+
+    // The name the branch would have been given if this tool had created it.
+    // Not being able to generate it, for example because of a missing template
+    // parameter, just means it can't be used as a hint.
+    let expected_name = commit.summary().and_then(|summary| {
+        generate_branch_name(
+            branch_name_template,
+            branch_name_parameters,
+            summary,
+        )
+        .ok()
+    });
+    let head_name = repo.head().ok().and_then(|h| h.name().map(String::from));
+
+    let mut ranked = Vec::new();
+    for branch in candidates {
+        let name = branch_name(&branch)?;
+        let preference = branch_preference(
+            &branch,
+            head_name.as_deref(),
+            open_pull_requests.contains(&name),
+            expected_name.as_deref(),
+        );
+        ranked.push((preference, name, branch));
+    }
+
+    let best = ranked.iter().map(|(p, _, _)| *p).max().unwrap_or_default();
+    ranked.retain(|(p, _, _)| *p == best);
+    // `repo.branches()` has no particular order, sorting keeps the listing
+    // stable between runs.
+    ranked.sort_by(|(_, a, _), (_, b, _)| a.cmp(b));
+    Ok(ranked.into_iter().map(|(_, _, branch)| branch).collect())
+}
+
+/// Ranks how likely it is that `branch` is the one intended for a pull
+/// request. A branch that is checked out is an explicit choice by the user,
+/// after that a branch with an open pull request is preferred, then one that
+/// has already been pushed, and then one that has the name this tool would
+/// have generated.
+fn branch_preference(
+    branch: &Branch,
+    head_name: Option<&str>,
+    open_pull_request: bool,
+    expected_name: Option<&str>,
+) -> u8 {
+    let mut preference = 0;
+    if head_name.is_some() && branch.get().name() == head_name {
+        preference += 8;
+    }
+    if open_pull_request {
+        preference += 4;
+    }
+    if branch.upstream().is_ok() {
+        preference += 2;
+    }
+    if expected_name.is_some() && branch.name().ok().flatten() == expected_name
+    {
+        preference += 1;
+    }
+    preference
+}
+
+/// The name for a new branch for a commit with `summary`, from the branch
+/// name template.
+pub fn generate_branch_name(
+    branch_name_template: &str,
+    branch_name_parameters: &HashMap<String, String>,
+    summary: &str,
+) -> Result<String, Error> {
+    // Process the summary to something that can be used as a branch name.
+    let summary = transform(summary);
+
+    use tera::{Context, Tera};
+    let mut context = Context::new();
+    context.insert("summary", &summary);
+    for (k, v) in branch_name_parameters {
+        context.insert(k, &v);
+    }
+    // "{{ summary ~ '-' }}"
+    let branch_name = match Tera::one_off(branch_name_template, &context, true)
+    {
+        Ok(b) => b,
+        Err(e) => {
+            return Err(match get_missing_variable(&e) {
+                Some(name) => Error::MissingBranchParameter(name),
+                None => Error::BranchTemplateMalformed(e.to_string()),
+            })
+        }
+    };
+
+    // if let Some(branch_prefix) = branch_prefix {
+    //     branch_name = format!(
+    //         "{prefix}-{suffix}",
+    //         prefix = branch_prefix,
+    //         suffix = branch_name
+    //     );
+    // }
+
+    Ok(branch_name)
+}
+
+/// Transform the input string into something that is a valid branch name.
+fn transform(summary: &str) -> String {
+    // Currently this filters out non-alphabetic characters. A decision made for
+    // rapid implementation that discriminates against non-English languages. A
+    // better filter would be one based on what characters git and Github don't
+    // allow in branch names.
+    summary
+        .to_lowercase()
+        .chars()
+        .filter(|c| {
+            ('a' <= *c && *c <= 'z')
+                || ('A' <= *c && *c <= 'Z')
+                || ('0' <= *c && *c <= '9')
+                || *c == '-'
+                || *c == ' '
+        })
+        .map(|c| if c.is_whitespace() { '-' } else { c })
+        // I didn't find any documented limits on Git branch names. I didn't
+        // look for documented limits on Github branch names. I did decide there
+        // is a practical limit for usability, however I don't know what it is,
+        // so this number is arbitrary.
+        .take(40)
+        .collect()
+}
+
+/// Given a Tera error message, convert it into the name of the missing
+/// variable. If there is a variable in the template that is missing, this is
+/// the only way I was able to find to detect the name of the variable.
+fn get_missing_variable<T>(e: &T) -> Option<String>
+where
+    T: std::error::Error,
+{
+    let e = e.source()?;
+
+    let e = e.downcast_ref::<tera::Error>()?;
+
+    let m = match &e.kind {
+        tera::ErrorKind::Msg(m) => m,
+        _ => return None,
+    };
+
+    if !m.starts_with("Variable `")
+        || !m.contains("` not found in context while rendering ")
+    {
+        return None;
+    }
+
+    let start = m.find('`').unwrap();
+    let end = m.rfind('`').unwrap();
+    let variable = m[start + 1..end].to_string();
+
+    Some(variable)
+}
+
+pub fn get_main_branch_commit<'a>(
+    repo: &'a Repository,
+) -> Result<(Commit<'a>, Branch<'a>)> {
+    // There is a `mainBranch` property in the branchless section of a
+    // configured git repo. I would prefer to take the main branch name from
+    // there, so behavior is consistent with branchless.
+    let branches = repo.branches(Some(BranchType::Local))?;
+
+    for branch in branches {
+        match branch {
+            Ok((branch, _branch_type)) => {
+                let name = branch.name()?;
+                if name == Some("main") || name == Some("master") {
+                    return Ok((
+                        branch.get().peel_to_commit().unwrap(),
+                        branch,
+                    ));
+                }
+            }
+            Err(e) => println!("Couldn't list branch: {:?}", e),
+        };
+    }
+
+    Err(Error::UnknownMainBranch)
+}
+
+/// A commit in a stack with changes that are already on the main branch, in
+/// another commit, usually because it was squash merged.
+#[derive(Debug, PartialEq)]
+pub struct AlreadyUpstream {
+    pub commit: Oid,
+    pub upstream: Oid,
+}
+
+/// The changes on the main branch since the stack of `commit` branched off
+/// it, for recognizing commits in the stack that have already been merged.
+/// Working them out means diffing each of those commits, so it is done once
+/// for each command.
+pub fn main_branch_changes<'a>(
+    repo: &'a Repository,
+    commit: &Commit,
+) -> Result<patch_id::Upstream<'a>> {
+    let (main_commit, _) = get_main_branch_commit(repo)?;
+    let merge_base = repo.merge_base(main_commit.id(), commit.id())?;
+    patch_id::Upstream::new(repo, main_commit.id(), merge_base)
+}
+
+/// Finds the branch the pull request for `current_commit` goes onto, which is
+/// the first branch below it, or the main branch. Commits that are already on
+/// the main branch, in `upstream`, aren't part of the stack anymore, so their
+/// branches are passed over, and the commits are returned as ones that can be
+/// dropped.
+pub fn find_base_branch_skipping_upstream<'a>(
+    repo: &'a Repository,
+    current_commit: &Commit<'a>,
+    upstream: &patch_id::Upstream,
+    branch_name_template: &str,
+    branch_name_parameters: &HashMap<String, String>,
+    open_pull_requests: &HashSet<String>,
+) -> Result<(Branch<'a>, Vec<AlreadyUpstream>)> {
+    walk_to_base_branch(repo, current_commit, upstream, |commit| {
+        get_branch_for_commit(
+            repo,
+            commit,
+            branch_name_template,
+            branch_name_parameters,
+            open_pull_requests,
+        )
+    })
+}
+
+/// Finds the base branch of `commit` the same way as
+/// `find_base_branch_skipping_upstream`, but without ever asking which branch
+/// a commit is for, for following the other branches in a stack.
+pub fn find_stack_base_branch<'a>(
+    repo: &'a Repository,
+    commit: &Commit<'a>,
+    upstream: &patch_id::Upstream,
+    branch_name_template: &str,
+    branch_name_parameters: &HashMap<String, String>,
+    open_pull_requests: &HashSet<String>,
+) -> Result<Branch<'a>> {
+    let (base, _) = walk_to_base_branch(repo, commit, upstream, |commit| {
+        get_branch_for_commit_without_asking(
+            repo,
+            commit,
+            branch_name_template,
+            branch_name_parameters,
+            open_pull_requests,
+        )
+    })?;
+    Ok(base)
+}
+
+/// Walks down from `current_commit` to the first commit that `branch_for`
+/// finds a branch for, or to the main branch.
+fn walk_to_base_branch<'a>(
+    repo: &'a Repository,
+    current_commit: &Commit<'a>,
+    upstream: &patch_id::Upstream,
+    mut branch_for: impl FnMut(&Commit<'a>) -> Result<Option<Branch<'a>>>,
+) -> Result<(Branch<'a>, Vec<AlreadyUpstream>)> {
+    let (main_commit, main_branch) = get_main_branch_commit(repo)?;
+
+    let merge_base = repo.merge_base(main_commit.id(), current_commit.id())?;
+    let mut dropped = Vec::new();
+
+    let mut commit = current_commit.clone();
+
+    while commit.id() != merge_base {
+        if commit.parents().len() == 0 {
+            return Err(Error::NoBaseBranch);
+        }
+
+        if commit.parents().len() > 1 {
+            // This error message would work better with the branch name when it is
+            // available. I didn't do it at the time because of time constraints.
+            return Err(Error::MultipleParentCommits(commit.id().to_string()));
+        }
+
+        let parent_commit = commit.parents().next().unwrap();
+
+        if parent_commit.id() != merge_base {
+            if let Some(id) = upstream.find(repo, &parent_commit)? {
+                info!("{} is already upstream as {id}.", parent_commit.id());
+                dropped.push(AlreadyUpstream {
+                    commit: parent_commit.id(),
+                    upstream: id,
+                });
+                commit = parent_commit;
+                continue;
+            }
+        }
+
+        match branch_for(&parent_commit)? {
+            Some(branch) => return Ok((branch, dropped)),
+            None => commit = parent_commit,
+        };
+    }
+
+    Ok((main_branch, dropped))
+}
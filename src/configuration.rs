@@ -16,19 +16,26 @@
 //! - ssh key file location.
 //! - Github client key
 //! - branch name template
+//! - pull request title and body templates
+//! - section of the repository's pull request template for the description
 //! - name of mainline branch
+//! - remote to push branches to, and remote to open pull requests against
+//! - API endpoints and token for each Github host
+//! - OAuth App used to log in to each Github host
+//! - how pull requests are merged when they are landed
 //!
 //! I think there is going to be 3, very similar structures.
 //! - command line parser, with nearly everything optional
 //! - file parser, with everything optional
 //! - exported structure, with all the mandatory pieces mandatory
 //!
-use clap::{ArgAction, Parser, Subcommand};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
 use directories::{BaseDirs, ProjectDirs};
 use figment::providers::{Env, Format, Toml};
 use figment::Figment;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::PathBuf;
 use tracing::error;
 
 use crate::result::Error;
@@ -64,18 +71,334 @@ pub enum CmdCommands {
     Create {
         #[arg(short, long)]
         jira: Option<String>,
+
+        #[arg(
+            long,
+            help = r#"The pull request template to use, when the repository has several in
+a PULL_REQUEST_TEMPLATE directory. The name of the file, with or without
+the extension."#
+        )]
+        template: Option<String>,
+
+        #[arg(
+            long,
+            help = r#"Edit the title and description of the pull request before it is
+created."#
+        )]
+        edit: bool,
+
+        #[arg(long, help = "Open the pull request as a draft.")]
+        draft: bool,
+
+        #[arg(
+            long,
+            value_delimiter = ',',
+            help = r#"Request a review from a user, or from a team as org/team. Can be
+given more than once."#
+        )]
+        reviewer: Vec<String>,
+
+        #[arg(
+            long,
+            value_delimiter = ',',
+            help = "Add a label. Can be given more than once."
+        )]
+        label: Vec<String>,
+
+        #[arg(
+            long,
+            value_delimiter = ',',
+            help = "Assign a user. Can be given more than once."
+        )]
+        assignee: Vec<String>,
+
+        #[arg(long, help = "The title or number of the milestone to set.")]
+        milestone: Option<String>,
+
+        #[arg(
+            long,
+            help = r#"Request reviews from the code owners of the changed files, from
+the CODEOWNERS file on the base branch."#
+        )]
+        code_owners: bool,
+
+        #[arg(
+            long,
+            help = r#"Have Github merge the pull request once it can be, by adding it to
+the merge queue, or turning on auto-merge."#
+        )]
+        auto_merge: bool,
+    },
+    /// Have Github merge the pull request of the selected commit once it can
+    /// be, by adding it to the merge queue, or turning on auto-merge.
+    Automerge {
+        #[arg(
+            long,
+            value_enum,
+            help = r#"How to merge the pull request, when there is no merge queue.
+Defaults to `merge_method` in the configuration file, or merge."#
+        )]
+        method: Option<MergeMethod>,
+    },
+    /// Delete the branches whose pull requests have been merged or closed, or
+    /// whose changes are already in the main branch.
+    Cleanup {
+        #[arg(long, help = "Show what would be deleted, without deleting it.")]
+        dry_run: bool,
+    },
+    /// Merge the bottom pull request of the stack, and move the rest of the
+    /// stack onto the main branch.
+    Land {
+        #[arg(
+            long,
+            value_enum,
+            help = r#"How to merge the pull request. Defaults to `merge_method` in the
+configuration file, or merge."#
+        )]
+        method: Option<MergeMethod>,
+
+        #[arg(
+            long,
+            help = r#"Run `git branchless sync` afterwards, to rebase the rest of the stack
+onto the main branch."#
+        )]
+        sync: bool,
+    },
+    /// Undo the last operation, such as one that failed partway through.
+    Undo {
+        #[arg(
+            long,
+            help = r#"Also undo the pushes, and close the pull requests opened, rather
+than only putting the local branches and HEAD back."#
+        )]
+        remote: bool,
+    },
+    /// List the commits pushed for a pull request, or compare two of them.
+    Revisions {
+        #[arg(help = "The number of the pull request.")]
+        pr: u64,
+
+        #[arg(
+            long,
+            num_args = 2,
+            value_names = ["FROM", "TO"],
+            help = "Show the changes between two revisions."
+        )]
+        diff: Option<Vec<u32>>,
+    },
+    /// Check out the stack of a pull request for review, with a local branch
+    /// for each pull request in it.
+    Checkout {
+        #[arg(
+            help = "The number or URL of the pull request at the top of the stack."
+        )]
+        pull_request: String,
+    },
+    /// Close the pull request of a commit that was dropped from the stack, and
+    /// move the pull requests stacked on it onto its base.
+    Close {
+        #[arg(
+            long,
+            conflicts_with = "branch",
+            help = "Close the pull request of this commit, rather than the selected one."
+        )]
+        commit: Option<String>,
+
+        #[arg(long, help = "Close the pull request of this branch.")]
+        branch: Option<String>,
+
+        #[arg(long, help = "Comment on the pull request before closing it.")]
+        comment: Option<String>,
+
+        #[arg(
+            long,
+            help = "Delete the local branch as well as the remote one."
+        )]
+        delete_local: bool,
+    },
+    /// Manage authentication with Github.
+    Auth {
+        #[command(subcommand)]
+        command: CmdAuthCommands,
+    },
+}
+
+#[derive(Subcommand, Serialize, Deserialize, Debug)]
+pub enum CmdAuthCommands {
+    /// Show where the token for a Github host comes from.
+    Status {
+        #[arg(
+            long,
+            help = r#"The Github host to check. Defaults to the host of the pull
+request remote, or github.com outside a repository."#
+        )]
+        hostname: Option<String>,
+    },
+    /// Log in to a Github host in the browser, and save the token.
+    Login {
+        #[arg(
+            long,
+            help = r#"The Github host to log in to. Defaults to the host of the
+pull request remote, or github.com outside a repository."#
+        )]
+        hostname: Option<String>,
     },
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 struct FileOptions {
     branch_name_template: Option<String>,
+    pr_title_template: Option<String>,
+    pr_title_template_file: Option<PathBuf>,
+    pr_body_template: Option<String>,
+    pr_body_template_file: Option<PathBuf>,
+    pr_template_section: Option<String>,
+    push_remote: Option<String>,
+    pr_remote: Option<String>,
+    token: Option<String>,
+    hosts: Option<HashMap<String, HostOptions>>,
+    default_draft: Option<bool>,
+    default_reviewers: Option<Vec<String>>,
+    default_labels: Option<Vec<String>>,
+    default_assignees: Option<Vec<String>>,
+    default_milestone: Option<String>,
+    default_code_owners: Option<bool>,
+    default_auto_merge: Option<bool>,
+    merge_method: Option<MergeMethod>,
+    push_revisions: Option<bool>,
+    sync_notes: Option<bool>,
+    commit_pr_link: Option<CommitPrLink>,
+}
+
+/// Settings for a single Github host, from a `[hosts."<host name>"]` table.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct HostOptions {
+    api_url: Option<String>,
+    graphql_url: Option<String>,
+    pub token: Option<String>,
+    /// Authenticate as an installation of this Github App.
+    pub app_id: Option<u64>,
+    pub installation_id: Option<u64>,
+    /// The App's private key, in PEM format.
+    pub private_key: Option<String>,
+    pub private_key_path: Option<PathBuf>,
+    /// Where the OAuth device flow endpoints are served for `auth login`.
+    oauth_url: Option<String>,
+    /// The client ID of the OAuth App used for `auth login`, instead of
+    /// git-ghpr's own on github.com.
+    client_id: Option<String>,
+}
+
+/// A template that is either given in the configuration, or read from a file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Template {
+    Inline(String),
+    /// A relative path is relative to the root of the repository, so templates
+    /// can be kept with the code.
+    File(PathBuf),
+}
+
+/// What is set on a new pull request, besides the title and body. Anything not
+/// given on the command line comes from the `default_` settings in the
+/// configuration file.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct PullRequestOptions {
+    pub draft: bool,
+    /// Users, and teams in the form `org/team`.
+    pub reviewers: Vec<String>,
+    pub labels: Vec<String>,
+    pub assignees: Vec<String>,
+    /// The title or number of an open milestone.
+    pub milestone: Option<String>,
+    /// Whether to also request reviews from the code owners of the changes.
+    pub code_owners: bool,
+    /// Whether to have Github merge the pull request once it can be.
+    pub auto_merge: bool,
+}
+
+/// How a commit links to its pull request in its message.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommitPrLink {
+    /// A `Pull-Request: <url>` trailer.
+    Trailer,
+    /// `(#<number>)` at the end of the summary.
+    Summary,
+}
+
+/// How a pull request is merged.
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeMethod {
+    /// A merge commit.
+    #[default]
+    Merge,
+    /// The commits squashed into one.
+    Squash,
+    /// The commits rebased onto the base branch.
+    Rebase,
+}
+
+/// The client ID of git-ghpr's own OAuth App on github.com, so `auth login`
+/// works without registering one. An OAuth App belongs to a single Github
+/// instance, so Enterprise hosts need `client_id` set. Client IDs aren't
+/// secret, the device flow uses no client secret.
+const GITHUB_CLIENT_ID: &str = "REPLACE_WITH_REGISTERED_CLIENT_ID";
+
+/// The settings for a Github host, with defaults filled in.
+#[derive(Debug)]
+pub struct Host {
+    pub name: String,
+    pub api_url: String,
+    pub graphql_url: String,
+    pub oauth_url: String,
+    pub client_id: Option<String>,
 }
 
 #[derive(Debug)]
 pub struct Configuration {
     pub branch_name_template: String,
 
+    pub pr_title_template: Template,
+
+    pub pr_body_template: Template,
+
+    /// The heading in the repository's pull request template that the body is
+    /// put under.
+    pub pr_template_section: String,
+
+    /// The remote branches are pushed to. For a fork based workflow this is
+    /// the fork.
+    pub push_remote: String,
+
+    /// The remote that pull requests are opened against. For a fork based
+    /// workflow this is the upstream repository.
+    pub pr_remote: String,
+
+    /// The token used for github.com, when it has none of its own. Other hosts
+    /// only use a token configured for them.
+    pub token: Option<String>,
+
+    pub hosts: HashMap<String, HostOptions>,
+
+    /// How pull requests are merged by `land` and auto-merge, unless
+    /// `--method` is given.
+    pub merge_method: MergeMethod,
+
+    /// Whether the revisions recorded for pull requests are pushed to the
+    /// push remote, as well as kept locally.
+    pub push_revisions: bool,
+
+    /// Whether the notes saying which pull request each commit is for are
+    /// fetched from and pushed to the push remote.
+    pub sync_notes: bool,
+
+    /// Whether, and how, a commit is reworded to link to its pull request once
+    /// the pull request is created.
+    pub commit_pr_link: Option<CommitPrLink>,
+
     pub verbose: u8,
 
     pub command: Commands,
@@ -85,23 +408,170 @@ pub struct Configuration {
 pub enum Commands {
     Create {
         branch_name_parameters: HashMap<String, String>,
+        template: Option<String>,
+        edit: bool,
+        pull_request: PullRequestOptions,
+    },
+    Automerge {
+        method: Option<MergeMethod>,
+    },
+    Cleanup {
+        dry_run: bool,
+    },
+    Land {
+        method: Option<MergeMethod>,
+        sync: bool,
+    },
+    Undo {
+        remote: bool,
+    },
+    Revisions {
+        pr: u64,
+        diff: Option<Vec<u32>>,
+    },
+    Checkout {
+        pull_request: String,
+    },
+    Close {
+        commit: Option<String>,
+        branch: Option<String>,
+        comment: Option<String>,
+        delete_local: bool,
+    },
+    AuthStatus {
+        hostname: Option<String>,
+    },
+    AuthLogin {
+        hostname: Option<String>,
     },
 }
 
 impl From<CmdCommands> for Commands {
     fn from(c: CmdCommands) -> Self {
         match c {
-            CmdCommands::Create { jira } => match jira {
-                Some(v) => Self::Create {
-                    branch_name_parameters: HashMap::from([(
-                        "jira".to_string(),
-                        v,
-                    )]),
-                },
-                None => Self::Create {
-                    branch_name_parameters: HashMap::new(),
+            CmdCommands::Create {
+                jira,
+                template,
+                edit,
+                draft,
+                reviewer,
+                label,
+                assignee,
+                milestone,
+                code_owners,
+                auto_merge,
+            } => Self::Create {
+                branch_name_parameters: jira
+                    .map(|v| HashMap::from([("jira".to_string(), v)]))
+                    .unwrap_or_default(),
+                template,
+                edit,
+                pull_request: PullRequestOptions {
+                    draft,
+                    reviewers: reviewer,
+                    labels: label,
+                    assignees: assignee,
+                    milestone,
+                    code_owners,
+                    auto_merge,
                 },
             },
+            CmdCommands::Automerge { method } => Self::Automerge { method },
+            CmdCommands::Cleanup { dry_run } => Self::Cleanup { dry_run },
+            CmdCommands::Land { method, sync } => Self::Land { method, sync },
+            CmdCommands::Undo { remote } => Self::Undo { remote },
+            CmdCommands::Revisions { pr, diff } => Self::Revisions { pr, diff },
+            CmdCommands::Checkout { pull_request } => {
+                Self::Checkout { pull_request }
+            }
+            CmdCommands::Close {
+                commit,
+                branch,
+                comment,
+                delete_local,
+            } => Self::Close {
+                commit,
+                branch,
+                comment,
+                delete_local,
+            },
+            CmdCommands::Auth { command } => match command {
+                CmdAuthCommands::Status { hostname } => {
+                    Self::AuthStatus { hostname }
+                }
+                CmdAuthCommands::Login { hostname } => {
+                    Self::AuthLogin { hostname }
+                }
+            },
+        }
+    }
+}
+
+impl Configuration {
+    /// The defaults for `create`, for tests to change what they need from.
+    #[cfg(test)]
+    pub fn for_test() -> Self {
+        Self {
+            branch_name_template: "{{summary}}".to_string(),
+            pr_title_template: Template::Inline("{{ summary }}".to_string()),
+            pr_body_template: Template::Inline("{{ body }}".to_string()),
+            pr_template_section: "Description".to_string(),
+            push_remote: "origin".to_string(),
+            pr_remote: "origin".to_string(),
+            token: None,
+            hosts: HashMap::new(),
+            merge_method: MergeMethod::Merge,
+            push_revisions: false,
+            sync_notes: false,
+            commit_pr_link: None,
+            verbose: 0,
+            command: Commands::Create {
+                branch_name_parameters: HashMap::new(),
+                template: None,
+                edit: false,
+                pull_request: PullRequestOptions::default(),
+            },
+        }
+    }
+
+    /// The settings for the Github host `name`. Github itself serves its API
+    /// from a separate host, while Github Enterprise Server serves it under
+    /// `/api` on the same host, with different paths for REST and GraphQL.
+    pub fn host(&self, name: &str) -> Host {
+        let options = self.hosts.get(name).cloned().unwrap_or_default();
+
+        let api_url = match options.api_url {
+            Some(u) => u.trim_end_matches('/').to_string(),
+            None if name == "github.com" => {
+                "https://api.github.com".to_string()
+            }
+            None => format!("https://{name}/api/v3"),
+        };
+
+        let graphql_url = match options.graphql_url {
+            Some(u) => u,
+            None => match api_url.strip_suffix("/api/v3") {
+                Some(root) => format!("{root}/api/graphql"),
+                None => format!("{api_url}/graphql"),
+            },
+        };
+
+        let oauth_url = match options.oauth_url {
+            Some(u) => u.trim_end_matches('/').to_string(),
+            None => format!("https://{name}"),
+        };
+
+        let client_id = match options.client_id {
+            Some(id) => Some(id),
+            None if name == "github.com" => Some(GITHUB_CLIENT_ID.to_string()),
+            None => None,
+        };
+        Host {
+            name: name.to_string(),
+            api_url,
+            graphql_url,
+            oauth_url,
+            client_id,
         }
     }
 }
@@ -124,10 +594,90 @@ fn first_of(
     Err(Error::BadParameter(name.to_string()))
 }
 
+/// Picks between a template given inline and one in a file. Giving both is
+/// most likely a mistake, so it is reported rather than guessing which one was
+/// meant.
+fn template(
+    inline: Option<String>,
+    file: Option<PathBuf>,
+    default: &str,
+    name: &str,
+) -> Result<Template> {
+    match (inline, file) {
+        (Some(_), Some(_)) => Err(Error::BadParameter(format!(
+            "Only one of `{name}` and `{name}_file` can be set."
+        ))),
+        (Some(t), None) => Ok(Template::Inline(t)),
+        (None, Some(p)) => Ok(Template::File(p)),
+        (None, None) => Ok(Template::Inline(default.to_string())),
+    }
+}
+
+/// Fills in what wasn't given on the command line from the configuration file.
+/// A list given on the command line replaces the default one, rather than
+/// adding to it, so the defaults can be left out for a single pull request.
+fn pull_request_defaults(
+    command: Commands,
+    file_options: &FileOptions,
+) -> Commands {
+    match command {
+        Commands::Create {
+            branch_name_parameters,
+            template,
+            edit,
+            pull_request,
+        } => {
+            let or_default = |given: Vec<String>, default: &Option<Vec<_>>| {
+                if given.is_empty() {
+                    default.clone().unwrap_or_default()
+                } else {
+                    given
+                }
+            };
+            Commands::Create {
+                branch_name_parameters,
+                template,
+                edit,
+                pull_request: PullRequestOptions {
+                    draft: pull_request.draft
+                        || file_options.default_draft.unwrap_or(false),
+                    reviewers: or_default(
+                        pull_request.reviewers,
+                        &file_options.default_reviewers,
+                    ),
+                    labels: or_default(
+                        pull_request.labels,
+                        &file_options.default_labels,
+                    ),
+                    assignees: or_default(
+                        pull_request.assignees,
+                        &file_options.default_assignees,
+                    ),
+                    milestone: pull_request
+                        .milestone
+                        .or_else(|| file_options.default_milestone.clone()),
+                    code_owners: pull_request.code_owners
+                        || file_options.default_code_owners.unwrap_or(false),
+                    auto_merge: pull_request.auto_merge
+                        || file_options.default_auto_merge.unwrap_or(false),
+                },
+            }
+        }
+        command => command,
+    }
+}
+
 fn merge(
     file_options: FileOptions,
     cmd_options: CmdOptions,
 ) -> Result<Configuration> {
+    let command = pull_request_defaults(
+        Commands::from(cmd_options.command),
+        &file_options,
+    );
+    let push_remote = file_options
+        .push_remote
+        .unwrap_or_else(|| "origin".to_string());
     Ok(Configuration {
         branch_name_template: first_of(
             cmd_options.branch_name_template,
@@ -135,32 +685,60 @@ fn merge(
             Some("{{summary}}".to_string()),
             "branch_name_template",
         )?,
+        pr_title_template: template(
+            file_options.pr_title_template,
+            file_options.pr_title_template_file,
+            "{{ summary }}",
+            "pr_title_template",
+        )?,
+        pr_body_template: template(
+            file_options.pr_body_template,
+            file_options.pr_body_template_file,
+            "{{ body }}",
+            "pr_body_template",
+        )?,
+        pr_template_section: file_options
+            .pr_template_section
+            .unwrap_or_else(|| "Description".to_string()),
+        pr_remote: file_options
+            .pr_remote
+            .unwrap_or_else(|| push_remote.clone()),
+        push_remote,
+        token: file_options.token,
+        hosts: file_options.hosts.unwrap_or_default(),
+        merge_method: file_options.merge_method.unwrap_or_default(),
+        push_revisions: file_options.push_revisions.unwrap_or_default(),
+        sync_notes: file_options.sync_notes.unwrap_or_default(),
+        commit_pr_link: file_options.commit_pr_link,
         verbose: cmd_options.verbose,
-        command: Commands::from(cmd_options.command),
+        command,
     })
 }
 
 const CONFIG_FILE: &str = "gh-pull-request.toml";
 
-pub fn load() -> Result<Configuration> {
-    let mut f = Figment::new();
-
+/// The directory in the XDG configuration directory, or the equivalent for the
+/// OS, that holds the configuration file.
+pub fn config_dir() -> Option<PathBuf> {
     if let Some(pd) =
         ProjectDirs::from("org", "git tools", "github-pull-request")
     {
-        let mut p = pd.config_dir().to_path_buf();
+        return Some(pd.config_dir().to_path_buf());
+    }
+    error!("Could not get the project directories for this OS.");
+    if let Some(bd) = BaseDirs::new() {
+        return Some(bd.config_dir().join("github-pull-request"));
+    }
+    error!("Could not get the configuration directory for this OS.");
+    None
+}
+
+pub fn load() -> Result<Configuration> {
+    let mut f = Figment::new();
+
+    if let Some(mut p) = config_dir() {
         p.push(CONFIG_FILE);
         f = f.merge(Toml::file(p));
-    } else {
-        error!("Could not get the project directories for this OS.");
-        if let Some(bd) = BaseDirs::new() {
-            let mut p = bd.config_dir().to_path_buf();
-            p.push("github-pull-request");
-            p.push(CONFIG_FILE);
-            f = f.merge(Toml::file(p));
-        } else {
-            error!("Could not get the configuration directory for this OS.");
-        }
     }
 
     if let Some(bd) = BaseDirs::new() {
@@ -177,3 +755,190 @@ pub fn load() -> Result<Configuration> {
 
     merge(file_options, cmd_options)
 }
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use super::*;
+
+    fn configuration(hosts: &[(&str, HostOptions)]) -> Configuration {
+        Configuration {
+            token: Some("default-token".to_string()),
+            hosts: hosts
+                .iter()
+                .map(|(name, options)| (name.to_string(), options.clone()))
+                .collect(),
+            ..Configuration::for_test()
+        }
+    }
+
+    #[test]
+    fn github_defaults() {
+        let host = configuration(&[]).host("github.com");
+
+        assert_that!(host.api_url)
+            .is_equal_to("https://api.github.com".to_string());
+        assert_that!(host.graphql_url)
+            .is_equal_to("https://api.github.com/graphql".to_string());
+    }
+
+    #[test]
+    fn enterprise_defaults() {
+        let host = configuration(&[]).host("github.example.com");
+
+        assert_that!(host.api_url)
+            .is_equal_to("https://github.example.com/api/v3".to_string());
+        assert_that!(host.graphql_url)
+            .is_equal_to("https://github.example.com/api/graphql".to_string());
+    }
+
+    #[test]
+    fn enterprise_defaults_with_port() {
+        let host = configuration(&[]).host("github.example.com:8443");
+
+        assert_that!(host.api_url)
+            .is_equal_to("https://github.example.com:8443/api/v3".to_string());
+        assert_that!(host.oauth_url)
+            .is_equal_to("https://github.example.com:8443".to_string());
+    }
+
+    #[test]
+    fn configured_api_url() {
+        let host = configuration(&[(
+            "github.example.com",
+            HostOptions {
+                api_url: Some("https://ghe.internal:8443/api/v3/".to_string()),
+                ..Default::default()
+            },
+        )])
+        .host("github.example.com");
+
+        assert_that!(host.api_url)
+            .is_equal_to("https://ghe.internal:8443/api/v3".to_string());
+        assert_that!(host.graphql_url)
+            .is_equal_to("https://ghe.internal:8443/api/graphql".to_string());
+    }
+
+    #[test]
+    fn template_sources() {
+        assert_that!(template(None, None, "{{ body }}", "pr_body_template"))
+            .is_ok()
+            .is_equal_to(Template::Inline("{{ body }}".to_string()));
+        assert_that!(template(
+            Some("{{ summary }}!".to_string()),
+            None,
+            "{{ summary }}",
+            "pr_title_template"
+        ))
+        .is_ok()
+        .is_equal_to(Template::Inline("{{ summary }}!".to_string()));
+        assert_that!(template(
+            None,
+            Some(PathBuf::from(".github/pr_body.md")),
+            "{{ body }}",
+            "pr_body_template"
+        ))
+        .is_ok()
+        .is_equal_to(Template::File(PathBuf::from(".github/pr_body.md")));
+        assert_that!(template(
+            Some("{{ body }}".to_string()),
+            Some(PathBuf::from(".github/pr_body.md")),
+            "{{ body }}",
+            "pr_body_template"
+        ))
+        .is_err();
+    }
+
+    #[test]
+    fn oauth_defaults() {
+        let configuration = configuration(&[]);
+        assert_that!(configuration.host("github.com").oauth_url)
+            .is_equal_to("https://github.com".to_string());
+        assert_that!(configuration.host("github.example.com").oauth_url)
+            .is_equal_to("https://github.example.com".to_string());
+    }
+
+    #[test]
+    fn client_id_defaults() {
+        let configuration = configuration(&[]);
+        assert_that!(configuration.host("github.com").client_id)
+            .is_equal_to(Some(GITHUB_CLIENT_ID.to_string()));
+        assert_that!(configuration.host("github.example.com").client_id)
+            .is_none();
+    }
+
+    #[test]
+    fn configured_client_id() {
+        let host = configuration(&[(
+            "github.com",
+            HostOptions {
+                client_id: Some("own-app".to_string()),
+                ..Default::default()
+            },
+        )])
+        .host("github.com");
+        assert_that!(host.client_id).is_equal_to(Some("own-app".to_string()));
+    }
+
+    #[test]
+    fn configured_graphql_url() {
+        let host = configuration(&[(
+            "github.example.com",
+            HostOptions {
+                api_url: Some("http://localhost:8080".to_string()),
+                graphql_url: Some("http://localhost:8081/query".to_string()),
+                ..Default::default()
+            },
+        )])
+        .host("github.example.com");
+
+        assert_that!(host.api_url)
+            .is_equal_to("http://localhost:8080".to_string());
+        assert_that!(host.graphql_url)
+            .is_equal_to("http://localhost:8081/query".to_string());
+    }
+
+    fn create(pull_request: PullRequestOptions) -> Commands {
+        Commands::Create {
+            branch_name_parameters: HashMap::new(),
+            template: None,
+            edit: false,
+            pull_request,
+        }
+    }
+
+    #[test]
+    fn pull_request_options_from_defaults() {
+        let file_options = FileOptions {
+            default_draft: Some(true),
+            default_reviewers: Some(vec!["octocat".to_string()]),
+            default_labels: Some(vec!["bug".to_string()]),
+            default_milestone: Some("v1".to_string()),
+            ..FileOptions::default()
+        };
+
+        let command = pull_request_defaults(
+            create(PullRequestOptions {
+                labels: vec!["feature".to_string()],
+                assignees: vec!["hubot".to_string()],
+                ..PullRequestOptions::default()
+            }),
+            &file_options,
+        );
+
+        match command {
+            Commands::Create { pull_request, .. } => assert_that!(pull_request)
+                .is_equal_to(PullRequestOptions {
+                    draft: true,
+                    reviewers: vec!["octocat".to_string()],
+                    labels: vec!["feature".to_string()],
+                    assignees: vec!["hubot".to_string()],
+                    milestone: Some("v1".to_string()),
+                    code_owners: false,
+                    auto_merge: false,
+                }),
+            c => panic!("Expected a create command, not {c:?}"),
+        }
+    }
+}
@@ -2,13 +2,36 @@ use git2::Branch;
 use git2::BranchType;
 use git2::Commit;
 use git2::Repository;
-use std::collections::HashMap;
-use tracing::{error, info};
-
-use crate::common::get_selected_commit;
+use std::collections::{HashMap, HashSet};
+use tracing::info;
+
+use crate::auth;
+use crate::auto_merge;
+use crate::code_owners;
+use crate::common::{
+    branches_with_open_pull_requests, find_base_branch_skipping_upstream,
+    generate_branch_name, get_branch_for_commit, get_remote_branch_name,
+    get_selected_commit, main_branch_changes, AlreadyUpstream,
+};
+use crate::configuration::{Configuration, PullRequestOptions};
+use crate::editor;
+use crate::github::{Client, NewPullRequest, PullRequest};
+use crate::journal::Journal;
+use crate::notes;
+use crate::pr_options;
+use crate::pr_template::{
+    self, PullRequestContext, PullRequestLink, PullRequestText, Stack,
+};
+use crate::range_diff;
+use crate::remote::{get_github_repository, GithubRepository};
+use crate::repository_template;
 use crate::result::Error;
 use crate::result::Message;
 use crate::result::Result;
+use crate::revisions;
+use crate::rewrite;
+use crate::stack;
+use crate::undo;
 
 /// Creates a pull request for the current commit. This is a safe operation, it
 /// will do it's best to detect the current state of the repository and Github,
@@ -21,41 +44,430 @@ use crate::result::Result;
 /// - Check the base branch is main or there is a base branch PR.
 /// * Find the branch for the current commit.
 /// * Create a branch if one does not exist.
-/// - Push the branch upstream if necessary, possibly force push.
-/// - Check if there is a PR for this branch.
-/// - Create a PR for this branch.
+/// * Check if there is a PR for this branch. If there is, push the branch, and
+///   comment on the PR with a range-diff from the commits it had before.
+/// * Record the pushed commit as the next revision of the PR, and note the PR
+///   on the commit.
+/// * Render the PR title and body, using the repository's pull request
+///   template, and let the user edit them.
+/// * Push the branch upstream if necessary, possibly force push.
+/// * Create a PR for this branch, link the commit to it in its message if
+///   configured to, and set its reviewers, labels, assignees and milestone.
+/// * If any of this fails, or is interrupted with Ctrl-C, put the local
+///   branches and HEAD back the way they were.
 pub async fn create_pull_request(
-    branch_name_template: &str,
+    options: &Configuration,
     branch_name_parameters: &HashMap<String, String>,
+    template_name: Option<&str>,
+    edit: bool,
+    pr_options: &PullRequestOptions,
 ) -> Result<Message> {
     info!("Opening the local git repository.");
     let repo = Repository::discover(".")?;
 
     check_has_remote(&repo)?;
 
-    let current_commit = get_selected_commit(&repo)?;
+    let journal = Journal::begin(&repo, "create")?;
+    let created = create(
+        &repo,
+        &journal,
+        options,
+        branch_name_parameters,
+        template_name,
+        edit,
+        pr_options,
+    );
+    undo::rolling_back(&repo, &journal, created, tokio::signal::ctrl_c()).await
+}
+
+async fn create(
+    repo: &Repository,
+    journal: &Journal,
+    options: &Configuration,
+    branch_name_parameters: &HashMap<String, String>,
+    template_name: Option<&str>,
+    edit: bool,
+    pr_options: &PullRequestOptions,
+) -> Result<Message> {
+    let current_commit = get_selected_commit(repo)?;
 
-    let base_branch = find_base_branch(&repo, &current_commit)?;
+    let open_pull_requests =
+        branches_with_open_pull_requests(repo, options).await?;
+    let upstream = main_branch_changes(repo, &current_commit)?;
+    let (base_branch, dropped) = find_base_branch_skipping_upstream(
+        repo,
+        &current_commit,
+        &upstream,
+        &options.branch_name_template,
+        branch_name_parameters,
+        &open_pull_requests,
+    )?;
+    let mut warnings = dropped_warnings(repo, &dropped)?;
 
     check_branch_has_remote(&base_branch)?;
 
     let current_branch = get_or_create_branch(
-        &repo,
+        repo,
+        journal,
         &current_commit,
-        branch_name_template,
+        &options.branch_name_template,
         branch_name_parameters,
+        &open_pull_requests,
     )?;
 
-    if let Err(e) = current_branch.upstream() {
-        if e.code() != git2::ErrorCode::NotFound {
-            return Err(Error::Generic);
+    // In a fork based workflow the branch goes to the fork, and the pull
+    // request is opened against the upstream repository.
+    let push_repository = get_github_repository(repo, &options.push_remote)?;
+    let pr_repository = get_github_repository(repo, &options.pr_remote)?;
+
+    let credential = auth::get_credential(options, &pr_repository.host).await?;
+    let client =
+        Client::new(&options.host(&pr_repository.host), &credential.token)?;
+
+    let branch_name = match current_branch.name()? {
+        Some(n) => n.to_string(),
+        None => return Err(Error::Generic),
+    };
+    let base_name = get_remote_branch_name(repo, &base_branch)?;
+
+    let ancestors = stack::ancestors(
+        repo,
+        &branch_name,
+        &upstream,
+        options,
+        branch_name_parameters,
+        &open_pull_requests,
+    )?;
+    let descendants = stack::descendants(
+        repo,
+        &branch_name,
+        &upstream,
+        options,
+        branch_name_parameters,
+        &open_pull_requests,
+    )?;
+    let below = find_stack_pull_requests(
+        repo,
+        &client,
+        &pr_repository,
+        &push_repository.owner,
+        &ancestors,
+    )
+    .await?;
+
+    let head = format!("{}:{}", push_repository.owner, branch_name);
+    if let Some(pr) = client.find_pull_request(&pr_repository, &head).await? {
+        let base_commit = base_branch.get().peel_to_commit()?;
+        let comment = range_diff::comment(
+            repo,
+            &pr.head.sha,
+            current_commit.id(),
+            base_commit.id(),
+        );
+        journal.push_branch(repo, &options.push_remote, &branch_name)?;
+        warnings.extend(revisions::record_pushed(
+            repo,
+            journal,
+            options,
+            pr.number,
+            current_commit.id(),
+        )?);
+        warnings.extend(notes::record_pushed(
+            repo,
+            journal,
+            options,
+            current_commit.id(),
+            &notes::Note {
+                number: pr.number,
+                url: pr.html_url.clone(),
+                branch: branch_name.clone(),
+            },
+        )?);
+        match comment {
+            Ok(Some(comment)) => {
+                if let Err(e) = client
+                    .add_comment(&pr_repository, pr.number, &comment)
+                    .await
+                {
+                    warnings.push(format!("Could not add the range-diff: {e}"));
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warnings.push(format!("Could not work out the range-diff: {e}"))
+            }
+        }
+        if pr_options.auto_merge {
+            auto_merge::enable(
+                &client,
+                &pr_repository,
+                &pr,
+                &base_name,
+                options.merge_method,
+            )
+            .await?;
         }
+        let url = pr.html_url.clone();
+        update_stack(
+            repo,
+            &client,
+            &pr_repository,
+            &push_repository.owner,
+            below,
+            pr,
+            &descendants,
+        )
+        .await?;
+        return Ok(Message::PullRequestUpdated { url, warnings });
     }
 
-    Ok(Message::Empty)
+    let stack =
+        get_stack(&client, &pr_repository, &ancestors, &below, &branch_name)
+            .await?;
+    let root = repo.workdir().unwrap_or_else(|| repo.path());
+    let text = pr_template::render(
+        &options.pr_title_template,
+        &options.pr_body_template,
+        root,
+        &PullRequestContext {
+            commit: &current_commit,
+            branch: &branch_name,
+            base: &base_name,
+            parameters: branch_name_parameters,
+            stack: &stack,
+        },
+    )?;
+    let text = PullRequestText {
+        body: match repository_template::read(root, template_name)? {
+            Some(template) => repository_template::merge(
+                &template,
+                &text.body,
+                &options.pr_template_section,
+            ),
+            None => text.body,
+        },
+        ..text
+    };
+    // A message edited for an earlier attempt that failed takes the place of
+    // the generated one.
+    let text = editor::saved(repo, &branch_name).unwrap_or(text);
+    let text = if edit {
+        editor::edit(repo, &branch_name, &text)?
+    } else {
+        text
+    };
+
+    let pr_options = with_code_owners(
+        repo,
+        &client,
+        &base_branch,
+        &current_commit,
+        pr_options,
+    )
+    .await?;
+
+    journal.push_branch(repo, &options.push_remote, &branch_name)?;
+
+    let mut pr = client
+        .create_pull_request(
+            &pr_repository,
+            &NewPullRequest {
+                title: &text.title,
+                body: &text.body,
+                head: &head,
+                base: &base_name,
+                draft: pr_options.draft,
+            },
+        )
+        .await?;
+    journal.pull_request(&pr_repository, &pr)?;
+    editor::discard(repo)?;
+    let pushed = match options.commit_pr_link {
+        Some(link) => rewrite::link_pull_request(
+            repo,
+            journal,
+            options,
+            current_commit.id(),
+            &pr,
+            link,
+        )?,
+        None => current_commit.id(),
+    };
+    warnings.extend(revisions::record_pushed(
+        repo, journal, options, pr.number, pushed,
+    )?);
+    warnings.extend(notes::record_pushed(
+        repo,
+        journal,
+        options,
+        pushed,
+        &notes::Note {
+            number: pr.number,
+            url: pr.html_url.clone(),
+            branch: branch_name.clone(),
+        },
+    )?);
+    warnings.extend(
+        pr_options::apply(&client, &pr_repository, pr.number, &pr_options)
+            .await,
+    );
+    if pr_options.auto_merge {
+        // Github refuses auto-merge for a pull request that can be merged
+        // straight away, which is no reason to fail now it has been created.
+        if let Err(e) = auto_merge::enable(
+            &client,
+            &pr_repository,
+            &pr,
+            &base_name,
+            options.merge_method,
+        )
+        .await
+        {
+            warnings.push(format!("Could not turn on auto-merge: {e}"));
+        }
+    }
+
+    // Github leaves out an empty body, which would otherwise look like the body
+    // needs the stack table added on its own.
+    pr.body.get_or_insert(text.body);
+    let url = pr.html_url.clone();
+    update_stack(
+        repo,
+        &client,
+        &pr_repository,
+        &push_repository.owner,
+        below,
+        pr,
+        &descendants,
+    )
+    .await?;
+
+    Ok(Message::PullRequestCreated { url, warnings })
 }
 
-fn check_branch_has_remote<'a>(branch: &Branch<'a>) -> Result<()> {
+/// Adds the code owners of the changes in `commit` to the reviewers, when they
+/// were asked for.
+async fn with_code_owners(
+    repo: &Repository,
+    client: &Client,
+    base_branch: &Branch<'_>,
+    commit: &Commit<'_>,
+    pr_options: &PullRequestOptions,
+) -> Result<PullRequestOptions> {
+    let mut pr_options = pr_options.clone();
+    if !pr_options.code_owners {
+        return Ok(pr_options);
+    }
+
+    // A Github App token doesn't belong to a user, so there is nobody to leave
+    // out.
+    let author = match client.current_user().await {
+        Ok(user) => Some(user.login),
+        Err(e) => {
+            info!("Could not find who is opening the pull request: {e}");
+            None
+        }
+    };
+    let base_commit = base_branch.get().peel_to_commit()?;
+    for reviewer in
+        code_owners::reviewers(repo, &base_commit, commit, author.as_deref())?
+    {
+        if !pr_options.reviewers.contains(&reviewer) {
+            pr_options.reviewers.push(reviewer);
+        }
+    }
+    Ok(pr_options)
+}
+
+/// Finds the latest pull request for each of the local `branches`. Branches
+/// that haven't been pushed, or have no pull request, are left out.
+async fn find_stack_pull_requests(
+    repo: &Repository,
+    client: &Client,
+    pr_repository: &GithubRepository,
+    push_owner: &str,
+    branches: &[String],
+) -> Result<Vec<(String, PullRequest)>> {
+    let mut pull_requests = Vec::new();
+    for name in branches {
+        let branch = repo.find_branch(name, BranchType::Local)?;
+        let remote_name = match get_remote_branch_name(repo, &branch) {
+            Ok(n) => n,
+            Err(_) => continue,
+        };
+        let head = format!("{push_owner}:{remote_name}");
+        if let Some(pr) = client
+            .find_latest_pull_request(pr_repository, &head)
+            .await?
+        {
+            pull_requests.push((name.clone(), pr));
+        }
+    }
+    Ok(pull_requests)
+}
+
+/// Refreshes the stack table in the pull request `current`, and the pull
+/// requests above and below it.
+async fn update_stack(
+    repo: &Repository,
+    client: &Client,
+    pr_repository: &GithubRepository,
+    push_owner: &str,
+    below: Vec<(String, PullRequest)>,
+    current: PullRequest,
+    descendants: &[String],
+) -> Result<()> {
+    let above = find_stack_pull_requests(
+        repo,
+        client,
+        pr_repository,
+        push_owner,
+        descendants,
+    )
+    .await?;
+
+    let pull_requests: Vec<PullRequest> = below
+        .into_iter()
+        .map(|(_, pr)| pr)
+        .chain(std::iter::once(current))
+        .chain(above.into_iter().map(|(_, pr)| pr))
+        .collect();
+    stack::update(client, pr_repository, &pull_requests).await
+}
+
+/// Works out where the pull request for `branch` sits in its stack, for the
+/// pull request templates. `ancestors` are the branches below it, and `below`
+/// their pull requests. The children are looked up on Github.
+async fn get_stack(
+    client: &Client,
+    pr_repository: &GithubRepository,
+    ancestors: &[String],
+    below: &[(String, PullRequest)],
+    branch_name: &str,
+) -> Result<Stack> {
+    let position = ancestors.len() + 1;
+
+    // The parent is the pull request for the branch directly below.
+    let parent = below
+        .last()
+        .filter(|(name, _)| Some(name) == ancestors.last())
+        .map(|(_, pr)| PullRequestLink::from(pr));
+
+    let children = client
+        .find_pull_requests_with_base(pr_repository, branch_name)
+        .await?
+        .iter()
+        .map(PullRequestLink::from)
+        .collect();
+
+    Ok(Stack {
+        position,
+        parent,
+        children,
+    })
+}
+
+fn check_branch_has_remote(branch: &Branch) -> Result<()> {
     if let Err(e) = branch.upstream() {
         if e.code() == git2::ErrorCode::NotFound {
             let name = match branch.name()? {
@@ -69,9 +481,9 @@ fn check_branch_has_remote<'a>(branch: &Branch<'a>) -> Result<()> {
     Ok(())
 }
 
-fn check_has_remote<'a>(repo: &'a Repository) -> Result<()> {
+fn check_has_remote(repo: &Repository) -> Result<()> {
     let remotes = repo.remotes()?;
-    if remotes.len() == 0 {
+    if remotes.is_empty() {
         return Err(Error::NoRemoteRepository);
     }
     Ok(())
@@ -79,17 +491,26 @@ fn check_has_remote<'a>(repo: &'a Repository) -> Result<()> {
 
 fn get_or_create_branch<'a>(
     repo: &'a Repository,
+    journal: &Journal,
     current_commit: &Commit<'a>,
     branch_name_template: &str,
     branch_name_parameters: &HashMap<String, String>,
+    open_pull_requests: &HashSet<String>,
 ) -> Result<Branch<'a>> {
-    let current_branch = match get_branch_for_commit(&repo, &current_commit)? {
+    let current_branch = match get_branch_for_commit(
+        repo,
+        current_commit,
+        branch_name_template,
+        branch_name_parameters,
+        open_pull_requests,
+    )? {
         Some(b) => b,
         None => {
             info!("No existing branch, creating a new one.");
             create_new_branch(
-                &repo,
-                &current_commit,
+                repo,
+                journal,
+                current_commit,
                 branch_name_template,
                 branch_name_parameters,
             )?
@@ -98,29 +519,9 @@ fn get_or_create_branch<'a>(
     Ok(current_branch)
 }
 
-fn get_branch_for_commit<'a>(
-    repo: &'a Repository,
-    commit: &Commit<'a>,
-) -> Result<Option<Branch<'a>>, git2::Error> {
-    let branches = repo.branches(Some(BranchType::Local))?;
-
-    for branch in branches {
-        match branch {
-            Ok((branch, _branch_type)) => {
-                let branch_commit = branch.get().peel_to_commit().unwrap();
-                if branch_commit.id() == commit.id() {
-                    return Ok(Some(branch));
-                }
-            }
-            Err(e) => error!("Couldn't list branch: {:?}", e),
-        };
-    }
-
-    Ok(None)
-}
-
 fn create_new_branch<'a>(
     repo: &'a Repository,
+    journal: &Journal,
     commit: &Commit<'a>,
     branch_name_template: &str,
     branch_name_parameters: &HashMap<String, String>,
@@ -144,174 +545,46 @@ fn create_new_branch<'a>(
             base_commit: commit.id().to_string(),
         }
     })?;
+    journal.reference(
+        &format!("refs/heads/{branch_name}"),
+        None,
+        commit.id(),
+    )?;
 
     // Setting `head` like this, with `refs/heads/XYZ`, is what sets the current
     // current branch for `git` commands. However, doing it this way means that
     // `libgit2` doesn't recognize it as a branch for `is_head` or
     // `symbolic_target`.
-    repo.set_head(&format!("refs/heads/{branch_name}"))
-        .map_err(|_e| Error::UnableToSelectBranch(branch_name))?;
+    journal.move_head(repo, || {
+        repo.set_head(&format!("refs/heads/{branch_name}"))
+            .map_err(|_e| Error::UnableToSelectBranch(branch_name))
+    })?;
 
     Ok(branch)
 }
 
-fn generate_branch_name(
-    branch_name_template: &str,
-    branch_name_parameters: &HashMap<String, String>,
-    summary: &str,
-) -> Result<String, Error> {
-    // Process the summary to something that can be used as a branch name.
-    let summary = transform(summary);
-
-    use tera::{Context, Tera};
-    let mut context = Context::new();
-    context.insert("summary", &summary);
-    for (k, v) in branch_name_parameters {
-        context.insert(k, &v);
+/// Says which commits below the selected one are already on the main branch,
+/// and can be dropped from the stack, e.g. with `git branchless sync`.
+fn dropped_warnings(
+    repo: &Repository,
+    dropped: &[AlreadyUpstream],
+) -> Result<Vec<String>> {
+    let mut warnings = Vec::new();
+    for d in dropped {
+        let commit = repo.find_commit(d.commit)?;
+        let upstream = repo.find_commit(d.upstream)?;
+        warnings.push(format!(
+            "Commit {} \"{}\" is already on the main branch as {}, and can \
+             be dropped from the stack.",
+            short_id(&commit)?,
+            commit.summary().unwrap_or_default(),
+            short_id(&upstream)?,
+        ));
     }
-    // "{{ summary ~ '-' }}"
-    let branch_name = match Tera::one_off(branch_name_template, &context, true)
-    {
-        Ok(b) => b,
-        Err(e) => {
-            return Err(match get_missing_variable(&e) {
-                Some(name) => Error::MissingBranchParameter(name),
-                None => Error::BranchTemplateMalformed(e.to_string()),
-            })
-        }
-    };
-
-    // if let Some(branch_prefix) = branch_prefix {
-    //     branch_name = format!(
-    //         "{prefix}-{suffix}",
-    //         prefix = branch_prefix,
-    //         suffix = branch_name
-    //     );
-    // }
-
-    Ok(branch_name)
-}
-
-/// Transform the input string into something that is a valid branch name.
-fn transform(summary: &str) -> String {
-    // Currently this filters out non-alphabetic characters. A decision made for
-    // rapid implementation that discriminates against non-English languages. A
-    // better filter would be one based on what characters git and Github don't
-    // allow in branch names.
-    summary
-        .to_lowercase()
-        .chars()
-        .filter(|c| {
-            ('a' <= *c && *c <= 'z')
-                || ('A' <= *c && *c <= 'Z')
-                || ('0' <= *c && *c <= '9')
-                || *c == '-'
-                || *c == ' '
-        })
-        .map(|c| if c.is_whitespace() { '-' } else { c })
-        // I didn't find any documented limits on Git branch names. I didn't
-        // look for documented limits on Github branch names. I did decide there
-        // is a practical limit for usability, however I don't know what it is,
-        // so this number is arbitrary.
-        .take(40)
-        .collect()
+    Ok(warnings)
 }
 
-/// Given a Tera error message, convert it into the name of the missing
-/// variable. If there is a variable in the template that is missing, this is
-/// the only way I was able to find to detect the name of the variable.
-fn get_missing_variable<T>(e: &T) -> Option<String>
-where
-    T: std::error::Error,
-{
-    let e = e.source();
-    if e.is_none() {
-        return None;
-    }
-
-    let e = e.unwrap();
-
-    let e = match e.downcast_ref::<tera::Error>() {
-        Some(e) => e,
-        None => return None,
-    };
-
-    let m = match &e.kind {
-        tera::ErrorKind::Msg(m) => m,
-        _ => return None,
-    };
-
-    if !m.starts_with("Variable `")
-        || !m.contains("` not found in context while rendering ")
-    {
-        return None;
-    }
-
-    let start = m.find('`').unwrap();
-    let end = m.rfind('`').unwrap();
-    let variable = m[start + 1..end].to_string();
-
-    Some(variable)
-}
-
-fn get_main_branch_commit<'a>(
-    repo: &'a Repository,
-) -> Result<(Commit, Branch<'a>)> {
-    // There is a `mainBranch` property in the branchless section of a
-    // configured git repo. I would prefer to take the main branch name from
-    // there, so behavior is consistent with branchless.
-    let branches = repo.branches(Some(BranchType::Local))?;
-
-    for branch in branches {
-        match branch {
-            Ok((branch, _branch_type)) => {
-                if branch.name()? == Some("main") {
-                    return Ok((
-                        branch.get().peel_to_commit().unwrap(),
-                        branch,
-                    ));
-                } else if branch.name()? == Some("master") {
-                    return Ok((
-                        branch.get().peel_to_commit().unwrap(),
-                        branch,
-                    ));
-                }
-            }
-            Err(e) => println!("Couldn't list branch: {:?}", e),
-        };
-    }
-
-    Err(Error::UnknownMainBranch)
-}
-
-fn find_base_branch<'a>(
-    repo: &'a Repository,
-    current_commit: &'a Commit,
-) -> Result<Branch<'a>> {
-    let (main_commit, main_branch) = get_main_branch_commit(repo)?;
-
-    let merge_base = repo.merge_base(main_commit.id(), current_commit.id())?;
-
-    let mut commit = current_commit.clone();
-
-    while commit.id() != merge_base {
-        if commit.parents().len() == 0 {
-            return Err(Error::NoBaseBranch);
-        }
-
-        if commit.parents().len() > 1 {
-            // This error message would work better with the branch name when it is
-            // available. I didn't do it at the time because of time constraints.
-            return Err(Error::MultipleParentCommits(commit.id().to_string()));
-        }
-
-        let parent_commit = commit.parents().nth(0).unwrap();
-
-        match get_branch_for_commit(&repo, &parent_commit)? {
-            Some(branch) => return Ok(branch),
-            None => commit = parent_commit,
-        };
-    }
-
-    Ok(main_branch)
+fn short_id(commit: &Commit) -> Result<String> {
+    let id = commit.as_object().short_id()?;
+    Ok(id.as_str().unwrap_or_default().to_string())
 }
@@ -0,0 +1,68 @@
+//! Converts between days since the Unix epoch and dates in the proleptic
+//! Gregorian calendar, using Howard Hinnant's date algorithms. Years start in
+//! March in the middle of the calculation, so the leap day is the last day of
+//! the year.
+
+/// The number of days from 1970-01-01 to `year`-`month`-`day`, negative for
+/// dates before it.
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era =
+        year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The date `days` days after 1970-01-01, as year, month and day.
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524
+        - day_of_era / 146096)
+        / 365;
+    let day_of_year =
+        day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn days_for_dates() {
+        assert_that!(days_from_civil(1970, 1, 1)).is_equal_to(0);
+        assert_that!(days_from_civil(2000, 2, 29)).is_equal_to(11016);
+        assert_that!(days_from_civil(2000, 3, 1)).is_equal_to(11017);
+        assert_that!(days_from_civil(1969, 12, 31)).is_equal_to(-1);
+    }
+
+    #[test]
+    fn dates_for_days() {
+        assert_that!(civil_from_days(0)).is_equal_to((1970, 1, 1));
+        assert_that!(civil_from_days(11016)).is_equal_to((2000, 2, 29));
+        assert_that!(civil_from_days(-1)).is_equal_to((1969, 12, 31));
+    }
+
+    #[test]
+    fn round_trip() {
+        for days in -300_000..300_000 {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+}
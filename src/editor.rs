@@ -0,0 +1,165 @@
+//! Lets the user edit the title and description of a pull request before it is
+//! created, the same way `git commit` has the commit message edited. The text
+//! is kept in a scratch file in the `.git` directory until the pull request is
+//! created, so it isn't lost when the push or the request to Github fails, and
+//! is used again the next time a pull request is created for the branch.
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use git2::Repository;
+use tracing::info;
+
+use crate::git;
+use crate::pr_template::PullRequestText;
+use crate::result::Error;
+use crate::result::Result;
+
+const SCRATCH_FILE: &str = "GHPR_EDITMSG";
+
+/// Lines starting with this are left out of the title and description. Only
+/// a single `#` followed by a space is a comment, so Markdown headings other
+/// than the top level survive editing.
+const COMMENT: &str = "# ";
+
+const BRANCH_COMMENT: &str = "# Branch: ";
+
+const INSTRUCTIONS: &str = r#"# Enter the title and description of the pull request. The first line is the
+# title, the rest is the description. Lines starting with '# ' are ignored, and
+# an empty message aborts the pull request.
+#"#;
+
+/// Opens the editor on `text`, and returns what the user saved. The text is
+/// left in the scratch file until `discard` is called.
+pub fn edit(
+    repo: &Repository,
+    branch: &str,
+    text: &PullRequestText,
+) -> Result<PullRequestText> {
+    let path = scratch_file(repo);
+    fs::write(&path, format_message(branch, text))?;
+
+    let editor = git::editor(repo).map_err(Error::EditorFailed)?;
+    info!("Editing {:?} with {editor}.", path);
+    // Run through the shell, the way git does, since the editor is often
+    // configured with arguments.
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$@\""))
+        .arg(&editor)
+        .arg(&path)
+        .status()
+        .map_err(|e| Error::EditorFailed(e.to_string()))?;
+    if !status.success() {
+        return Err(Error::EditorFailed(format!(
+            "{editor} exited with {status}"
+        )));
+    }
+
+    let edited = parse_message(&fs::read_to_string(&path)?);
+    edited.ok_or(Error::EmptyPullRequestMessage)
+}
+
+/// The text saved by an earlier `edit` for `branch`, if creating that pull
+/// request didn't succeed.
+pub fn saved(repo: &Repository, branch: &str) -> Option<PullRequestText> {
+    let path = scratch_file(repo);
+    let message = fs::read_to_string(&path).ok()?;
+    let for_branch = message
+        .lines()
+        .any(|l| l.strip_prefix(BRANCH_COMMENT) == Some(branch));
+    if !for_branch {
+        return None;
+    }
+    info!("Using the pull request message saved in {:?}.", path);
+    parse_message(&message)
+}
+
+/// Removes the scratch file, once the pull request has been created.
+pub fn discard(repo: &Repository) -> Result<()> {
+    let path = scratch_file(repo);
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+fn scratch_file(repo: &Repository) -> PathBuf {
+    repo.path().join(SCRATCH_FILE)
+}
+
+fn format_message(branch: &str, text: &PullRequestText) -> String {
+    format!(
+        "{}\n\n{}\n\n{INSTRUCTIONS}\n{BRANCH_COMMENT}{branch}\n",
+        text.title, text.body
+    )
+}
+
+/// Splits an edited message into the title and description, leaving out the
+/// comments. `None` when nothing is left.
+fn parse_message(message: &str) -> Option<PullRequestText> {
+    let lines: Vec<&str> = message
+        .lines()
+        .filter(|l| !l.starts_with(COMMENT) && *l != COMMENT.trim())
+        .collect();
+    let text = lines.join("\n");
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+
+    let (title, body) = text.split_once('\n').unwrap_or((text, ""));
+    Some(PullRequestText {
+        title: title.trim().to_string(),
+        body: body.trim().to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let text = PullRequestText {
+            title: "Add things.".to_string(),
+            body: "## Summary\n\nThe things are needed.".to_string(),
+        };
+
+        let message = format_message("add-things", &text);
+
+        assert_that!(parse_message(&message)).is_equal_to(Some(text));
+    }
+
+    #[test]
+    fn comments_removed() {
+        let message =
+            "# Ignored\nAdd things.\n#\n\nSome\n# Ignored too\nthings.\n";
+
+        assert_that!(parse_message(message)).is_equal_to(Some(
+            PullRequestText {
+                title: "Add things.".to_string(),
+                body: "Some\nthings.".to_string(),
+            },
+        ));
+    }
+
+    #[test]
+    fn title_only() {
+        assert_that!(parse_message("\n\nAdd things.\n\n")).is_equal_to(Some(
+            PullRequestText {
+                title: "Add things.".to_string(),
+                body: String::new(),
+            },
+        ));
+    }
+
+    #[test]
+    fn empty_message() {
+        assert_that!(parse_message("")).is_none();
+        assert_that!(parse_message("\n  \n")).is_none();
+        assert_that!(parse_message(&format!("{INSTRUCTIONS}\n"))).is_none();
+    }
+}
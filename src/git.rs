@@ -0,0 +1,361 @@
+//! Operations that run the `git` command line tool rather than going through
+//! `libgit2`. Talking to remotes this way means the user's git configuration,
+//! credential helpers and ssh setup all apply, the same as for any other git
+//! command they run.
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use git2::{Oid, Repository};
+use tracing::{debug, info};
+
+use crate::result::Error;
+use crate::result::Result;
+
+/// Pushes `branch` to the branch of the same name on `remote`, and makes that
+/// the upstream of the local branch. The push is forced, as long as the remote
+/// branch hasn't changed since it was last fetched, since commits are expected
+/// to be rewritten while a pull request is under review.
+pub fn push_branch(
+    repo: &Repository,
+    remote: &str,
+    branch: &str,
+) -> Result<()> {
+    info!("Pushing {branch} to {remote}.");
+    run(
+        repo,
+        &[
+            "push",
+            "--force-with-lease",
+            "--set-upstream",
+            remote,
+            &format!("refs/heads/{branch}:refs/heads/{branch}"),
+        ],
+    )
+    .map_err(|e| Error::PushFailed {
+        branch: branch.to_string(),
+        remote: remote.to_string(),
+        message: e,
+    })?;
+    Ok(())
+}
+
+/// Fetches `branch` from `remote`, updating the remote tracking branch.
+pub fn fetch(repo: &Repository, remote: &str, branch: &str) -> Result<()> {
+    info!("Fetching {branch} from {remote}.");
+    run(repo, &["fetch", remote, branch]).map_err(|e| Error::FetchFailed {
+        branch: branch.to_string(),
+        remote: remote.to_string(),
+        message: e,
+    })?;
+    Ok(())
+}
+
+/// Fetches the head of pull request `number` from `remote` into the local
+/// branch `branch`. Github keeps the head of every pull request in the base
+/// repository as `refs/pull/<number>/head`, even when it comes from a fork.
+/// Unless `force` is set, an existing branch is only moved forward, so no
+/// local commits are lost. With it, the branch follows the pull request when
+/// its commits are rewritten.
+pub fn fetch_pull_request(
+    repo: &Repository,
+    remote: &str,
+    number: u64,
+    branch: &str,
+    force: bool,
+) -> Result<()> {
+    info!("Fetching pull request #{number} from {remote} into {branch}.");
+    let force = if force { "+" } else { "" };
+    run(
+        repo,
+        &[
+            "fetch",
+            remote,
+            &format!("{force}refs/pull/{number}/head:refs/heads/{branch}"),
+        ],
+    )
+    .map_err(|e| Error::FetchFailed {
+        branch: branch.to_string(),
+        remote: remote.to_string(),
+        message: e,
+    })?;
+    Ok(())
+}
+
+/// Checks out `branch` without switching to it, leaving HEAD detached the way
+/// git-branchless does.
+pub fn checkout_detached(repo: &Repository, branch: &str) -> Result<()> {
+    info!("Checking out {branch}.");
+    run(repo, &["checkout", "--detach", branch]).map_err(|e| {
+        Error::CheckoutFailed {
+            branch: branch.to_string(),
+            message: e,
+        }
+    })?;
+    Ok(())
+}
+
+/// Deletes `branch` from `remote`. A branch that is already gone, because
+/// Github deleted it after merging, isn't an error.
+pub fn delete_remote_branch(
+    repo: &Repository,
+    remote: &str,
+    branch: &str,
+) -> Result<()> {
+    info!("Deleting {branch} from {remote}.");
+    match run(repo, &["push", "--delete", remote, branch]) {
+        Ok(_) => Ok(()),
+        Err(e) if e.contains("remote ref does not exist") => {
+            info!("{branch} was already deleted from {remote}.");
+            Ok(())
+        }
+        Err(e) => Err(Error::DeleteBranchFailed {
+            branch: branch.to_string(),
+            remote: remote.to_string(),
+            message: e,
+        }),
+    }
+}
+
+/// Compares the commits in the range `old` with the ones in the range `new`,
+/// the way `git range-diff` does, without colour.
+pub fn range_diff(
+    repo: &Repository,
+    old: &str,
+    new: &str,
+) -> Result<String, String> {
+    run(repo, &["range-diff", "--no-color", old, new])
+}
+
+/// Fetches the notes in `notes_ref` from `remote`, and merges them into the
+/// local ones. A remote without the notes yet isn't an error.
+pub fn fetch_notes(
+    repo: &Repository,
+    remote: &str,
+    notes_ref: &str,
+) -> Result<(), String> {
+    let name = notes_ref.strip_prefix("refs/notes/").unwrap_or(notes_ref);
+    let fetched = format!("refs/notes/remotes/{remote}/{name}");
+    info!("Fetching {notes_ref} from {remote}.");
+    match run(repo, &["fetch", remote, &format!("+{notes_ref}:{fetched}")]) {
+        Ok(_) => {}
+        Err(e) if e.contains("couldn't find remote ref") => return Ok(()),
+        Err(e) => return Err(e),
+    }
+    // Both sides only ever add lines about pull requests, so keeping all of
+    // them is the right way to merge.
+    run(
+        repo,
+        &[
+            "notes",
+            &format!("--ref={notes_ref}"),
+            "merge",
+            "--quiet",
+            "--strategy=cat_sort_uniq",
+            &fetched,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Pushes the reference `name` to the same name on `remote`.
+pub fn push_ref(repo: &Repository, remote: &str, name: &str) -> Result<()> {
+    info!("Pushing {name} to {remote}.");
+    run(repo, &["push", remote, &format!("{name}:{name}")]).map_err(|e| {
+        Error::PushFailed {
+            branch: name.to_string(),
+            remote: remote.to_string(),
+            message: e,
+        }
+    })?;
+    Ok(())
+}
+
+/// The commit the reference `name` points at on `remote`, asked of the remote
+/// itself rather than taken from a remote tracking branch, which may be
+/// missing or out of date. It is asked where pushes go, which can differ from
+/// where fetches come from. `None` when the remote has no such reference.
+pub fn remote_ref(
+    repo: &Repository,
+    remote: &str,
+    name: &str,
+) -> Result<Option<String>> {
+    let error = |message| Error::FetchFailed {
+        branch: name.to_string(),
+        remote: remote.to_string(),
+        message,
+    };
+    let url =
+        run(repo, &["remote", "get-url", "--push", remote]).map_err(error)?;
+    let output = run(repo, &["ls-remote", url.trim(), name]).map_err(error)?;
+    // `ls-remote` matches the end of the name, so look for it exactly.
+    Ok(output.lines().find_map(|line| {
+        let (commit, found) = line.split_once('\t')?;
+        (found == name).then(|| commit.to_string())
+    }))
+}
+
+/// Points the reference `name` on `remote` at `commit`, whatever it points at
+/// now.
+pub fn push_commit(
+    repo: &Repository,
+    remote: &str,
+    commit: &str,
+    name: &str,
+) -> Result<()> {
+    info!("Pushing {commit} to {name} on {remote}.");
+    run(
+        repo,
+        &["push", "--force", remote, &format!("{commit}:{name}")],
+    )
+    .map_err(|e| Error::PushFailed {
+        branch: name.to_string(),
+        remote: remote.to_string(),
+        message: e,
+    })?;
+    Ok(())
+}
+
+/// The changes from the commit `from` to the commit `to`, without colour.
+pub fn diff(repo: &Repository, from: &str, to: &str) -> Result<String, String> {
+    run(repo, &["diff", "--no-color", from, to])
+}
+
+/// Rebases the commits of the stack onto the main branch with git-branchless.
+pub fn branchless_sync(repo: &Repository) -> Result<()> {
+    info!("Running git branchless sync.");
+    run(repo, &["branchless", "sync"]).map_err(Error::SyncFailed)?;
+    Ok(())
+}
+
+/// Runs the hook `name` with `args`, and `input` on stdin, if the repository
+/// has one, the way git would after an operation of its own.
+pub fn run_hook(
+    repo: &Repository,
+    name: &str,
+    args: &[&str],
+    input: &str,
+) -> Result<()> {
+    let hooks = match repo.config()?.get_path("core.hooksPath") {
+        Ok(p) => p,
+        Err(_) => repo.path().join("hooks"),
+    };
+    let hook = hooks.join(name);
+    if !hook.is_file() {
+        return Ok(());
+    }
+    let directory = repo.workdir().unwrap_or_else(|| repo.path());
+    let hook_failed = |e: String| Error::HookFailed {
+        hook: name.to_string(),
+        message: e,
+    };
+    info!("Running the {name} hook.");
+    let mut child = Command::new(&hook)
+        .current_dir(directory)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| hook_failed(e.to_string()))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(input.as_bytes())
+            .map_err(|e| hook_failed(e.to_string()))?;
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| hook_failed(e.to_string()))?;
+    if !output.status.success() {
+        return Err(hook_failed(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Moves each reference in `updates`, given as its name, old commit and new
+/// commit, in one transaction with `git update-ref`, so either all of them
+/// move or none do. A reference that has moved since is not touched. Going
+/// through git runs the `reference-transaction` hook, which is how
+/// git-branchless learns about branches moved outside of it.
+pub fn update_refs(
+    repo: &Repository,
+    message: &str,
+    updates: &[(String, Oid, Oid)],
+) -> Result<()> {
+    if updates.is_empty() {
+        return Ok(());
+    }
+    let input: String = updates
+        .iter()
+        .map(|(name, old, new)| {
+            // A detached HEAD is moved itself, not followed.
+            let option = if name == "HEAD" {
+                "option no-deref\n"
+            } else {
+                ""
+            };
+            format!("{option}update {name} {new} {old}\n")
+        })
+        .collect();
+    info!("Moving {} references.", updates.len());
+    run_with_input(
+        repo,
+        &["update-ref", "-m", message, "--stdin"],
+        Some(&input),
+    )
+    .map_err(Error::UpdateRefsFailed)?;
+    Ok(())
+}
+
+/// The editor git would use, from `GIT_EDITOR`, `core.editor`, `VISUAL` or
+/// `EDITOR`, in that order.
+pub fn editor(repo: &Repository) -> Result<String, String> {
+    Ok(run(repo, &["var", "GIT_EDITOR"])?.trim().to_string())
+}
+
+/// Runs `git` with `args` in the repository, returning stdout when it succeeds
+/// and stderr when it doesn't.
+fn run(repo: &Repository, args: &[&str]) -> Result<String, String> {
+    run_with_input(repo, args, None)
+}
+
+/// Runs `git` the same as `run`, with `input` on stdin.
+fn run_with_input(
+    repo: &Repository,
+    args: &[&str],
+    input: Option<&str>,
+) -> Result<String, String> {
+    let directory = repo.workdir().unwrap_or_else(|| repo.path());
+    debug!("Running git {:?} in {:?}", args, directory);
+
+    let could_not_run = |e: std::io::Error| format!("Could not run git: {e}");
+    let mut command = Command::new("git");
+    command.current_dir(directory).args(args);
+    let output = match input {
+        None => command.output().map_err(could_not_run)?,
+        Some(input) => {
+            let mut child = command
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(could_not_run)?;
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(input.as_bytes()).map_err(could_not_run)?;
+            }
+            child.wait_with_output().map_err(could_not_run)?
+        }
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    debug!("git stdout: {stdout}");
+    debug!("git stderr: {stderr}");
+
+    if output.status.success() {
+        Ok(stdout)
+    } else {
+        Err(stderr.trim().to_string())
+    }
+}
@@ -0,0 +1,609 @@
+//! A small client for the parts of the Github REST API needed for managing
+//! pull requests.
+use reqwest::header::{ACCEPT, AUTHORIZATION, USER_AGENT};
+use reqwest::{RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::configuration::{Host, MergeMethod};
+use crate::remote::GithubRepository;
+use crate::result::Error;
+use crate::result::Result;
+
+pub const AGENT: &str =
+    concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// How many items to ask for in each page of a list, the most Github allows.
+const PAGE_SIZE: usize = 100;
+
+pub struct Client {
+    http: reqwest::Client,
+    api_url: String,
+    graphql_url: String,
+    token: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PullRequest {
+    pub number: u64,
+    pub html_url: String,
+    #[serde(default)]
+    pub title: String,
+    /// `open` or `closed`. A merged pull request is closed, with `merged_at`
+    /// set.
+    #[serde(default)]
+    pub state: String,
+    #[serde(default)]
+    pub draft: bool,
+    pub merged_at: Option<String>,
+    pub body: Option<String>,
+    /// The ID of the pull request in the GraphQL API.
+    #[serde(default)]
+    pub node_id: String,
+    #[serde(default)]
+    pub head: BranchRef,
+    #[serde(default)]
+    pub base: BranchRef,
+}
+
+/// One side of a pull request.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct BranchRef {
+    #[serde(rename = "ref")]
+    pub name: String,
+    #[serde(default)]
+    pub sha: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NewPullRequest<'a> {
+    pub title: &'a str,
+    pub body: &'a str,
+    /// The branch with the changes. For a branch in a fork, this is in the
+    /// form `owner:branch`.
+    pub head: &'a str,
+    /// The branch the changes are to be merged into.
+    pub base: &'a str,
+    pub draft: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct PullRequestBody<'a> {
+    body: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct PullRequestState<'a> {
+    state: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct Comment<'a> {
+    body: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct PullRequestBase<'a> {
+    base: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct Merge<'a> {
+    merge_method: MergeMethod,
+    /// The head the pull request must still have for it to be merged.
+    sha: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MergeResult {
+    pub sha: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ReviewRequest<'a> {
+    reviewers: &'a [&'a str],
+    team_reviewers: &'a [&'a str],
+}
+
+#[derive(Debug, Serialize)]
+struct Labels<'a> {
+    labels: &'a [String],
+}
+
+#[derive(Debug, Serialize)]
+struct Assignees<'a> {
+    assignees: &'a [String],
+}
+
+#[derive(Debug, Serialize)]
+struct IssueMilestone {
+    milestone: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct User {
+    pub login: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Milestone {
+    pub number: u64,
+    pub title: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InstallationToken {
+    pub token: String,
+    pub expires_at: String,
+}
+
+#[derive(Debug, Serialize)]
+struct GraphqlRequest<'a> {
+    query: &'a str,
+    variables: serde_json::Value,
+}
+
+/// GraphQL reports errors in the response, rather than with the status.
+#[derive(Debug, Deserialize)]
+struct GraphqlResponse<T> {
+    data: Option<T>,
+    #[serde(default)]
+    errors: Vec<ErrorResponse>,
+}
+
+/// The error document Github returns with unsuccessful responses.
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+impl Client {
+    pub fn new(host: &Host, token: &str) -> Result<Self> {
+        let client = Self {
+            http: reqwest::Client::builder().build()?,
+            api_url: host.api_url.clone(),
+            graphql_url: host.graphql_url.clone(),
+            token: token.to_string(),
+        };
+        debug!(
+            "Github API for {} at {} and {}",
+            host.name, client.api_url, client.graphql_url
+        );
+        Ok(client)
+    }
+
+    /// Finds the open pull request for `head`, in the form `owner:branch`, in
+    /// the repository `repo`.
+    pub async fn find_pull_request(
+        &self,
+        repo: &GithubRepository,
+        head: &str,
+    ) -> Result<Option<PullRequest>> {
+        info!("Looking for a pull request for {head} in {repo}.");
+        let request = self
+            .get(&format!("/repos/{}/{}/pulls", repo.owner, repo.name))
+            .query(&[("head", head), ("state", "open")]);
+        let mut pull_requests: Vec<PullRequest> =
+            self.send_all(request).await?;
+        let pull_request = pull_requests.pop();
+        if let Some(pr) = &pull_request {
+            info!("Found pull request #{}.", pr.number);
+        }
+        Ok(pull_request)
+    }
+
+    pub async fn get_pull_request(
+        &self,
+        repo: &GithubRepository,
+        number: u64,
+    ) -> Result<PullRequest> {
+        info!("Getting pull request #{number} in {repo}.");
+        let request = self.get(&format!(
+            "/repos/{}/{}/pulls/{number}",
+            repo.owner, repo.name
+        ));
+        self.send(request).await
+    }
+
+    /// Finds the most recent pull request for `head`, whether it is open,
+    /// closed or merged.
+    pub async fn find_latest_pull_request(
+        &self,
+        repo: &GithubRepository,
+        head: &str,
+    ) -> Result<Option<PullRequest>> {
+        info!("Looking for the latest pull request for {head} in {repo}.");
+        let request = self
+            .get(&format!("/repos/{}/{}/pulls", repo.owner, repo.name))
+            .query(&[("head", head), ("state", "all")]);
+        let pull_requests: Vec<PullRequest> = self.send(request).await?;
+        Ok(pull_requests.into_iter().next())
+    }
+
+    /// Lists the open pull requests in `repo` that are to be merged into the
+    /// branch `base`.
+    pub async fn find_pull_requests_with_base(
+        &self,
+        repo: &GithubRepository,
+        base: &str,
+    ) -> Result<Vec<PullRequest>> {
+        info!("Looking for pull requests onto {base} in {repo}.");
+        let request = self
+            .get(&format!("/repos/{}/{}/pulls", repo.owner, repo.name))
+            .query(&[("base", base), ("state", "open")]);
+        self.send_all(request).await
+    }
+
+    pub async fn create_pull_request(
+        &self,
+        repo: &GithubRepository,
+        pull_request: &NewPullRequest<'_>,
+    ) -> Result<PullRequest> {
+        info!(
+            "Creating a pull request for {} onto {} in {repo}.",
+            pull_request.head, pull_request.base
+        );
+        let request = self
+            .post(&format!("/repos/{}/{}/pulls", repo.owner, repo.name))
+            .json(pull_request);
+        self.send(request).await
+    }
+
+    pub async fn update_pull_request_body(
+        &self,
+        repo: &GithubRepository,
+        number: u64,
+        body: &str,
+    ) -> Result<PullRequest> {
+        info!("Updating the body of pull request #{number} in {repo}.");
+        let request = self
+            .patch(&format!(
+                "/repos/{}/{}/pulls/{number}",
+                repo.owner, repo.name
+            ))
+            .json(&PullRequestBody { body });
+        self.send(request).await
+    }
+
+    /// Changes the branch pull request `number` is to be merged into.
+    pub async fn update_pull_request_base(
+        &self,
+        repo: &GithubRepository,
+        number: u64,
+        base: &str,
+    ) -> Result<PullRequest> {
+        info!("Changing the base of pull request #{number} to {base}.");
+        let request = self
+            .patch(&format!(
+                "/repos/{}/{}/pulls/{number}",
+                repo.owner, repo.name
+            ))
+            .json(&PullRequestBase { base });
+        self.send(request).await
+    }
+
+    pub async fn close_pull_request(
+        &self,
+        repo: &GithubRepository,
+        number: u64,
+    ) -> Result<PullRequest> {
+        info!("Closing pull request #{number} in {repo}.");
+        let request = self
+            .patch(&format!(
+                "/repos/{}/{}/pulls/{number}",
+                repo.owner, repo.name
+            ))
+            .json(&PullRequestState { state: "closed" });
+        self.send(request).await
+    }
+
+    /// Adds a comment to the conversation of pull request `number`.
+    pub async fn add_comment(
+        &self,
+        repo: &GithubRepository,
+        number: u64,
+        body: &str,
+    ) -> Result<()> {
+        info!("Commenting on #{number} in {repo}.");
+        let request = self
+            .post(&format!(
+                "/repos/{}/{}/issues/{number}/comments",
+                repo.owner, repo.name
+            ))
+            .json(&Comment { body });
+        self.execute(request).await
+    }
+
+    /// Merges pull request `number`, as long as its head is still `sha`.
+    pub async fn merge_pull_request(
+        &self,
+        repo: &GithubRepository,
+        number: u64,
+        merge_method: MergeMethod,
+        sha: &str,
+    ) -> Result<MergeResult> {
+        info!("Merging pull request #{number} in {repo}.");
+        let request = self
+            .put(&format!(
+                "/repos/{}/{}/pulls/{number}/merge",
+                repo.owner, repo.name
+            ))
+            .json(&Merge { merge_method, sha });
+        self.send(request).await
+    }
+
+    /// The user the token belongs to.
+    pub async fn current_user(&self) -> Result<User> {
+        self.send(self.get("/user")).await
+    }
+
+    /// Requests a review of pull request `number` from a user, or from a team
+    /// when `team` is set. `reviewer` is the team's slug, without the
+    /// organization.
+    pub async fn request_review(
+        &self,
+        repo: &GithubRepository,
+        number: u64,
+        reviewer: &str,
+        team: bool,
+    ) -> Result<()> {
+        info!("Requesting a review of #{number} from {reviewer} in {repo}.");
+        let reviewers = [reviewer];
+        let request = self
+            .post(&format!(
+                "/repos/{}/{}/pulls/{number}/requested_reviewers",
+                repo.owner, repo.name
+            ))
+            .json(&ReviewRequest {
+                reviewers: if team { &[] } else { &reviewers },
+                team_reviewers: if team { &reviewers } else { &[] },
+            });
+        self.execute(request).await
+    }
+
+    /// Whether `repo` has the label `name`. Github creates labels that don't
+    /// exist when they are added, so they are checked first.
+    pub async fn has_label(
+        &self,
+        repo: &GithubRepository,
+        name: &str,
+    ) -> Result<bool> {
+        let request = self.get(&format!(
+            "/repos/{}/{}/labels/{}",
+            repo.owner,
+            repo.name,
+            path_segment(name)
+        ));
+        self.exists(request).await
+    }
+
+    pub async fn add_labels(
+        &self,
+        repo: &GithubRepository,
+        number: u64,
+        labels: &[String],
+    ) -> Result<()> {
+        info!("Adding the labels {labels:?} to #{number} in {repo}.");
+        let request = self
+            .post(&format!(
+                "/repos/{}/{}/issues/{number}/labels",
+                repo.owner, repo.name
+            ))
+            .json(&Labels { labels });
+        self.execute(request).await
+    }
+
+    /// Whether `user` can be assigned to issues and pull requests in `repo`.
+    /// Github silently leaves out anyone who can't be.
+    pub async fn is_assignable(
+        &self,
+        repo: &GithubRepository,
+        user: &str,
+    ) -> Result<bool> {
+        let request = self.get(&format!(
+            "/repos/{}/{}/assignees/{}",
+            repo.owner,
+            repo.name,
+            path_segment(user)
+        ));
+        self.exists(request).await
+    }
+
+    pub async fn add_assignees(
+        &self,
+        repo: &GithubRepository,
+        number: u64,
+        assignees: &[String],
+    ) -> Result<()> {
+        info!("Assigning {assignees:?} to #{number} in {repo}.");
+        let request = self
+            .post(&format!(
+                "/repos/{}/{}/issues/{number}/assignees",
+                repo.owner, repo.name
+            ))
+            .json(&Assignees { assignees });
+        self.execute(request).await
+    }
+
+    pub async fn list_open_milestones(
+        &self,
+        repo: &GithubRepository,
+    ) -> Result<Vec<Milestone>> {
+        let request = self
+            .get(&format!("/repos/{}/{}/milestones", repo.owner, repo.name))
+            .query(&[("state", "open")]);
+        self.send_all(request).await
+    }
+
+    pub async fn set_milestone(
+        &self,
+        repo: &GithubRepository,
+        number: u64,
+        milestone: u64,
+    ) -> Result<()> {
+        info!("Setting milestone {milestone} on #{number} in {repo}.");
+        let request = self
+            .patch(&format!(
+                "/repos/{}/{}/issues/{number}",
+                repo.owner, repo.name
+            ))
+            .json(&IssueMilestone { milestone });
+        self.execute(request).await
+    }
+
+    /// Exchanges the App JWT this client was created with for a token for the
+    /// App installation.
+    pub async fn create_installation_token(
+        &self,
+        installation_id: u64,
+    ) -> Result<InstallationToken> {
+        info!("Requesting a token for App installation {installation_id}.");
+        let request = self.post(&format!(
+            "/app/installations/{installation_id}/access_tokens"
+        ));
+        self.send(request).await
+    }
+
+    /// Runs a GraphQL query or mutation, returning its `data`.
+    pub async fn graphql<T: DeserializeOwned>(
+        &self,
+        query: &str,
+        variables: serde_json::Value,
+    ) -> Result<T> {
+        let request = self
+            .request(self.http.post(&self.graphql_url))
+            .json(&GraphqlRequest { query, variables });
+        let response: GraphqlResponse<T> = self.send(request).await?;
+        if !response.errors.is_empty() {
+            let messages: Vec<String> =
+                response.errors.into_iter().map(|e| e.message).collect();
+            return Err(Error::GithubGraphql(messages.join(" ")));
+        }
+        response.data.ok_or_else(|| {
+            Error::GithubGraphql("The response had no data.".to_string())
+        })
+    }
+
+    fn get(&self, path: &str) -> RequestBuilder {
+        self.request(self.http.get(format!("{}{path}", self.api_url)))
+    }
+
+    fn post(&self, path: &str) -> RequestBuilder {
+        self.request(self.http.post(format!("{}{path}", self.api_url)))
+    }
+
+    fn put(&self, path: &str) -> RequestBuilder {
+        self.request(self.http.put(format!("{}{path}", self.api_url)))
+    }
+
+    fn patch(&self, path: &str) -> RequestBuilder {
+        self.request(self.http.patch(format!("{}{path}", self.api_url)))
+    }
+
+    fn request(&self, request: RequestBuilder) -> RequestBuilder {
+        request
+            .header(ACCEPT, "application/vnd.github+json")
+            .header(AUTHORIZATION, format!("Bearer {}", self.token))
+            .header(USER_AGENT, AGENT)
+            .header("X-GitHub-Api-Version", "2022-11-28")
+    }
+
+    async fn send<T: DeserializeOwned>(
+        &self,
+        request: RequestBuilder,
+    ) -> Result<T> {
+        let response = request.send().await?;
+        let status = response.status();
+        debug!("Github responded with {status}.");
+
+        if !status.is_success() {
+            return Err(api_error(status, &response.text().await?));
+        }
+
+        Ok(response.json().await?)
+    }
+
+    /// Sends a request for a list, and for each further page of it, until a
+    /// page comes back short. Github only gives 30 items at a time otherwise.
+    async fn send_all<T: DeserializeOwned>(
+        &self,
+        request: RequestBuilder,
+    ) -> Result<Vec<T>> {
+        let mut all = Vec::new();
+        for page in 1.. {
+            let page_request = request
+                .try_clone()
+                .ok_or_else(|| {
+                    Error::GithubRequest(
+                        "A request with a streamed body can't be repeated for \
+                         each page."
+                            .to_string(),
+                    )
+                })?
+                .query(&[("per_page", PAGE_SIZE), ("page", page)]);
+            let items: Vec<T> = self.send(page_request).await?;
+            let last = items.len() < PAGE_SIZE;
+            all.extend(items);
+            if last {
+                break;
+            }
+        }
+        Ok(all)
+    }
+
+    /// Sends a request where only whether it succeeded matters.
+    async fn execute(&self, request: RequestBuilder) -> Result<()> {
+        let response = request.send().await?;
+        let status = response.status();
+        debug!("Github responded with {status}.");
+
+        if !status.is_success() {
+            return Err(api_error(status, &response.text().await?));
+        }
+        Ok(())
+    }
+
+    /// Sends a request for something that may not exist, where Github answers
+    /// with `404 Not Found` if it doesn't.
+    async fn exists(&self, request: RequestBuilder) -> Result<bool> {
+        let response = request.send().await?;
+        let status = response.status();
+        debug!("Github responded with {status}.");
+
+        match status {
+            StatusCode::NOT_FOUND => Ok(false),
+            s if s.is_success() => Ok(true),
+            s => Err(api_error(s, &response.text().await?)),
+        }
+    }
+}
+
+/// Escapes `segment`, like a label name with spaces in it, for use as one
+/// segment of a URL path.
+fn path_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'_'
+            | b'.'
+            | b'~' => (b as char).to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+fn api_error(status: StatusCode, body: &str) -> Error {
+    let message = match serde_json::from_str::<ErrorResponse>(body) {
+        Ok(e) => e.message,
+        Err(_) => body.to_string(),
+    };
+    Error::GithubApi {
+        status: status.as_u16(),
+        message,
+    }
+}
@@ -0,0 +1,245 @@
+//! Authenticates as an installation of a Github App, for automation that acts
+//! as a bot rather than as a user. A JWT signed with the App's private key is
+//! exchanged for an installation token, which lasts an hour. The token is
+//! cached in the user cache directory so every command doesn't have to ask for
+//! a new one.
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use directories::ProjectDirs;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::configuration::{Host, HostOptions};
+use crate::date;
+use crate::github::Client;
+use crate::result::Error;
+use crate::result::Result;
+use crate::secret_file;
+
+const CACHE_FILE: &str = "app-tokens.json";
+
+/// Cached tokens are replaced this many seconds before they expire, so they
+/// don't expire part way through a command.
+const EXPIRY_MARGIN: u64 = 5 * 60;
+
+/// The configuration needed to authenticate as an App installation.
+#[derive(Debug)]
+pub struct App {
+    pub app_id: u64,
+    pub installation_id: u64,
+    private_key: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Claims {
+    iat: u64,
+    exp: u64,
+    iss: String,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Cache {
+    tokens: HashMap<String, CachedToken>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct CachedToken {
+    token: String,
+    /// Seconds since the Unix epoch.
+    expires_at: u64,
+}
+
+impl App {
+    /// Reads the App settings for a host. Returns `None` when no App is
+    /// configured, and an error when the configuration is incomplete.
+    pub fn from_options(
+        host: &str,
+        options: &HostOptions,
+    ) -> Result<Option<Self>> {
+        let app_id = match options.app_id {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+
+        let installation_id = options.installation_id.ok_or_else(|| {
+            Error::GithubApp(format!(
+                "`installation_id` is required with `app_id` for {host}."
+            ))
+        })?;
+
+        let private_key = match (&options.private_key, &options.private_key_path)
+        {
+            (Some(key), _) => key.clone(),
+            (None, Some(path)) => fs::read_to_string(path).map_err(|e| {
+                Error::GithubApp(format!(
+                    "Could not read the private key {}: {e}",
+                    path.display()
+                ))
+            })?,
+            (None, None) => {
+                return Err(Error::GithubApp(format!(
+                    "`private_key` or `private_key_path` is required with `app_id` for {host}."
+                )))
+            }
+        };
+
+        Ok(Some(Self {
+            app_id,
+            installation_id,
+            private_key,
+        }))
+    }
+
+    /// Gets an installation token, from the cache if there is one that is
+    /// still valid, otherwise from Github.
+    pub async fn installation_token(&self, host: &Host) -> Result<String> {
+        let key =
+            format!("{}/{}/{}", host.name, self.app_id, self.installation_id);
+        let now = now();
+
+        let mut cache = read_cache();
+        if let Some(cached) = cache.tokens.get(&key) {
+            if cached.expires_at > now + EXPIRY_MARGIN {
+                info!("Using the cached installation token for {key}.");
+                return Ok(cached.token.clone());
+            }
+        }
+
+        let jwt = self.sign(now)?;
+        let client = Client::new(host, &jwt)?;
+        let token = client
+            .create_installation_token(self.installation_id)
+            .await?;
+
+        let expires_at =
+            parse_timestamp(&token.expires_at).ok_or_else(|| {
+                Error::GithubApp(format!(
+                    "Unexpected expiry time {} for the installation token.",
+                    token.expires_at
+                ))
+            })?;
+        cache.tokens.retain(|_, t| t.expires_at > now);
+        cache.tokens.insert(
+            key,
+            CachedToken {
+                token: token.token.clone(),
+                expires_at,
+            },
+        );
+        write_cache(&cache);
+
+        Ok(token.token)
+    }
+
+    /// Creates the JWT that authenticates as the App itself. Github allows a
+    /// lifetime of at most 10 minutes, and recommends backdating the issue
+    /// time to allow for clock drift.
+    fn sign(&self, now: u64) -> Result<String> {
+        let claims = Claims {
+            iat: now - 60,
+            exp: now + 9 * 60,
+            iss: self.app_id.to_string(),
+        };
+        let key = EncodingKey::from_rsa_pem(self.private_key.as_bytes())
+            .map_err(|e| {
+                Error::GithubApp(format!("The private key is not valid: {e}"))
+            })?;
+        jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &key)
+            .map_err(|e| {
+                Error::GithubApp(format!("Could not sign the JWT: {e}"))
+            })
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn cache_path() -> Option<PathBuf> {
+    let pd = ProjectDirs::from("org", "git tools", "github-pull-request")?;
+    Some(pd.cache_dir().join(CACHE_FILE))
+}
+
+fn read_cache() -> Cache {
+    let path = match cache_path() {
+        Some(p) => p,
+        None => return Cache::default(),
+    };
+    match fs::read_to_string(&path) {
+        Ok(text) => serde_json::from_str(&text).unwrap_or_default(),
+        Err(e) => {
+            debug!("Could not read {:?}: {}", path, e);
+            Cache::default()
+        }
+    }
+}
+
+/// Saves the cache, readable only by the user since it holds tokens. Failing
+/// to save only means a new token is requested next time.
+fn write_cache(cache: &Cache) {
+    let path = match cache_path() {
+        Some(p) => p,
+        None => return,
+    };
+    let result = serde_json::to_string(cache)
+        .map_err(std::io::Error::other)
+        .and_then(|text| secret_file::write(&path, &text));
+    if let Err(e) = result {
+        debug!("Could not write {:?}: {}", path, e);
+    }
+}
+
+/// Converts a UTC timestamp, as Github formats them, `2016-07-11T22:14:10Z`,
+/// into seconds since the Unix epoch.
+fn parse_timestamp(timestamp: &str) -> Option<u64> {
+    let timestamp = timestamp.strip_suffix('Z')?;
+    let (date, time) = timestamp.split_once('T')?;
+
+    let mut date = date.split('-').map(|p| p.parse::<i64>());
+    let (year, month, day) =
+        (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+
+    let mut time = time.split(':').map(|p| p.parse::<i64>());
+    let (hour, minute, second) =
+        (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let days = date::days_from_civil(year, month, day);
+    let seconds = days * 86400 + hour * 3600 + minute * 60 + second;
+    u64::try_from(seconds).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn timestamps() {
+        assert_that!(parse_timestamp("1970-01-01T00:00:00Z"))
+            .is_equal_to(Some(0));
+        assert_that!(parse_timestamp("2016-07-11T22:14:10Z"))
+            .is_equal_to(Some(1468275250));
+        assert_that!(parse_timestamp("2024-02-29T12:00:00Z"))
+            .is_equal_to(Some(1709208000));
+    }
+
+    #[test]
+    fn invalid_timestamps() {
+        assert_that!(parse_timestamp("2016-07-11T22:14:10")).is_none();
+        assert_that!(parse_timestamp("2016-07-11 22:14:10Z")).is_none();
+        assert_that!(parse_timestamp("2016-13-11T22:14:10Z")).is_none();
+        assert_that!(parse_timestamp("yesterday")).is_none();
+    }
+}
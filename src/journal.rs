@@ -0,0 +1,382 @@
+//! Keeps a journal of what each operation changes, in `.git/ghpr/journal`, so
+//! an operation that fails partway through, or wasn't wanted, can be undone
+//! with `git ghpr undo`. Each change is written as it is made, one JSON object
+//! per line, so the journal is complete up to wherever an operation stopped.
+use std::cell::Cell;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use git2::{Oid, Repository};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::git;
+use crate::github::PullRequest;
+use crate::remote::GithubRepository;
+use crate::result::Result;
+
+/// A change made by an operation, with what is needed to undo it.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Entry {
+    /// The start of an operation.
+    Begin { command: String },
+    /// The local reference `name` was created, when `old` is `None`, or moved
+    /// from `old`.
+    Reference {
+        name: String,
+        old: Option<String>,
+        new: String,
+    },
+    /// HEAD was moved from `old`, a reference name when it was on a branch,
+    /// or a commit when it was detached.
+    Head { old: String },
+    /// The reference `name` was pushed to `remote`, where it was `old`, or
+    /// didn't exist when `old` is `None`.
+    Push {
+        remote: String,
+        name: String,
+        old: Option<String>,
+    },
+    /// A pull request was opened.
+    PullRequest {
+        host: String,
+        owner: String,
+        repository: String,
+        number: u64,
+        url: String,
+    },
+}
+
+/// The journal of the operation in progress.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    command: String,
+    /// Whether the operation's `Begin` has been written. It is only written
+    /// with the first change, so an operation that changes nothing doesn't
+    /// hide the one before it from `undo`.
+    begun: Cell<bool>,
+}
+
+impl Journal {
+    /// Starts journaling the operation `command`.
+    pub fn begin(repo: &Repository, command: &str) -> Result<Self> {
+        let journal = Self {
+            path: path(repo),
+            command: command.to_string(),
+            begun: Cell::new(false),
+        };
+        if let Some(parent) = journal.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Ok(journal)
+    }
+
+    /// Whether the operation has changed anything yet.
+    pub fn has_changes(&self) -> bool {
+        self.begun.get()
+    }
+
+    pub fn reference(
+        &self,
+        name: &str,
+        old: Option<Oid>,
+        new: Oid,
+    ) -> Result<()> {
+        self.write(&Entry::Reference {
+            name: name.to_string(),
+            old: old.map(|o| o.to_string()),
+            new: new.to_string(),
+        })
+    }
+
+    /// Runs `change`, recording the reference `name` if it moves.
+    pub fn track<T>(
+        &self,
+        repo: &Repository,
+        name: &str,
+        change: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        let target = |repo: &Repository| {
+            repo.find_reference(name).ok().and_then(|r| r.target())
+        };
+        let old = target(repo);
+        let result = change()?;
+        match target(repo) {
+            Some(new) if Some(new) != old => self.reference(name, old, new)?,
+            _ => {}
+        }
+        Ok(result)
+    }
+
+    /// Runs `change`, which moves HEAD, and records where HEAD was once it
+    /// has worked.
+    pub fn move_head(
+        &self,
+        repo: &Repository,
+        change: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        let head = repo.find_reference("HEAD")?;
+        let old = match (head.symbolic_target(), head.target()) {
+            (Some(name), _) => Some(name.to_string()),
+            (None, Some(commit)) => Some(commit.to_string()),
+            (None, None) => None,
+        };
+        change()?;
+        match old {
+            Some(old) => self.write(&Entry::Head { old }),
+            None => Ok(()),
+        }
+    }
+
+    pub fn pull_request(
+        &self,
+        repository: &GithubRepository,
+        pr: &PullRequest,
+    ) -> Result<()> {
+        self.write(&Entry::PullRequest {
+            host: repository.host.clone(),
+            owner: repository.owner.clone(),
+            repository: repository.name.clone(),
+            number: pr.number,
+            url: pr.html_url.clone(),
+        })
+    }
+
+    /// Pushes `branch` to `remote`. Where the remote branch was is asked of
+    /// the remote first, and the push is only recorded once it has worked, so
+    /// undoing it never deletes a branch that was there before.
+    pub fn push_branch(
+        &self,
+        repo: &Repository,
+        remote: &str,
+        branch: &str,
+    ) -> Result<()> {
+        let name = format!("refs/heads/{branch}");
+        let old = git::remote_ref(repo, remote, &name)?;
+        git::push_branch(repo, remote, branch)?;
+        self.write(&Entry::Push {
+            remote: remote.to_string(),
+            name,
+            old,
+        })
+    }
+
+    /// Pushes the reference `name` to `remote`, recording it the same way as
+    /// `push_branch`.
+    pub fn push_ref(
+        &self,
+        repo: &Repository,
+        remote: &str,
+        name: &str,
+    ) -> Result<()> {
+        let old = git::remote_ref(repo, remote, name)?;
+        git::push_ref(repo, remote, name)?;
+        self.write(&Entry::Push {
+            remote: remote.to_string(),
+            name: name.to_string(),
+            old,
+        })
+    }
+
+    fn write(&self, entry: &Entry) -> Result<()> {
+        if !self.begun.get() {
+            self.append(&Entry::Begin {
+                command: self.command.clone(),
+            })?;
+            self.begun.set(true);
+        }
+        self.append(entry)
+    }
+
+    fn append(&self, entry: &Entry) -> Result<()> {
+        debug!("Journal: {entry:?}");
+        let mut line = serde_json::to_string(entry)
+            .expect("journal entries always serialize");
+        line.push('\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+fn path(repo: &Repository) -> PathBuf {
+    repo.path().join("ghpr").join("journal")
+}
+
+/// The entries of the last operation in the journal, after its `Begin`, and
+/// the command it was for.
+pub fn last_operation(
+    repo: &Repository,
+) -> Result<Option<(String, Vec<Entry>)>> {
+    let entries = read(repo)?;
+    let start = match entries
+        .iter()
+        .rposition(|e| matches!(e, Entry::Begin { .. }))
+    {
+        Some(s) => s,
+        None => return Ok(None),
+    };
+    let command = match &entries[start] {
+        Entry::Begin { command } => command.clone(),
+        _ => unreachable!(),
+    };
+    Ok(Some((command, entries[start + 1..].to_vec())))
+}
+
+/// Replaces the entries of the last operation, `command`, with `remaining`,
+/// the ones still to be undone. With none remaining, the operation is removed
+/// from the journal.
+pub fn replace_last_operation(
+    repo: &Repository,
+    command: &str,
+    remaining: Vec<Entry>,
+) -> Result<()> {
+    let mut entries = read(repo)?;
+    let start = entries
+        .iter()
+        .rposition(|e| matches!(e, Entry::Begin { .. }))
+        .unwrap_or(0);
+    entries.truncate(start);
+    if !remaining.is_empty() {
+        entries.push(Entry::Begin {
+            command: command.to_string(),
+        });
+        entries.extend(remaining);
+    }
+    let mut file = File::create(path(repo))?;
+    for entry in entries {
+        let line = serde_json::to_string(&entry)
+            .expect("journal entries always serialize");
+        writeln!(file, "{line}")?;
+    }
+    Ok(())
+}
+
+fn read(repo: &Repository) -> Result<Vec<Entry>> {
+    let file = match File::open(path(repo)) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Vec::new())
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        // A line cut short by the operation being killed is of no use.
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => debug!("Skipping journal line {line:?}: {e}"),
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn reference(name: &str) -> Entry {
+        Entry::Reference {
+            name: name.to_string(),
+            old: None,
+            new: Oid::zero().to_string(),
+        }
+    }
+
+    #[test]
+    fn last_operation_read_back() {
+        let dir = tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        assert_that!(last_operation(&repo)).is_ok().is_none();
+
+        let first = Journal::begin(&repo, "create").unwrap();
+        first
+            .reference("refs/heads/first", None, Oid::zero())
+            .unwrap();
+        let second = Journal::begin(&repo, "create").unwrap();
+        second
+            .reference("refs/heads/second", None, Oid::zero())
+            .unwrap();
+
+        assert_that!(last_operation(&repo))
+            .is_ok()
+            .is_equal_to(Some((
+                "create".to_string(),
+                vec![reference("refs/heads/second")],
+            )));
+
+        replace_last_operation(&repo, "create", Vec::new()).unwrap();
+        assert_that!(last_operation(&repo))
+            .is_ok()
+            .is_equal_to(Some((
+                "create".to_string(),
+                vec![reference("refs/heads/first")],
+            )));
+
+        replace_last_operation(
+            &repo,
+            "create",
+            vec![reference("refs/heads/other")],
+        )
+        .unwrap();
+        assert_that!(last_operation(&repo))
+            .is_ok()
+            .is_equal_to(Some((
+                "create".to_string(),
+                vec![reference("refs/heads/other")],
+            )));
+
+        replace_last_operation(&repo, "create", Vec::new()).unwrap();
+        assert_that!(last_operation(&repo)).is_ok().is_none();
+    }
+
+    #[test]
+    fn operation_without_changes_not_written() {
+        let dir = tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let first = Journal::begin(&repo, "create").unwrap();
+        first
+            .reference("refs/heads/first", None, Oid::zero())
+            .unwrap();
+
+        let second = Journal::begin(&repo, "create").unwrap();
+
+        assert_that!(second.has_changes()).is_false();
+        assert_that!(last_operation(&repo))
+            .is_ok()
+            .is_equal_to(Some((
+                "create".to_string(),
+                vec![reference("refs/heads/first")],
+            )));
+    }
+
+    #[test]
+    fn partly_written_line_skipped() {
+        let dir = tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let journal = Journal::begin(&repo, "create").unwrap();
+        journal
+            .reference("refs/heads/first", None, Oid::zero())
+            .unwrap();
+        let mut file =
+            OpenOptions::new().append(true).open(path(&repo)).unwrap();
+        file.write_all(br#"{"type":"refer"#).unwrap();
+
+        assert_that!(last_operation(&repo))
+            .is_ok()
+            .is_equal_to(Some((
+                "create".to_string(),
+                vec![reference("refs/heads/first")],
+            )));
+    }
+}
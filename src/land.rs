@@ -0,0 +1,149 @@
+//! Lands the bottom pull request of a stack. It is merged on Github, the pull
+//! requests stacked on it are moved onto the main branch, and its branch is
+//! deleted, which is otherwise a long series of clicks on Github followed by
+//! a rebase.
+use std::collections::HashMap;
+
+use git2::{Branch, BranchType, Repository};
+use tracing::info;
+
+use crate::auth;
+use crate::common::{
+    branch_name, branches_with_open_pull_requests, get_branch_for_commit,
+    get_main_branch_commit, get_remote_branch_name, get_selected_commit,
+    main_branch_changes,
+};
+use crate::configuration::{Configuration, MergeMethod};
+use crate::git;
+use crate::github::Client;
+use crate::remote::get_github_repository;
+use crate::result::Error;
+use crate::result::Message;
+use crate::result::Result;
+use crate::stack;
+
+/// Lands the bottom pull request of the stack the selected commit is in.
+/// * Find the branch at the bottom of the stack, and its pull request.
+/// * Merge the pull request with `method`, as long as Github has the same
+///   commit for it as the local branch.
+/// * Fetch the main branch, and fast forward the local one.
+/// * Move the pull requests based on the merged branch onto the main branch.
+///   This has to happen before the branch is deleted, or Github closes them.
+/// * Delete the merged branch from the remote.
+/// * Rebase the rest of the stack with `git branchless sync`, if `sync` is set.
+pub async fn land(
+    options: &Configuration,
+    method: MergeMethod,
+    sync: bool,
+) -> Result<Message> {
+    info!("Opening the local git repository.");
+    let repo = Repository::discover(".")?;
+    let parameters = HashMap::new();
+
+    let current_commit = get_selected_commit(&repo)?;
+    let open_pull_requests =
+        branches_with_open_pull_requests(&repo, options).await?;
+    let current_branch = get_branch_for_commit(
+        &repo,
+        &current_commit,
+        &options.branch_name_template,
+        &parameters,
+        &open_pull_requests,
+    )?
+    .ok_or_else(|| Error::NoBranch(current_commit.id().to_string()))?;
+    let current_name = branch_name(&current_branch)?;
+
+    let upstream = main_branch_changes(&repo, &current_commit)?;
+    let ancestors = stack::ancestors(
+        &repo,
+        &current_name,
+        &upstream,
+        options,
+        &parameters,
+        &open_pull_requests,
+    )?;
+    let bottom_name = ancestors.first().cloned().unwrap_or(current_name);
+    let bottom = repo.find_branch(&bottom_name, BranchType::Local)?;
+    let remote_name = get_remote_branch_name(&repo, &bottom)?;
+    let descendants = stack::descendants(
+        &repo,
+        &bottom_name,
+        &upstream,
+        options,
+        &parameters,
+        &open_pull_requests,
+    )?;
+
+    let push_repository = get_github_repository(&repo, &options.push_remote)?;
+    let pr_repository = get_github_repository(&repo, &options.pr_remote)?;
+    let credential = auth::get_credential(options, &pr_repository.host).await?;
+    let client =
+        Client::new(&options.host(&pr_repository.host), &credential.token)?;
+
+    let head = format!("{}:{}", push_repository.owner, remote_name);
+    let pr = client
+        .find_pull_request(&pr_repository, &head)
+        .await?
+        .ok_or_else(|| Error::NoPullRequest(bottom_name.clone()))?;
+
+    let sha = bottom.get().peel_to_commit()?.id().to_string();
+    let merged = client
+        .merge_pull_request(&pr_repository, pr.number, method, &sha)
+        .await?;
+    info!("Merged pull request #{} as {}.", pr.number, merged.sha);
+
+    let (_, mut main_branch) = get_main_branch_commit(&repo)?;
+    let main_name = get_remote_branch_name(&repo, &main_branch)?;
+    git::fetch(&repo, &options.pr_remote, &main_name)?;
+    fast_forward(&repo, &mut main_branch, &options.pr_remote, &main_name)?;
+
+    let mut retargeted = Vec::new();
+    for child in client
+        .find_pull_requests_with_base(&pr_repository, &remote_name)
+        .await?
+    {
+        client
+            .update_pull_request_base(&pr_repository, child.number, &main_name)
+            .await?;
+        retargeted.push(child.html_url);
+    }
+
+    git::delete_remote_branch(&repo, &options.push_remote, &remote_name)?;
+
+    if sync && !descendants.is_empty() {
+        git::branchless_sync(&repo)?;
+    }
+
+    Ok(Message::Landed {
+        url: pr.html_url,
+        main: main_name,
+        retargeted,
+        restack: !sync && !descendants.is_empty(),
+    })
+}
+
+/// Moves the local main branch up to the one fetched from `remote`. It is left
+/// alone when it is checked out, or has commits of its own.
+fn fast_forward(
+    repo: &Repository,
+    main_branch: &mut Branch,
+    remote: &str,
+    name: &str,
+) -> Result<()> {
+    let fetched = repo
+        .find_reference(&format!("refs/remotes/{remote}/{name}"))?
+        .peel_to_commit()?;
+    let local = main_branch.get().peel_to_commit()?;
+    if local.id() == fetched.id()
+        || main_branch.is_head()
+        || !repo.graph_descendant_of(fetched.id(), local.id())?
+    {
+        return Ok(());
+    }
+
+    info!("Fast forwarding the main branch to {}.", fetched.id());
+    main_branch
+        .get_mut()
+        .set_target(fetched.id(), "git-ghpr land: fast forward")?;
+    Ok(())
+}
@@ -0,0 +1,237 @@
+//! Logs in to a Github host with the OAuth device flow, so nobody has to
+//! create a personal access token by hand. The user is shown a code to enter
+//! in the browser, while the token is polled for. The token is saved in
+//! `credentials.toml`, next to the configuration file, readable only by the
+//! user.
+//!
+//! https://docs.github.com/en/apps/oauth-apps/building-oauth-apps/authorizing-oauth-apps#device-flow
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use reqwest::header::{ACCEPT, USER_AGENT};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::auth::default_host;
+use crate::configuration::{config_dir, Configuration, Host};
+use crate::github::AGENT;
+use crate::result::Error;
+use crate::result::Message;
+use crate::result::Result;
+use crate::secret_file;
+
+const CREDENTIALS_FILE: &str = "credentials.toml";
+
+/// Enough to open pull requests in private repositories.
+const SCOPES: &str = "repo";
+
+const GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+#[derive(Debug, Deserialize)]
+struct DeviceCode {
+    device_code: String,
+    user_code: String,
+    verification_uri: String,
+    /// Seconds until the codes expire.
+    expires_in: u64,
+    /// The minimum number of seconds between polls for the token.
+    interval: u64,
+}
+
+/// The response to polling for the token. Until the user has entered the code
+/// there is an `error` instead of a token.
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+    /// Sent with `slow_down`, the interval to use from now on.
+    interval: Option<u64>,
+}
+
+/// The tokens saved by `auth login`, in a `[hosts."<host name>"]` table like
+/// the configuration file.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Credentials {
+    #[serde(default)]
+    hosts: BTreeMap<String, StoredHost>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct StoredHost {
+    token: String,
+}
+
+/// Runs the device flow for a host, and saves the token.
+pub async fn login(
+    options: &Configuration,
+    hostname: &Option<String>,
+) -> Result<Message> {
+    let name = match hostname {
+        Some(h) => h.to_lowercase(),
+        None => default_host(options),
+    };
+    let host = options.host(&name);
+    let client_id = host.client_id.clone().ok_or_else(|| {
+        Error::Login(format!(
+            "No OAuth App is configured for {name}. Register one on {name}, and set its `client_id` in the [hosts.\"{name}\"] section of the configuration file."
+        ))
+    })?;
+
+    let http = reqwest::Client::builder().build()?;
+    let code: DeviceCode = post(
+        &http,
+        &format!("{}/login/device/code", host.oauth_url),
+        &[("client_id", client_id.as_str()), ("scope", SCOPES)],
+    )
+    .await?;
+
+    eprintln!("First copy your one-time code: {}", code.user_code);
+    eprintln!(
+        "Then open {} in a browser to enter it.",
+        code.verification_uri
+    );
+
+    let token = poll_for_token(&http, &host, &client_id, &code).await?;
+    let path = store_token(&name, &token)?;
+
+    Ok(Message::LoggedIn { host: name, path })
+}
+
+/// Polls until the user has entered the code, they decline, or the code
+/// expires.
+async fn poll_for_token(
+    http: &reqwest::Client,
+    host: &Host,
+    client_id: &str,
+    code: &DeviceCode,
+) -> Result<String> {
+    let url = format!("{}/login/oauth/access_token", host.oauth_url);
+    let expires = Instant::now() + Duration::from_secs(code.expires_in);
+    let mut interval = code.interval;
+
+    loop {
+        tokio::time::sleep(Duration::from_secs(interval)).await;
+        if Instant::now() > expires {
+            return Err(Error::Login(
+                "The code expired before it was entered.".to_string(),
+            ));
+        }
+
+        let response: TokenResponse = post(
+            http,
+            &url,
+            &[
+                ("client_id", client_id),
+                ("device_code", code.device_code.as_str()),
+                ("grant_type", GRANT_TYPE),
+            ],
+        )
+        .await?;
+
+        if let Some(token) = response.access_token {
+            return Ok(token);
+        }
+        match response.error.as_deref() {
+            Some("authorization_pending") => {
+                debug!("Waiting for the code to be entered.");
+            }
+            Some("slow_down") => {
+                interval = response.interval.unwrap_or(interval + 5);
+                debug!("Polling every {interval} seconds.");
+            }
+            Some("expired_token") => {
+                return Err(Error::Login(
+                    "The code expired before it was entered.".to_string(),
+                ))
+            }
+            Some("access_denied") => {
+                return Err(Error::Login("Access was denied.".to_string()))
+            }
+            Some(e) => {
+                return Err(Error::Login(
+                    response.error_description.unwrap_or_else(|| e.to_string()),
+                ))
+            }
+            None => {
+                return Err(Error::Login(
+                    "Github responded without a token.".to_string(),
+                ))
+            }
+        }
+    }
+}
+
+async fn post<T: DeserializeOwned>(
+    http: &reqwest::Client,
+    url: &str,
+    form: &[(&str, &str)],
+) -> Result<T> {
+    let response = http
+        .post(url)
+        .header(ACCEPT, "application/json")
+        .header(USER_AGENT, AGENT)
+        .form(form)
+        .send()
+        .await?;
+    let status = response.status();
+    debug!("{url} responded with {status}.");
+
+    if !status.is_success() {
+        return Err(Error::GithubApi {
+            status: status.as_u16(),
+            message: response.text().await?,
+        });
+    }
+
+    Ok(response.json().await?)
+}
+
+fn credentials_path() -> Option<PathBuf> {
+    Some(config_dir()?.join(CREDENTIALS_FILE))
+}
+
+fn read_credentials(path: &Path) -> Credentials {
+    match fs::read_to_string(path) {
+        Ok(text) => toml::from_str(&text).unwrap_or_else(|e| {
+            debug!("Could not parse {:?}: {}", path, e);
+            Credentials::default()
+        }),
+        Err(e) => {
+            debug!("Could not read {:?}: {}", path, e);
+            Credentials::default()
+        }
+    }
+}
+
+/// Saves the token for a host, keeping the tokens of the other hosts.
+fn store_token(host: &str, token: &str) -> Result<PathBuf> {
+    let path = credentials_path().ok_or_else(|| {
+        Error::Login("Could not find the configuration directory.".to_string())
+    })?;
+
+    let mut credentials = read_credentials(&path);
+    credentials.hosts.insert(
+        host.to_string(),
+        StoredHost {
+            token: token.to_string(),
+        },
+    );
+    let text = toml::to_string(&credentials)
+        .map_err(|e| Error::Login(e.to_string()))?;
+    secret_file::write(&path, &text)?;
+
+    info!("Saved the token for {host} in {:?}.", path);
+    Ok(path)
+}
+
+/// The token saved by `auth login` for a host, and the file it is saved in.
+pub fn stored_token(host: &str) -> Option<(String, PathBuf)> {
+    let path = credentials_path()?;
+    let mut credentials = read_credentials(&path);
+    let stored = credentials.hosts.remove(host)?;
+    Some((stored.token, path))
+}
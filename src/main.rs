@@ -4,10 +4,38 @@ use crate::configuration::Commands;
 use crate::result::Message;
 use crate::result::Result;
 
+mod auth;
+mod auto_merge;
+mod checkout;
+mod cleanup;
+mod close;
+mod code_owners;
 mod common;
 mod configuration;
 mod create;
+mod date;
+mod editor;
+mod git;
+mod github;
+mod github_app;
+mod journal;
+mod land;
+mod login;
+mod notes;
+mod patch_id;
+mod pr_options;
+mod pr_template;
+mod prompt;
+mod range_diff;
+mod remote;
+mod repository_template;
 mod result;
+mod revisions;
+mod rewrite;
+mod secret_file;
+mod ssh_config;
+mod stack;
+mod undo;
 mod verbose;
 
 #[tokio::main]
@@ -25,7 +53,119 @@ async fn main() -> ExitCode {
     match execute(options).await {
         Ok(m) => {
             match m {
-                Message::Empty => (),
+                Message::AuthStatus {
+                    host,
+                    source,
+                    token,
+                } => {
+                    println!("{host}");
+                    println!("  Token: {token}");
+                    println!("  Source: {source}");
+                }
+                Message::LoggedIn { host, path } => {
+                    println!("Logged in to {host}.");
+                    println!("The token is saved in {}.", path.display());
+                }
+                Message::PullRequestCreated { url, warnings } => {
+                    println!("Created pull request {url}");
+                    for warning in warnings {
+                        eprintln!("Warning: {warning}");
+                    }
+                }
+                Message::PullRequestUpdated { url, warnings } => {
+                    println!("Updated pull request {url}");
+                    for warning in warnings {
+                        eprintln!("Warning: {warning}");
+                    }
+                }
+                Message::AutoMergeEnabled { url, queued: true } => {
+                    println!("Added pull request {url} to the merge queue")
+                }
+                Message::AutoMergeEnabled { url, queued: false } => {
+                    println!("Turned on auto-merge for pull request {url}")
+                }
+                Message::CleanedUp {
+                    dry_run,
+                    deleted,
+                    kept,
+                    retargeted,
+                } => {
+                    if deleted.is_empty() && kept.is_empty() {
+                        println!("There are no branches to clean up.");
+                    }
+                    let action =
+                        if dry_run { "Would delete" } else { "Deleted" };
+                    for f in deleted {
+                        println!("{action} {}: {}", f.branch, f.reason);
+                    }
+                    for f in kept {
+                        println!("Kept {}: {}", f.branch, f.reason);
+                    }
+                    for (url, base) in retargeted {
+                        println!("Moved pull request {url} onto {base}");
+                    }
+                }
+                Message::Landed {
+                    url,
+                    main,
+                    retargeted,
+                    restack,
+                } => {
+                    println!("Landed pull request {url}");
+                    for url in retargeted {
+                        println!("Moved pull request {url} onto {main}");
+                    }
+                    if restack {
+                        println!("Run `git branchless sync` to move the rest of the stack onto {main}.");
+                    }
+                }
+                Message::Revisions { pr, revisions } => {
+                    if revisions.is_empty() {
+                        println!("No revisions of pull request #{pr} have been pushed.");
+                    }
+                    for r in revisions {
+                        println!(
+                            "Revision {}: {:.7} pushed {}",
+                            r.number, r.commit, r.date
+                        );
+                    }
+                }
+                Message::RevisionDiff(diff) => print!("{diff}"),
+                Message::Undone {
+                    command,
+                    undone,
+                    kept,
+                } => {
+                    println!("Undid {command}.");
+                    for u in undone {
+                        println!("{u}");
+                    }
+                    for k in kept {
+                        eprintln!("Warning: {k}");
+                    }
+                }
+                Message::CheckedOut { branches } => {
+                    for (url, branch) in &branches {
+                        println!("Fetched pull request {url} into {branch}");
+                    }
+                    if let Some((_, top)) = branches.last() {
+                        println!("Checked out {top}");
+                    }
+                }
+                Message::Closed {
+                    url,
+                    base,
+                    retargeted,
+                    deleted,
+                } => {
+                    println!("Closed pull request {url}");
+                    for url in retargeted {
+                        println!("Moved pull request {url} onto {base}");
+                    }
+                    if let Some(branch) = deleted {
+                        println!("Deleted {branch}");
+                    }
+                }
             }
             ExitCode::SUCCESS
         }
@@ -37,15 +177,62 @@ async fn main() -> ExitCode {
 }
 
 async fn execute(options: configuration::Configuration) -> Result<Message> {
-    match options.command {
+    match &options.command {
         Commands::Create {
             branch_name_parameters,
+            template,
+            edit,
+            pull_request,
         } => {
             create::create_pull_request(
-                &options.branch_name_template,
-                &branch_name_parameters,
+                &options,
+                branch_name_parameters,
+                template.as_deref(),
+                *edit,
+                pull_request,
             )
             .await
         }
+        Commands::Automerge { method } => {
+            auto_merge::automerge(
+                &options,
+                method.unwrap_or(options.merge_method),
+            )
+            .await
+        }
+        Commands::Cleanup { dry_run } => {
+            cleanup::cleanup(&options, *dry_run).await
+        }
+        Commands::Land { method, sync } => {
+            land::land(&options, method.unwrap_or(options.merge_method), *sync)
+                .await
+        }
+        Commands::Undo { remote } => undo::undo(&options, *remote).await,
+        Commands::Revisions { pr, diff } => {
+            revisions::revisions(*pr, diff.as_ref().map(|d| (d[0], d[1])))
+        }
+        Commands::Checkout { pull_request } => {
+            checkout::checkout(&options, pull_request).await
+        }
+        Commands::Close {
+            commit,
+            branch,
+            comment,
+            delete_local,
+        } => {
+            let target = match (commit, branch) {
+                (Some(commit), _) => close::Target::Commit(commit),
+                (_, Some(branch)) => close::Target::Branch(branch),
+                (None, None) => close::Target::Selected,
+            };
+            close::close(&options, target, comment.as_deref(), *delete_local)
+                .await
+        }
+        Commands::AuthStatus { hostname } => {
+            auth::status(&options, hostname).await
+        }
+        Commands::AuthLogin { hostname } => {
+            login::login(&options, hostname).await
+        }
     }
 }
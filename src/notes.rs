@@ -0,0 +1,191 @@
+//! Records which pull request a commit was submitted for, in git notes under
+//! `refs/notes/ghpr`. Branches are deleted once they are merged, but the notes
+//! stay, so `git log --notes=ghpr` still shows the pull request of a commit.
+use std::fmt::{Display, Formatter};
+
+use git2::{Oid, Repository};
+use tracing::info;
+
+use crate::common::signature;
+use crate::configuration::Configuration;
+use crate::git;
+use crate::journal::Journal;
+use crate::result::Result;
+
+pub const NOTES_REF: &str = "refs/notes/ghpr";
+
+/// The pull request a commit was submitted for.
+#[derive(Debug, PartialEq)]
+pub struct Note {
+    pub number: u64,
+    pub url: String,
+    pub branch: String,
+}
+
+impl Display for Note {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        writeln!(f, "Pull-Request: #{}", self.number)?;
+        writeln!(f, "URL: {}", self.url)?;
+        writeln!(f, "Branch: {}", self.branch)
+    }
+}
+
+impl Note {
+    pub fn parse(text: &str) -> Option<Self> {
+        let field = |name: &str| {
+            text.lines()
+                .find_map(|l| l.strip_prefix(name)?.strip_prefix(": "))
+                .map(str::trim)
+        };
+        Some(Self {
+            number: field("Pull-Request")?.strip_prefix('#')?.parse().ok()?,
+            url: field("URL")?.to_string(),
+            branch: field("Branch")?.to_string(),
+        })
+    }
+}
+
+/// The pull request `commit` was submitted for, if it has a note.
+pub fn read(repo: &Repository, commit: Oid) -> Option<Note> {
+    let note = repo.find_note(Some(NOTES_REF), commit).ok()?;
+    Note::parse(note.message()?)
+}
+
+/// Adds `note` to `commit`, replacing any note it already has.
+pub fn write(repo: &Repository, commit: Oid, note: &Note) -> Result<()> {
+    info!("Noting pull request #{} on {commit}.", note.number);
+    let signature = signature(repo)?;
+    repo.note(
+        &signature,
+        &signature,
+        Some(NOTES_REF),
+        commit,
+        &note.to_string(),
+        true,
+    )?;
+    Ok(())
+}
+
+/// Makes git copy the notes to the new commit when a commit is amended or
+/// rebased, by adding `refs/notes/ghpr` to `notes.rewriteRef` in the
+/// repository's configuration.
+pub fn carry_across_rewrites(repo: &Repository) -> Result<()> {
+    let config = repo.config()?;
+    let mut configured = false;
+    {
+        let mut entries = config.multivar("notes.rewriteRef", None)?;
+        while let Some(entry) = entries.next() {
+            configured |= entry?.value() == Some(NOTES_REF);
+        }
+    }
+    if !configured {
+        info!("Adding {NOTES_REF} to notes.rewriteRef.");
+        config.open_level(git2::ConfigLevel::Local)?.set_multivar(
+            "notes.rewriteRef",
+            "^$",
+            NOTES_REF,
+        )?;
+    }
+    Ok(())
+}
+
+/// Notes `commit`, just pushed for pull request `number`, and shares the notes
+/// through the push remote if the configuration says to. Sharing going wrong
+/// doesn't lose the local note, so it is returned as a warning.
+pub fn record_pushed(
+    repo: &Repository,
+    journal: &Journal,
+    options: &Configuration,
+    commit: Oid,
+    note: &Note,
+) -> Result<Option<String>> {
+    if options.sync_notes {
+        if let Err(e) = git::fetch_notes(repo, &options.push_remote, NOTES_REF)
+        {
+            return Ok(Some(format!("Could not fetch the notes: {e}")));
+        }
+    }
+    journal.track(repo, NOTES_REF, || write(repo, commit, note))?;
+    carry_across_rewrites(repo)?;
+    if options.sync_notes {
+        if let Err(e) = journal.push_ref(repo, &options.push_remote, NOTES_REF)
+        {
+            return Ok(Some(format!("Could not push the notes: {e}")));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::Signature;
+    use speculoos::prelude::*;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn note() -> Note {
+        Note {
+            number: 8,
+            url: "https://github.com/owner/repo/pull/8".to_string(),
+            branch: "commit-2".to_string(),
+        }
+    }
+
+    #[test]
+    fn format() {
+        assert_that!(note().to_string()).is_equal_to(
+            r#"Pull-Request: #8
+URL: https://github.com/owner/repo/pull/8
+Branch: commit-2
+"#
+            .to_string(),
+        );
+    }
+
+    #[test]
+    fn parse() {
+        assert_that!(Note::parse(&note().to_string()))
+            .is_some()
+            .is_equal_to(note());
+        assert_that!(Note::parse("Something else\n")).is_none();
+    }
+
+    #[test]
+    fn write_and_read() {
+        let dir = tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let tree_id = repo.index().unwrap().write_tree().unwrap();
+        let tree = repo.find_tree(tree_id).unwrap();
+        let commit = repo
+            .commit(None, &signature, &signature, "Commit", &tree, &[])
+            .unwrap();
+
+        assert_that!(read(&repo, commit)).is_none();
+        write(&repo, commit, &note()).unwrap();
+        let mut updated = note();
+        updated.number = 9;
+        write(&repo, commit, &updated).unwrap();
+        assert_that!(read(&repo, commit))
+            .is_some()
+            .is_equal_to(updated);
+    }
+
+    #[test]
+    fn rewrite_ref_configured_once() {
+        let dir = tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+
+        carry_across_rewrites(&repo).unwrap();
+        carry_across_rewrites(&repo).unwrap();
+
+        let config = repo.config().unwrap().snapshot().unwrap();
+        let mut values = Vec::new();
+        let mut entries = config.multivar("notes.rewriteRef", None).unwrap();
+        while let Some(entry) = entries.next() {
+            values.push(entry.unwrap().value().unwrap().to_string());
+        }
+        assert_that!(values).is_equal_to(vec![NOTES_REF.to_string()]);
+    }
+}
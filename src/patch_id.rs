@@ -0,0 +1,195 @@
+//! Recognizes changes that reached the main branch as a different commit, the
+//! way `git cherry` does. Squash merging and rebase merging on Github both
+//! create new commits, so a branch that has been merged that way doesn't look
+//! merged when following the history. The patch ID of a change only depends
+//! on the diff, so it is the same for the new commit.
+use std::collections::HashMap;
+
+use git2::{Commit, Oid, Repository, Tree};
+use tracing::debug;
+
+use crate::result::Result;
+
+/// The patch ID of the changes from `from` to `to`. `None` when there are no
+/// changes.
+pub fn of_trees(
+    repo: &Repository,
+    from: Option<&Tree>,
+    to: &Tree,
+) -> Result<Option<Oid>> {
+    let diff = repo.diff_tree_to_tree(from, Some(to), None)?;
+    if diff.deltas().len() == 0 {
+        return Ok(None);
+    }
+    Ok(Some(diff.patchid(None)?))
+}
+
+/// The patch ID of the changes `commit` makes to its first parent.
+pub fn of_commit(repo: &Repository, commit: &Commit) -> Result<Option<Oid>> {
+    let parent_tree = match commit.parents().next() {
+        Some(p) => Some(p.tree()?),
+        None => None,
+    };
+    of_trees(repo, parent_tree.as_ref(), &commit.tree()?)
+}
+
+/// The changes made on the main branch since `base`, where a stack branched
+/// off it.
+pub struct Upstream<'a> {
+    base_tree: Tree<'a>,
+    /// The commits on the main branch, by patch ID.
+    commits: HashMap<Oid, Oid>,
+}
+
+impl<'a> Upstream<'a> {
+    pub fn new(repo: &'a Repository, main: Oid, base: Oid) -> Result<Self> {
+        let mut commits = HashMap::new();
+        let mut walk = repo.revwalk()?;
+        walk.push(main)?;
+        walk.hide(base)?;
+        for id in walk {
+            let commit = repo.find_commit(id?)?;
+            // Merge commits bring in changes from elsewhere, rather than
+            // making any of their own.
+            if commit.parent_count() > 1 {
+                continue;
+            }
+            if let Some(patch_id) = of_commit(repo, &commit)? {
+                commits.insert(patch_id, commit.id());
+            }
+        }
+        Ok(Self {
+            base_tree: repo.find_commit(base)?.tree()?,
+            commits,
+        })
+    }
+
+    /// The commit on the main branch with the same changes as `commit`. That
+    /// is either `commit` on its own, cherry picked or rebase merged, or
+    /// `commit` and all the commits below it since the base, squash merged.
+    pub fn find(
+        &self,
+        repo: &Repository,
+        commit: &Commit,
+    ) -> Result<Option<Oid>> {
+        if self.commits.is_empty() {
+            return Ok(None);
+        }
+        let squashed = of_trees(repo, Some(&self.base_tree), &commit.tree()?)?;
+        for patch_id in [of_commit(repo, commit)?, squashed].iter().flatten() {
+            if let Some(upstream) = self.commits.get(patch_id) {
+                debug!("{} is on the main branch as {upstream}.", commit.id());
+                return Ok(Some(*upstream));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Finds the commit on `main` with the same changes as all of the commits on
+/// `tip` since it branched off `main` together. Only commits on `main` since
+/// then are looked at.
+pub fn find_squash_merge(
+    repo: &Repository,
+    main: Oid,
+    tip: Oid,
+) -> Result<Option<Oid>> {
+    let base = repo.merge_base(main, tip)?;
+    let upstream = Upstream::new(repo, main, base)?;
+    upstream.find(repo, &repo.find_commit(tip)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::Signature;
+    use speculoos::prelude::*;
+    use tempfile::{tempdir, TempDir};
+
+    use super::*;
+
+    /// Commits `files`, all at the root of the repository, on top of
+    /// `parent`.
+    fn commit(
+        repo: &Repository,
+        parent: Option<Oid>,
+        files: &[(&str, &str)],
+    ) -> Oid {
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let parent = parent.map(|p| repo.find_commit(p).unwrap());
+        let parent_tree = parent.as_ref().map(|p| p.tree().unwrap());
+        let mut builder = repo.treebuilder(parent_tree.as_ref()).unwrap();
+        for (path, content) in files {
+            let blob = repo.blob(content.as_bytes()).unwrap();
+            builder.insert(path, blob, 0o100644).unwrap();
+        }
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let parents: Vec<_> = parent.iter().collect();
+        repo.commit(None, &signature, &signature, "Commit", &tree, &parents)
+            .unwrap()
+    }
+
+    fn init() -> (TempDir, Repository, Oid) {
+        let dir = tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let root = commit(&repo, None, &[]);
+        (dir, repo, root)
+    }
+
+    #[test]
+    fn squash_merged() {
+        let (_dir, repo, root) = init();
+        let first = commit(&repo, Some(root), &[("a.txt", "A\n")]);
+        let second = commit(&repo, Some(first), &[("b.txt", "B\n")]);
+        let other = commit(&repo, Some(root), &[("c.txt", "C\n")]);
+        let squashed =
+            commit(&repo, Some(other), &[("a.txt", "A\n"), ("b.txt", "B\n")]);
+        let main = commit(&repo, Some(squashed), &[("d.txt", "D\n")]);
+
+        assert_that!(find_squash_merge(&repo, main, second))
+            .is_ok()
+            .is_equal_to(Some(squashed));
+    }
+
+    #[test]
+    fn not_merged() {
+        let (_dir, repo, root) = init();
+        let change = commit(&repo, Some(root), &[("a.txt", "A\n")]);
+        let main = commit(&repo, Some(root), &[("a.txt", "Different\n")]);
+
+        assert_that!(find_squash_merge(&repo, main, change))
+            .is_ok()
+            .is_none();
+    }
+
+    #[test]
+    fn rebase_merged() {
+        let (_dir, repo, root) = init();
+        let first = commit(&repo, Some(root), &[("a.txt", "A\n")]);
+        let second = commit(&repo, Some(first), &[("b.txt", "B\n")]);
+        let other = commit(&repo, Some(root), &[("c.txt", "C\n")]);
+        let rebased = commit(&repo, Some(other), &[("a.txt", "A\n")]);
+
+        let upstream = Upstream::new(&repo, rebased, root).unwrap();
+        let first = repo.find_commit(first).unwrap();
+        let second = repo.find_commit(second).unwrap();
+        assert_that!(upstream.find(&repo, &first))
+            .is_ok()
+            .is_equal_to(Some(rebased));
+        assert_that!(upstream.find(&repo, &second))
+            .is_ok()
+            .is_none();
+    }
+
+    #[test]
+    fn same_patch_for_cherry_pick() {
+        let (_dir, repo, root) = init();
+        let other = commit(&repo, Some(root), &[("b.txt", "B\n")]);
+        let change = commit(&repo, Some(root), &[("a.txt", "A\n")]);
+        let picked = commit(&repo, Some(other), &[("a.txt", "A\n")]);
+
+        let change = repo.find_commit(change).unwrap();
+        let picked = repo.find_commit(picked).unwrap();
+        assert_that!(of_commit(&repo, &change).unwrap())
+            .is_equal_to(of_commit(&repo, &picked).unwrap());
+    }
+}
@@ -0,0 +1,145 @@
+//! Sets the reviewers, labels, assignees and milestone of a pull request once
+//! it has been created. Github has separate requests for each of them, and
+//! opening the pull request matters more than any of them, so when one can't
+//! be set it is reported as a warning rather than an error.
+use tracing::info;
+
+use crate::configuration::PullRequestOptions;
+use crate::github::Client;
+use crate::remote::GithubRepository;
+use crate::result::Result;
+
+/// Applies `options` to pull request `number`, returning a warning for each
+/// thing that couldn't be set.
+pub async fn apply(
+    client: &Client,
+    repo: &GithubRepository,
+    number: u64,
+    options: &PullRequestOptions,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    // Reviewers are requested one at a time, since Github rejects the whole
+    // request when any of them can't review.
+    for reviewer in &options.reviewers {
+        let (slug, team) = reviewer_slug(reviewer);
+        if let Err(e) = client.request_review(repo, number, slug, team).await {
+            warnings.push(format!(
+                "Could not request a review from {reviewer}: {e}"
+            ));
+        }
+    }
+
+    if let Err(e) =
+        add_labels(client, repo, number, options, &mut warnings).await
+    {
+        warnings.push(format!("Could not add the labels: {e}"));
+    }
+
+    if let Err(e) =
+        add_assignees(client, repo, number, options, &mut warnings).await
+    {
+        warnings.push(format!("Could not add the assignees: {e}"));
+    }
+
+    if let Some(milestone) = &options.milestone {
+        if let Err(e) =
+            set_milestone(client, repo, number, milestone, &mut warnings).await
+        {
+            warnings.push(format!("Could not set the milestone: {e}"));
+        }
+    }
+
+    warnings
+}
+
+/// Splits a team, given as `org/team`, into its slug. Anything else is a user.
+fn reviewer_slug(reviewer: &str) -> (&str, bool) {
+    match reviewer.split_once('/') {
+        Some((_, team)) => (team, true),
+        None => (reviewer, false),
+    }
+}
+
+async fn add_labels(
+    client: &Client,
+    repo: &GithubRepository,
+    number: u64,
+    options: &PullRequestOptions,
+    warnings: &mut Vec<String>,
+) -> Result<()> {
+    let mut labels = Vec::new();
+    for label in &options.labels {
+        if client.has_label(repo, label).await? {
+            labels.push(label.clone());
+        } else {
+            warnings.push(format!("There is no label {label} in {repo}."));
+        }
+    }
+    if !labels.is_empty() {
+        client.add_labels(repo, number, &labels).await?;
+    }
+    Ok(())
+}
+
+async fn add_assignees(
+    client: &Client,
+    repo: &GithubRepository,
+    number: u64,
+    options: &PullRequestOptions,
+    warnings: &mut Vec<String>,
+) -> Result<()> {
+    let mut assignees = Vec::new();
+    for assignee in &options.assignees {
+        if client.is_assignable(repo, assignee).await? {
+            assignees.push(assignee.clone());
+        } else {
+            warnings.push(format!("{assignee} can't be assigned in {repo}."));
+        }
+    }
+    if !assignees.is_empty() {
+        client.add_assignees(repo, number, &assignees).await?;
+    }
+    Ok(())
+}
+
+/// Sets the milestone with the title `milestone`, or failing that, the number.
+async fn set_milestone(
+    client: &Client,
+    repo: &GithubRepository,
+    number: u64,
+    milestone: &str,
+    warnings: &mut Vec<String>,
+) -> Result<()> {
+    let milestones = client.list_open_milestones(repo).await?;
+    let by_title = milestones.iter().find(|m| m.title == milestone);
+    let by_number = || {
+        let n = milestone.parse::<u64>().ok()?;
+        milestones.iter().find(|m| m.number == n)
+    };
+    match by_title.or_else(by_number) {
+        Some(m) => {
+            info!("Milestone {milestone} is number {}.", m.number);
+            client.set_milestone(repo, number, m.number).await
+        }
+        None => {
+            warnings.push(format!(
+                "There is no open milestone {milestone} in {repo}."
+            ));
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn reviewers() {
+        assert_that!(reviewer_slug("octocat")).is_equal_to(("octocat", false));
+        assert_that!(reviewer_slug("github/docs")).is_equal_to(("docs", true));
+    }
+}
@@ -0,0 +1,250 @@
+//! Renders the title and body of a pull request from Tera templates, the same
+//! way branch names are generated. The templates have the commit, the branches
+//! and where the pull request sits in its stack available to them:
+//! - `summary`, `body` and `sha` of the commit
+//! - `branch` and `base`, the branch with the changes and the one they are to
+//!   be merged into
+//! - `stack.position`, 1 for a pull request directly on the main branch
+//! - `stack.parent`, the pull request for `base`, if there is one
+//! - `stack.children`, the open pull requests based on `branch`
+//!
+//! Pull requests are available as `number` and `url`. The branch name
+//! parameters, like `jira`, are available too.
+use std::collections::HashMap;
+use std::path::Path;
+
+use git2::Commit;
+use serde::Serialize;
+use tera::Tera;
+
+use crate::configuration::Template;
+use crate::github::PullRequest;
+use crate::result::Error;
+use crate::result::Result;
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PullRequestLink {
+    pub number: u64,
+    pub url: String,
+}
+
+impl From<&PullRequest> for PullRequestLink {
+    fn from(pr: &PullRequest) -> Self {
+        Self {
+            number: pr.number,
+            url: pr.html_url.clone(),
+        }
+    }
+}
+
+/// Where a pull request sits in a stack of pull requests.
+#[derive(Debug, Serialize)]
+pub struct Stack {
+    pub position: usize,
+    pub parent: Option<PullRequestLink>,
+    pub children: Vec<PullRequestLink>,
+}
+
+/// Everything the templates can refer to.
+pub struct PullRequestContext<'a> {
+    pub commit: &'a Commit<'a>,
+    pub branch: &'a str,
+    pub base: &'a str,
+    pub parameters: &'a HashMap<String, String>,
+    pub stack: &'a Stack,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PullRequestText {
+    pub title: String,
+    pub body: String,
+}
+
+/// Renders the title and body templates. Template files are found relative to
+/// `root`, the root of the repository.
+pub fn render(
+    title_template: &Template,
+    body_template: &Template,
+    root: &Path,
+    context: &PullRequestContext,
+) -> Result<PullRequestText> {
+    let context = tera_context(context);
+    Ok(PullRequestText {
+        title: render_one(title_template, root, &context)?
+            .trim()
+            .to_string(),
+        body: render_one(body_template, root, &context)?
+            .trim()
+            .to_string(),
+    })
+}
+
+fn tera_context(context: &PullRequestContext) -> tera::Context {
+    let mut tera_context = tera::Context::new();
+    tera_context
+        .insert("summary", context.commit.summary().unwrap_or_default());
+    tera_context.insert("body", context.commit.body().unwrap_or_default());
+    tera_context.insert("sha", &context.commit.id().to_string());
+    tera_context.insert("branch", context.branch);
+    tera_context.insert("base", context.base);
+    tera_context.insert("stack", context.stack);
+    for (k, v) in context.parameters {
+        tera_context.insert(k, &v);
+    }
+    tera_context
+}
+
+fn render_one(
+    template: &Template,
+    root: &Path,
+    context: &tera::Context,
+) -> Result<String> {
+    let text = match template {
+        Template::Inline(t) => t.clone(),
+        Template::File(path) => {
+            let path = root.join(path);
+            std::fs::read_to_string(&path).map_err(|e| {
+                Error::PullRequestTemplateMalformed(format!(
+                    "{}: {e}",
+                    path.display()
+                ))
+            })?
+        }
+    };
+
+    // Pull request bodies are Markdown, not HTML, so nothing is escaped.
+    Tera::one_off(&text, context, false)
+        .map_err(|e| Error::PullRequestTemplateMalformed(describe(&e)))
+}
+
+/// Tera puts the useful part of the message, like which variable is missing,
+/// in the source of the error.
+fn describe(e: &tera::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = std::error::Error::source(e);
+    while let Some(s) = source {
+        message = format!("{message}: {s}");
+        source = s.source();
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::{Repository, Signature};
+    use speculoos::prelude::*;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn render_for_message(
+        title: &str,
+        body: &str,
+        message: &str,
+        stack: &Stack,
+    ) -> Result<PullRequestText> {
+        let dir = tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let tree_id = repo.index().unwrap().write_tree().unwrap();
+        let tree = repo.find_tree(tree_id).unwrap();
+        let id = repo
+            .commit(None, &signature, &signature, message, &tree, &[])
+            .unwrap();
+        let commit = repo.find_commit(id).unwrap();
+
+        render(
+            &Template::Inline(title.to_string()),
+            &Template::Inline(body.to_string()),
+            dir.path(),
+            &PullRequestContext {
+                commit: &commit,
+                branch: "add-things",
+                base: "main",
+                parameters: &HashMap::from([(
+                    "jira".to_string(),
+                    "ABC-123".to_string(),
+                )]),
+                stack,
+            },
+        )
+    }
+
+    fn bottom_of_stack() -> Stack {
+        Stack {
+            position: 1,
+            parent: None,
+            children: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let text = render_for_message(
+            "{{ summary }}",
+            "{{ body }}",
+            "Add things.\n\nThe things are <b>needed</b>.\n",
+            &bottom_of_stack(),
+        );
+
+        assert_that!(text).is_ok().is_equal_to(PullRequestText {
+            title: "Add things.".to_string(),
+            body: "The things are <b>needed</b>.".to_string(),
+        });
+    }
+
+    #[test]
+    fn parameters() {
+        let text = render_for_message(
+            "[{{ jira }}] {{ summary }}",
+            "Merging {{ branch }} into {{ base }}.",
+            "Add things.",
+            &bottom_of_stack(),
+        );
+
+        assert_that!(text).is_ok().is_equal_to(PullRequestText {
+            title: "[ABC-123] Add things.".to_string(),
+            body: "Merging add-things into main.".to_string(),
+        });
+    }
+
+    #[test]
+    fn stack() {
+        let stack = Stack {
+            position: 2,
+            parent: Some(PullRequestLink {
+                number: 5,
+                url: "https://github.com/owner/repo/pull/5".to_string(),
+            }),
+            children: vec![PullRequestLink {
+                number: 7,
+                url: "https://github.com/owner/repo/pull/7".to_string(),
+            }],
+        };
+        let body = r#"Position {{ stack.position }}.
+{% if stack.parent %}Depends on #{{ stack.parent.number }}.{% endif %}
+{% for child in stack.children %}Required by {{ child.url }}.{% endfor %}"#;
+
+        let text =
+            render_for_message("{{ summary }}", body, "Add things.", &stack);
+
+        assert_that!(text).is_ok().is_equal_to(PullRequestText {
+            title: "Add things.".to_string(),
+            body: "Position 2.\nDepends on #5.\nRequired by https://github.com/owner/repo/pull/7.".to_string(),
+        });
+    }
+
+    #[test]
+    fn missing_variable() {
+        let text = render_for_message(
+            "{{ ticket }}",
+            "{{ body }}",
+            "Add things.",
+            &bottom_of_stack(),
+        );
+
+        assert_that!(text.map_err(|e| e.to_string()))
+            .is_err()
+            .contains("ticket");
+    }
+}
@@ -0,0 +1,43 @@
+//! Simple interactive prompts, used when a decision can't be made from the
+//! state of the repository alone.
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use crate::result::Error;
+use crate::result::Result;
+
+/// Whether there is a user at a terminal who can answer questions. Both stdin
+/// and stderr have to be terminals, since the question is written to stderr.
+pub fn is_interactive() -> bool {
+    io::stdin().is_terminal() && io::stderr().is_terminal()
+}
+
+/// Asks the user to pick one of the `options`, returning the index of the one
+/// selected. Keeps asking until a valid selection is made, or stdin is closed.
+pub fn choose(question: &str, options: &[String]) -> Result<usize> {
+    let mut stderr = io::stderr();
+    let mut stdin = io::stdin().lock();
+
+    writeln!(stderr, "{question}")?;
+    for (i, option) in options.iter().enumerate() {
+        writeln!(stderr, "  {}) {}", i + 1, option)?;
+    }
+
+    loop {
+        write!(stderr, "Select [1-{}]: ", options.len())?;
+        stderr.flush()?;
+
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            return Err(Error::Aborted);
+        }
+
+        match line.trim().parse::<usize>() {
+            Ok(n) if 1 <= n && n <= options.len() => return Ok(n - 1),
+            _ => writeln!(
+                stderr,
+                "Please enter a number between 1 and {}.",
+                options.len()
+            )?,
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Shows reviewers what changed when the commits of a pull request are
+//! rewritten and force pushed. Github only shows the new commits, so without
+//! this the whole pull request has to be read again.
+use git2::{Oid, Repository};
+
+use crate::git;
+
+/// The comment to add to a pull request whose head moved from `old` to `new`,
+/// with `base` as the commit below its commits now. `None` when the head
+/// hasn't moved. The comment compares the commits with `git range-diff`, with
+/// the same number of commits below `old` as there are now.
+pub fn comment(
+    repo: &Repository,
+    old: &str,
+    new: Oid,
+    base: Oid,
+) -> Result<Option<String>, String> {
+    let old = match Oid::from_str(old) {
+        Ok(id) if id == new => return Ok(None),
+        Ok(id) => id,
+        // Github didn't say what the head was.
+        Err(_) => return Ok(None),
+    };
+    let old_commit = repo
+        .find_commit(old)
+        .map_err(|_| format!("{old} isn't in the local repository"))?;
+
+    let mut walk = repo.revwalk().map_err(|e| e.to_string())?;
+    walk.push(new).map_err(|e| e.to_string())?;
+    walk.hide(base).map_err(|e| e.to_string())?;
+    let count = walk.count();
+
+    let mut old_base = old_commit;
+    for _ in 0..count {
+        old_base = old_base
+            .parent(0)
+            .map_err(|_| format!("{old} has fewer than {count} commits"))?;
+    }
+
+    let range_diff = git::range_diff(
+        repo,
+        &format!("{}..{old}", old_base.id()),
+        &format!("{base}..{new}"),
+    )?;
+    Ok(Some(format_comment(
+        &old.to_string(),
+        &new.to_string(),
+        &range_diff,
+    )))
+}
+
+fn format_comment(old: &str, new: &str, range_diff: &str) -> String {
+    format!(
+        "<details>\n<summary>Changes from {} to {}</summary>\n\n```diff\n{}\n```\n\n</details>\n",
+        &old[..7],
+        &new[..7],
+        range_diff.trim_end()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn collapsible_comment() {
+        let range_diff = "1:  1111111 ! 1:  2222222 Commit 2.\n    @@ file1.txt\n    -More text\n    +Changed text\n";
+        assert_that!(format_comment(
+            "1111111111111111111111111111111111111111",
+            "2222222222222222222222222222222222222222",
+            range_diff
+        ))
+        .is_equal_to(
+            r#"<details>
+<summary>Changes from 1111111 to 2222222</summary>
+
+```diff
+1:  1111111 ! 1:  2222222 Commit 2.
+    @@ file1.txt
+    -More text
+    +Changed text
+```
+
+</details>
+"#
+            .to_string(),
+        );
+    }
+}
@@ -0,0 +1,317 @@
+//! Finds the pull request templates a repository has for Github, and merges
+//! the pull request body into them, so pull requests created here get the same
+//! checklists as ones created on Github.
+//!
+//! Github looks for a single `PULL_REQUEST_TEMPLATE.md`, or a
+//! `PULL_REQUEST_TEMPLATE` directory of them, in `.github`, the root of the
+//! repository, and `docs`, in that order. Names are not case sensitive.
+//!
+//! https://docs.github.com/en/communities/using-templates-to-encourage-useful-issues-and-pull-requests/creating-a-pull-request-template-for-your-repository
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, info, warn};
+
+use crate::prompt;
+use crate::result::Error;
+use crate::result::Result;
+
+const LOCATIONS: [&str; 3] = [".github", "", "docs"];
+
+const NAME: &str = "pull_request_template";
+
+/// The pull request templates in a repository.
+#[derive(Debug, Default, PartialEq)]
+struct Templates {
+    /// The `PULL_REQUEST_TEMPLATE.md` file.
+    default: Option<PathBuf>,
+    /// The files in the `PULL_REQUEST_TEMPLATE` directory, by file name,
+    /// sorted.
+    named: Vec<(String, PathBuf)>,
+}
+
+/// Reads the template to use for a new pull request. `name` picks one of the
+/// templates in a `PULL_REQUEST_TEMPLATE` directory. Without a name the single
+/// `PULL_REQUEST_TEMPLATE.md` is used, and failing that the user is asked to
+/// pick one.
+pub fn read(root: &Path, name: Option<&str>) -> Result<Option<String>> {
+    let templates = find(root);
+
+    let path = match name {
+        Some(name) => Some(select_named(&templates, name)?),
+        None => match templates.default {
+            Some(path) => Some(path),
+            None => choose(&templates)?,
+        },
+    };
+
+    match path {
+        Some(path) => {
+            info!("Using the pull request template {:?}.", path);
+            Ok(Some(fs::read_to_string(path)?))
+        }
+        None => Ok(None),
+    }
+}
+
+fn select_named(templates: &Templates, name: &str) -> Result<PathBuf> {
+    let wanted = name.to_lowercase();
+    templates
+        .named
+        .iter()
+        .find(|(file_name, _)| {
+            let file_name = file_name.to_lowercase();
+            file_name == wanted
+                || file_name.strip_suffix(".md") == Some(&wanted)
+        })
+        .map(|(_, path)| path.clone())
+        .ok_or_else(|| Error::UnknownPullRequestTemplate {
+            name: name.to_string(),
+            available: templates
+                .named
+                .iter()
+                .map(|(file_name, _)| file_name.clone())
+                .collect(),
+        })
+}
+
+/// Asks the user which template to use, when there are several and none is
+/// the default. When nobody is there to ask, none is used.
+fn choose(templates: &Templates) -> Result<Option<PathBuf>> {
+    match templates.named.len() {
+        0 => Ok(None),
+        1 => Ok(Some(templates.named[0].1.clone())),
+        _ if !prompt::is_interactive() => {
+            warn!("There are several pull request templates, and none was picked with --template.");
+            Ok(None)
+        }
+        _ => {
+            let names: Vec<String> =
+                templates.named.iter().map(|(n, _)| n.clone()).collect();
+            let selected = prompt::choose(
+                "Which pull request template should be used?",
+                &names,
+            )?;
+            Ok(Some(templates.named[selected].1.clone()))
+        }
+    }
+}
+
+/// Looks in each of the locations Github does, and stops at the first that has
+/// any templates.
+fn find(root: &Path) -> Templates {
+    for location in LOCATIONS {
+        let dir = root.join(location);
+        let entries = match fs::read_dir(&dir) {
+            Ok(e) => e,
+            Err(e) => {
+                debug!("Could not read {:?}: {}", dir, e);
+                continue;
+            }
+        };
+
+        let mut templates = Templates::default();
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_lowercase();
+            let path = entry.path();
+            if path.is_file()
+                && (file_name == format!("{NAME}.md")
+                    || file_name == format!("{NAME}.txt"))
+            {
+                templates.default = Some(path);
+            } else if path.is_dir() && file_name == NAME {
+                templates.named = find_named(&path);
+            }
+        }
+
+        if templates != Templates::default() {
+            return templates;
+        }
+    }
+    Templates::default()
+}
+
+fn find_named(dir: &Path) -> Vec<(String, PathBuf)> {
+    let mut named: Vec<(String, PathBuf)> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.is_file())
+            .filter_map(|p| {
+                let name = p.file_name()?.to_string_lossy().to_string();
+                Some((name, p))
+            })
+            .collect(),
+        Err(e) => {
+            debug!("Could not read {:?}: {}", dir, e);
+            Vec::new()
+        }
+    };
+    named.sort();
+    named
+}
+
+/// Puts `body` at the start of the section under the heading `section`. When
+/// the template has no such heading, `body` goes at the top, above the rest of
+/// the template.
+pub fn merge(template: &str, body: &str, section: &str) -> String {
+    if body.is_empty() {
+        return template.to_string();
+    }
+
+    let lines: Vec<&str> = template.lines().collect();
+    let heading = lines.iter().position(|l| is_heading(l, section));
+
+    match heading {
+        Some(i) => {
+            let mut merged = lines[..=i].join("\n");
+            merged.push_str("\n\n");
+            merged.push_str(body);
+            merged.push('\n');
+            let rest = lines[i + 1..].join("\n");
+            if !rest.trim().is_empty() {
+                merged.push('\n');
+                merged.push_str(rest.trim_start_matches('\n'));
+                merged.push('\n');
+            }
+            merged
+        }
+        None => format!("{body}\n\n{template}"),
+    }
+}
+
+/// Whether `line` is a Markdown heading with the text `section`, at any level.
+fn is_heading(line: &str, section: &str) -> bool {
+    let text = line.trim_start_matches('#');
+    text.len() < line.len()
+        && text.starts_with(' ')
+        && text.trim().eq_ignore_ascii_case(section.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    const TEMPLATE: &str = r#"## Description
+
+<!-- What does this change, and why? -->
+
+## Checklist
+
+- [ ] Tests added
+"#;
+
+    #[test]
+    fn merge_into_section() {
+        assert_that!(merge(TEMPLATE, "Adds things.", "Description"))
+            .is_equal_to(
+                r#"## Description
+
+Adds things.
+
+<!-- What does this change, and why? -->
+
+## Checklist
+
+- [ ] Tests added
+"#
+                .to_string(),
+            );
+    }
+
+    #[test]
+    fn merge_into_other_section() {
+        assert_that!(merge(TEMPLATE, "Adds things.", "checklist")).is_equal_to(
+            r#"## Description
+
+<!-- What does this change, and why? -->
+
+## Checklist
+
+Adds things.
+
+- [ ] Tests added
+"#
+            .to_string(),
+        );
+    }
+
+    #[test]
+    fn merge_into_last_section() {
+        assert_that!(merge("# Summary\n", "Adds things.", "Summary"))
+            .is_equal_to("# Summary\n\nAdds things.\n".to_string());
+    }
+
+    #[test]
+    fn merge_without_section() {
+        assert_that!(merge(TEMPLATE, "Adds things.", "Motivation"))
+            .is_equal_to(format!("Adds things.\n\n{TEMPLATE}"));
+    }
+
+    #[test]
+    fn merge_empty_body() {
+        assert_that!(merge(TEMPLATE, "", "Description"))
+            .is_equal_to(TEMPLATE.to_string());
+    }
+
+    #[test]
+    fn headings() {
+        assert_that!(is_heading("## Description", "Description")).is_true();
+        assert_that!(is_heading("# description ", "Description")).is_true();
+        assert_that!(is_heading("Description", "Description")).is_false();
+        assert_that!(is_heading("##Description", "Description")).is_false();
+        assert_that!(is_heading("## Descriptions", "Description")).is_false();
+    }
+
+    #[test]
+    fn find_default_template() {
+        let root = tempdir().unwrap();
+        fs::create_dir(root.path().join("docs")).unwrap();
+        fs::write(root.path().join("docs/pull_request_template.md"), "")
+            .unwrap();
+        fs::create_dir(root.path().join(".github")).unwrap();
+        fs::write(root.path().join(".github/PULL_REQUEST_TEMPLATE.md"), "")
+            .unwrap();
+
+        let templates = find(root.path());
+
+        assert_that!(templates.default).is_equal_to(Some(
+            root.path().join(".github/PULL_REQUEST_TEMPLATE.md"),
+        ));
+        assert_that!(templates.named).is_empty();
+    }
+
+    #[test]
+    fn find_named_templates() {
+        let root = tempdir().unwrap();
+        let dir = root.path().join("PULL_REQUEST_TEMPLATE");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("feature.md"), "").unwrap();
+        fs::write(dir.join("bugfix.md"), "").unwrap();
+
+        let templates = find(root.path());
+
+        assert_that!(templates.default).is_none();
+        assert_that!(templates.named).is_equal_to(vec![
+            ("bugfix.md".to_string(), dir.join("bugfix.md")),
+            ("feature.md".to_string(), dir.join("feature.md")),
+        ]);
+        assert_that!(select_named(&templates, "Feature"))
+            .is_ok()
+            .is_equal_to(dir.join("feature.md"));
+        assert_that!(select_named(&templates, "bugfix.md"))
+            .is_ok()
+            .is_equal_to(dir.join("bugfix.md"));
+        assert_that!(select_named(&templates, "docs")).is_err();
+    }
+
+    #[test]
+    fn find_no_templates() {
+        let root = tempdir().unwrap();
+
+        assert_that!(find(root.path())).is_equal_to(Templates::default());
+    }
+}
@@ -1,41 +1,165 @@
 use std::fmt::Formatter;
+use std::path::PathBuf;
 
 use figment::error::Kind;
 use git2::ErrorClass;
 use git2::ErrorCode;
 
+use crate::cleanup::Finished;
+use crate::revisions::Revision;
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
+    /// The user declined to continue when asked.
+    Aborted,
+    /// More than one branch points at a commit and there was no way to decide
+    /// which one should be used for the pull request.
+    AmbiguousBranch {
+        commit: String,
+        branches: Vec<String>,
+    },
+    /// The repository doesn't allow auto-merge, and has no merge queue.
+    AutoMergeNotAllowed(String),
     BadParameter(String),
+    /// The branch is checked out, so it can't be deleted.
+    BranchCheckedOut(String),
     BranchTemplateMalformed(String),
+    CheckoutFailed {
+        branch: String,
+        message: String,
+    },
+    DeleteBranchFailed {
+        branch: String,
+        remote: String,
+        message: String,
+    },
+    DiffFailed {
+        from: String,
+        to: String,
+        message: String,
+    },
+    /// The editor for the pull request message couldn't be run.
+    EditorFailed(String),
+    /// The pull request message was emptied in the editor.
+    EmptyPullRequestMessage,
+    FetchFailed {
+        branch: String,
+        remote: String,
+        message: String,
+    },
     Generic,
+    /// A git hook run after rewriting commits failed.
+    HookFailed {
+        hook: String,
+        message: String,
+    },
+    /// Authenticating as a Github App failed, or it isn't configured properly.
+    GithubApp(String),
+    /// Github responded to a request with an error.
+    GithubApi {
+        status: u16,
+        message: String,
+    },
+    /// Github responded to a GraphQL request with errors.
+    GithubGraphql(String),
+    /// A request to Github could not be made, or the response not understood.
+    GithubRequest(String),
+    /// The operation was stopped with Ctrl-C.
+    Interrupted,
+    Io(std::io::Error),
+    /// Logging in with the OAuth device flow failed.
+    Login(String),
     MissingBranchParameter(String),
     MultipleParentCommits(String),
     NoBaseBranch,
+    /// The selected commit has no branch, so it can't have a pull request.
+    NoBranch(String),
     NoCommitMessage,
     NoRepository,
     /// Indicates that the local repository does not have a remote associated
     /// with it.
     NoRemoteRepository,
     NoRemoteBranch(String),
+    NoPullRequest(String),
     NoSelectedCommit,
+    /// The journal has no operation in it to undo.
+    NothingToUndo,
+    /// There is no token available to authenticate with the Github host.
+    NoToken(String),
+    NotGithubRemote {
+        remote: String,
+        url: String,
+    },
+    /// A pull request title or body template could not be read or rendered.
+    PullRequestTemplateMalformed(String),
+    PushFailed {
+        branch: String,
+        remote: String,
+        message: String,
+    },
+    /// An operation failed with `error`, and its local changes were rolled
+    /// back. When `remote`, it had pushed or opened pull requests already.
+    RolledBack {
+        error: Box<Error>,
+        remote: bool,
+    },
+    /// Running `git branchless sync` failed.
+    SyncFailed(String),
     UnableToCreateBranch {
         branch_name: String,
         base_commit: String,
     },
     UnableToSelectBranch(String),
+    UnknownBranch(String),
+    UnknownRevision {
+        pr: u64,
+        revision: u32,
+    },
+    /// A revision given on the command line doesn't name a commit.
+    UnknownCommit(String),
     UnknownMainBranch,
+    /// The pull request template picked with `--template` doesn't exist.
+    UnknownPullRequestTemplate {
+        name: String,
+        available: Vec<String>,
+    },
+    UnknownRemote(String),
+    /// Moving references with `git update-ref` failed.
+    UpdateRefsFailed(String),
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         match self {
+            Self::Aborted => write!(f, "Aborted."),
+            Self::AmbiguousBranch { commit, branches } => write!(
+                f,
+                "Commit {commit} has multiple branches: {}. Check out the one to use, or run interactively to choose.",
+                branches.join(", ")
+            ),
+            Self::AutoMergeNotAllowed(repo) => write!(f, "{repo} doesn't allow auto-merge, and has no merge queue for the base branch. Auto-merge can be allowed in the repository settings."),
             Self::BadParameter(m) => write!(f, "{m}"),
+            Self::BranchCheckedOut(b) => write!(f, "The branch {b} is checked out, so it can't be deleted. Check out another commit first."),
             Self::BranchTemplateMalformed(m)=>write!(f,"{m}"),
-            Self::Generic => write!(f, "{}", "Generic"),
+            Self::CheckoutFailed { branch, message } => write!(f, "Could not check out {branch}: {message}"),
+            Self::DeleteBranchFailed { branch, remote, message } => write!(f, "Could not delete {branch} from {remote}: {message}"),
+            Self::DiffFailed { from, to, message } => write!(f, "Could not compare {from} with {to}: {message}"),
+            Self::EditorFailed(m) => write!(f, "Could not edit the pull request message: {m}"),
+            Self::EmptyPullRequestMessage => write!(f, "Aborting the pull request due to an empty message."),
+            Self::FetchFailed { branch, remote, message } => write!(f, "Could not fetch {branch} from {remote}: {message}"),
+            Self::Generic => write!(f, "Generic"),
+            Self::GithubApp(m) => write!(f, "Github App authentication failed: {m}"),
+            Self::GithubApi { status, message } => write!(f, "Github request failed ({status}): {message}"),
+            Self::GithubGraphql(m) => write!(f, "Github request failed: {m}"),
+            Self::GithubRequest(m) => write!(f, "Could not communicate with Github: {m}"),
+            Self::HookFailed { hook, message } => write!(f, "The {hook} hook failed: {message}"),
+            Self::Interrupted => write!(f, "Interrupted."),
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Login(m) => write!(f, "Could not log in: {m}"),
             Self::MissingBranchParameter(p)=>write!(f, "Missing parameter {p}"),
             Self::MultipleParentCommits(c)=>write!(f,"Commit {} has multiple parents. Can not auto detect a base branch.",c),
             Self::NoBaseBranch => write!(f, "Reached the root of the repository and couldn't find a base branch."),
+            Self::NoBranch(commit) => write!(f, "Commit {commit} has no branch, so it has no pull request."),
             Self::NoCommitMessage=>write!(f, "No commit message available for generating the branch name."),
             Self::NoRepository => write!(
                 f,
@@ -43,10 +167,19 @@ impl std::fmt::Display for Error {
             ),
             Self::NoRemoteRepository => write!(f,"This repository has no remote."),
             Self::NoRemoteBranch(name)=>write!(f,"The branch {name} does not have a remote."),
+            Self::NoPullRequest(branch) => write!(f, "There is no open pull request for {branch}."),
             Self::NoSelectedCommit => write!(
                 f,
                 "No currently selected commit. Are there any commits on this repository?"
             ),
+            Self::NothingToUndo => write!(f, "There is nothing to undo."),
+            Self::NoToken(host) => write!(f, "No Github token found for {host}. Log in with `git ghpr auth login` or `gh auth login`, set GH_TOKEN, or set `token` in the [hosts.\"{host}\"] section of the configuration file."),
+            Self::NotGithubRemote { remote, url } => write!(f, "The remote {remote} ({url}) is not a Github repository."),
+            Self::PullRequestTemplateMalformed(m) => write!(f, "The pull request template could not be used: {m}"),
+            Self::PushFailed { branch, remote, message } => write!(f, "Could not push {branch} to {remote}: {message}"),
+            Self::RolledBack { error, remote: false } => write!(f, "{error}\nThe local branches and HEAD were put back as they were."),
+            Self::RolledBack { error, remote: true } => write!(f, "{error}\nThe local branches and HEAD were put back as they were. What was pushed, and any pull request opened, can be undone with `git ghpr undo --remote`."),
+            Self::SyncFailed(m) => write!(f, "Could not move the stack onto the main branch with git branchless sync: {m}"),
             Self::UnableToCreateBranch {
                 branch_name,
                 base_commit,
@@ -55,7 +188,14 @@ impl std::fmt::Display for Error {
                 "Could not create branch '{branch_name}' on commit {base_commit}.",
             ),
             Self::UnableToSelectBranch(b) => write!(f, "Could not switch to branch '{b}'."),
+            Self::UnknownBranch(b) => write!(f, "There is no branch named {b}."),
+            Self::UnknownRevision { pr, revision } => write!(f, "Pull request #{pr} has no revision {revision}. `git ghpr revisions {pr}` lists them."),
+            Self::UnknownCommit(rev) => write!(f, "Could not find the commit {rev}."),
             Self:: UnknownMainBranch=> write!(f, "Could not find a 'main' branch. Tried 'main' and 'master'."),
+            Self::UnknownPullRequestTemplate { name, available } if available.is_empty() => write!(f, "There is no pull request template named {name}. The repository has no PULL_REQUEST_TEMPLATE directory."),
+            Self::UnknownPullRequestTemplate { name, available } => write!(f, "There is no pull request template named {name}. The templates are: {}.", available.join(", ")),
+            Self::UnknownRemote(r) => write!(f, "The repository has no remote named {r}."),
+            Self::UpdateRefsFailed(m) => write!(f, "Could not move the branches: {m}"),
         }
     }
 }
@@ -77,6 +217,18 @@ impl From<git2::Error> for Error {
     }
 }
 
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Self::GithubRequest(e.to_string())
+    }
+}
+
 impl From<figment::Error> for Error {
     fn from(e: figment::Error) -> Self {
         match e.kind {
@@ -156,5 +308,72 @@ pub type Result<T, E = Error> = core::result::Result<T, E>;
 
 #[derive(Debug)]
 pub enum Message {
-    Empty,
+    AuthStatus {
+        host: String,
+        source: String,
+        token: String,
+    },
+    LoggedIn {
+        host: String,
+        path: PathBuf,
+    },
+    PullRequestCreated {
+        url: String,
+        /// What was asked for but couldn't be set on the pull request.
+        warnings: Vec<String>,
+    },
+    PullRequestUpdated {
+        url: String,
+        /// The commits below that are already on the main branch, and whether
+        /// the range-diff comment couldn't be added.
+        warnings: Vec<String>,
+    },
+    AutoMergeEnabled {
+        url: String,
+        /// Whether the pull request was added to a merge queue, rather than
+        /// set to merge once it can be.
+        queued: bool,
+    },
+    CleanedUp {
+        dry_run: bool,
+        deleted: Vec<Finished>,
+        /// Branches that are finished with, but weren't deleted.
+        kept: Vec<Finished>,
+        /// The pull requests moved off the deleted branches, with the branch
+        /// each went onto.
+        retargeted: Vec<(String, String)>,
+    },
+    Landed {
+        url: String,
+        main: String,
+        /// The pull requests moved onto the main branch.
+        retargeted: Vec<String>,
+        /// Whether the rest of the stack still has to be moved onto the main
+        /// branch locally.
+        restack: bool,
+    },
+    Revisions {
+        pr: u64,
+        revisions: Vec<Revision>,
+    },
+    RevisionDiff(String),
+    Undone {
+        command: String,
+        undone: Vec<String>,
+        /// What was left as it is, and why.
+        kept: Vec<String>,
+    },
+    CheckedOut {
+        /// The pull requests, from the bottom of the stack up, with the local
+        /// branch for each.
+        branches: Vec<(String, String)>,
+    },
+    Closed {
+        url: String,
+        base: String,
+        /// The pull requests moved onto `base`.
+        retargeted: Vec<String>,
+        /// The local branch, if it was deleted.
+        deleted: Option<String>,
+    },
 }
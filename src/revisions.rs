@@ -0,0 +1,232 @@
+//! Keeps every commit pushed for a pull request, like the patch sets of a
+//! Gerrit change. Force pushing otherwise loses what reviewers looked at, and
+//! what they approved. Revision `n` of pull request `pr` is an annotated tag
+//! object at `refs/ghpr/revisions/<pr>/<n>`, so it records when it was pushed.
+use git2::{Oid, Repository};
+use tracing::info;
+
+use crate::common::signature;
+use crate::configuration::Configuration;
+use crate::date;
+use crate::git;
+use crate::journal::Journal;
+use crate::result::Error;
+use crate::result::Message;
+use crate::result::Result;
+
+/// One pushed commit of a pull request.
+#[derive(Debug, PartialEq)]
+pub struct Revision {
+    pub number: u32,
+    pub commit: String,
+    /// When it was pushed, as git formats dates.
+    pub date: String,
+}
+
+/// Lists the revisions of pull request `pr`, or with `diff`, shows the changes
+/// from the first revision to the second.
+pub fn revisions(pr: u64, diff: Option<(u32, u32)>) -> Result<Message> {
+    info!("Opening the local git repository.");
+    let repo = Repository::discover(".")?;
+
+    let revisions = list(&repo, pr)?;
+    let (from, to) = match diff {
+        Some(d) => d,
+        None => return Ok(Message::Revisions { pr, revisions }),
+    };
+    let find = |n: u32| {
+        revisions
+            .iter()
+            .find(|r| r.number == n)
+            .map(|r| r.commit.as_str())
+            .ok_or(Error::UnknownRevision { pr, revision: n })
+    };
+    let diff = git::diff(&repo, find(from)?, find(to)?).map_err(|e| {
+        Error::DiffFailed {
+            from: ref_name(pr, from),
+            to: ref_name(pr, to),
+            message: e,
+        }
+    })?;
+    Ok(Message::RevisionDiff(diff))
+}
+
+fn ref_name(pr: u64, revision: u32) -> String {
+    format!("refs/ghpr/revisions/{pr}/{revision}")
+}
+
+/// The revisions of pull request `pr`, oldest first.
+pub fn list(repo: &Repository, pr: u64) -> Result<Vec<Revision>> {
+    let prefix = format!("refs/ghpr/revisions/{pr}/");
+    let mut revisions = Vec::new();
+    for reference in repo.references_glob(&format!("{prefix}*"))? {
+        let reference = reference?;
+        let number = match reference
+            .name()
+            .and_then(|n| n.strip_prefix(&prefix))
+            .and_then(|n| n.parse().ok())
+        {
+            Some(n) => n,
+            None => continue,
+        };
+        let tag = reference.peel_to_tag()?;
+        let date = tag
+            .tagger()
+            .map(|t| format_time(t.when()))
+            .unwrap_or_default();
+        revisions.push(Revision {
+            number,
+            commit: tag.target_id().to_string(),
+            date,
+        });
+    }
+    revisions.sort_by_key(|r| r.number);
+    Ok(revisions)
+}
+
+/// Records `commit` as the next revision of pull request `pr`, unless it is
+/// already the latest one. Returns the name of the new revision's reference.
+pub fn record(
+    repo: &Repository,
+    pr: u64,
+    commit: Oid,
+) -> Result<Option<String>> {
+    let revisions = list(repo, pr)?;
+    let number = match revisions.last() {
+        Some(r) if r.commit == commit.to_string() => return Ok(None),
+        Some(r) => r.number + 1,
+        None => 1,
+    };
+
+    let tagger = signature(repo)?;
+    let object = repo.find_object(commit, None)?;
+    let tag = repo.tag_annotation_create(
+        &number.to_string(),
+        &object,
+        &tagger,
+        &format!("Revision {number} of pull request #{pr}.\n"),
+    )?;
+    let name = ref_name(pr, number);
+    info!("Recording {commit} as {name}.");
+    repo.reference(&name, tag, false, "git-ghpr: record revision")?;
+    Ok(Some(name))
+}
+
+/// Records `commit`, just pushed for pull request `pr`, as its next revision,
+/// and pushes the revision too if the configuration says to. A failed push
+/// only loses the remote copy, so it is returned as a warning.
+pub fn record_pushed(
+    repo: &Repository,
+    journal: &Journal,
+    options: &Configuration,
+    pr: u64,
+    commit: Oid,
+) -> Result<Option<String>> {
+    let name = match record(repo, pr, commit)? {
+        Some(n) => n,
+        None => return Ok(None),
+    };
+    journal.reference(&name, None, repo.refname_to_id(&name)?)?;
+    if !options.push_revisions {
+        return Ok(None);
+    }
+    Ok(journal
+        .push_ref(repo, &options.push_remote, &name)
+        .err()
+        .map(|e| format!("Could not push the revision: {e}")))
+}
+
+/// Formats `time` the way `git log --date=iso` does, in the time zone it was
+/// recorded in.
+fn format_time(time: git2::Time) -> String {
+    let offset = time.offset_minutes();
+    let seconds = time.seconds() + i64::from(offset) * 60;
+    let days = seconds.div_euclid(86400);
+    let of_day = seconds.rem_euclid(86400);
+    let (year, month, day) = date::civil_from_days(days);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} {}{:02}{:02}",
+        of_day / 3600,
+        of_day % 3600 / 60,
+        of_day % 60,
+        if offset < 0 { '-' } else { '+' },
+        offset.abs() / 60,
+        offset.abs() % 60,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::Signature;
+    use speculoos::prelude::*;
+    use tempfile::{tempdir, TempDir};
+
+    use super::*;
+
+    fn init() -> (TempDir, Repository) {
+        let dir = tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        (dir, repo)
+    }
+
+    fn commit(repo: &Repository, message: &str) -> Oid {
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let tree_id = repo.index().unwrap().write_tree().unwrap();
+        let tree = repo.find_tree(tree_id).unwrap();
+        repo.commit(None, &signature, &signature, message, &tree, &[])
+            .unwrap()
+    }
+
+    #[test]
+    fn revisions_numbered_in_order() {
+        let (_dir, repo) = init();
+        let first = commit(&repo, "First");
+        let second = commit(&repo, "Second");
+
+        assert_that!(record(&repo, 7, first))
+            .is_ok()
+            .is_equal_to(Some("refs/ghpr/revisions/7/1".to_string()));
+        assert_that!(record(&repo, 7, second))
+            .is_ok()
+            .is_equal_to(Some("refs/ghpr/revisions/7/2".to_string()));
+
+        let revisions = list(&repo, 7).unwrap();
+        let commits: Vec<_> = revisions
+            .iter()
+            .map(|r| (r.number, r.commit.clone()))
+            .collect();
+        assert_that!(commits)
+            .is_equal_to(vec![(1, first.to_string()), (2, second.to_string())]);
+        assert_that!(list(&repo, 8)).is_ok().is_empty();
+    }
+
+    #[test]
+    fn same_commit_not_recorded_again() {
+        let (_dir, repo) = init();
+        let first = commit(&repo, "First");
+
+        record(&repo, 7, first).unwrap();
+        assert_that!(record(&repo, 7, first)).is_ok().is_none();
+        assert_that!(list(&repo, 7)).is_ok().has_length(1);
+    }
+
+    #[test]
+    fn more_than_nine_revisions_sorted() {
+        let (_dir, repo) = init();
+        for n in 1..=10 {
+            record(&repo, 7, commit(&repo, &n.to_string())).unwrap();
+        }
+
+        let numbers: Vec<_> =
+            list(&repo, 7).unwrap().iter().map(|r| r.number).collect();
+        assert_that!(numbers).is_equal_to((1..=10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn time_formatted_in_its_zone() {
+        assert_that!(format_time(git2::Time::new(1700000000, -300)))
+            .is_equal_to("2023-11-14 17:13:20 -0500".to_string());
+        assert_that!(format_time(git2::Time::new(951782400, 60)))
+            .is_equal_to("2000-02-29 01:00:00 +0100".to_string());
+    }
+}
@@ -0,0 +1,320 @@
+//! Links a commit to its pull request in the commit message, for teams that
+//! want the link to be part of the history. The commit is reworded once the
+//! pull request exists, and the commits stacked on it are moved onto the new
+//! commit.
+use std::collections::HashMap;
+
+use git2::{BranchType, Oid, Repository, Sort};
+use tracing::info;
+
+use crate::common::{branch_name, signature, upstream_remote};
+use crate::configuration::{CommitPrLink, Configuration};
+use crate::git;
+use crate::github::PullRequest;
+use crate::journal::Journal;
+use crate::notes;
+use crate::result::Result;
+
+const REFLOG_MESSAGE: &str = "git-ghpr: link pull request";
+
+/// Rewords `commit` to link to `pr`, in the way `link` says, and pushes the
+/// branches that moved. Returns the commit that replaced `commit`, which is
+/// `commit` itself when the message already has the link.
+pub fn link_pull_request(
+    repo: &Repository,
+    journal: &Journal,
+    options: &Configuration,
+    commit: Oid,
+    pr: &PullRequest,
+    link: CommitPrLink,
+) -> Result<Oid> {
+    let old = repo.find_commit(commit)?;
+    let message = old.message().unwrap_or_default();
+    let reworded = match link {
+        CommitPrLink::Trailer => with_trailer(message, &pr.html_url),
+        CommitPrLink::Summary => with_number(message, pr.number),
+    };
+    if reworded == message {
+        return Ok(commit);
+    }
+
+    let rewritten = reword(repo, journal, commit, &reworded)?;
+    for branch in moved_branches(repo, &rewritten)? {
+        if upstream_remote(repo, &branch).is_some() {
+            journal.push_branch(repo, &options.push_remote, &branch)?;
+        }
+    }
+    Ok(rewritten[&commit])
+}
+
+/// `message` with a `Pull-Request` trailer for `url` added to the end.
+fn with_trailer(message: &str, url: &str) -> String {
+    let trailer = format!("Pull-Request: {url}");
+    let message = message.trim_end();
+    if message.lines().any(|l| l == trailer) {
+        return format!("{message}\n");
+    }
+    // Trailers go in the last paragraph, together, when there already are
+    // some. The summary is never a trailer.
+    let last = message.rsplit("\n\n").next().unwrap_or_default();
+    let has_trailers = message.contains("\n\n")
+        && last.lines().all(|l| {
+            l.split_once(": ").is_some_and(|(token, _)| {
+                !token.is_empty() && !token.contains(char::is_whitespace)
+            })
+        });
+    let separator = if has_trailers { "\n" } else { "\n\n" };
+    format!("{message}{separator}{trailer}\n")
+}
+
+/// `message` with `(#number)` added to the summary, the way Github does for
+/// squash merges.
+fn with_number(message: &str, number: u64) -> String {
+    let reference = format!("(#{number})");
+    let (summary, rest) = match message.split_once('\n') {
+        Some((s, r)) => (s, Some(r)),
+        None => (message, None),
+    };
+    if summary.trim_end().ends_with(&reference) {
+        return message.to_string();
+    }
+    let summary = format!("{} {reference}", summary.trim_end());
+    match rest {
+        Some(r) => format!("{summary}\n{r}"),
+        None => format!("{summary}\n"),
+    }
+}
+
+/// Replaces `commit` with one that has `message`, and recreates every commit
+/// on a local branch or HEAD that descends from it, on top of the new commit.
+/// Only the messages change, so the trees are reused as they are, and there
+/// are never conflicts. Branches and HEAD are moved to the new commits with
+/// `git update-ref`, notes are copied, and the `post-rewrite` hook is run the
+/// way `git rebase` runs it. Between them, the `reference-transaction` and
+/// `post-rewrite` hooks are how git-branchless learns about the rewrite, for
+/// `git undo` and the smartlog. The moves go in `journal`. Returns the new commit for
+/// each rewritten one.
+fn reword(
+    repo: &Repository,
+    journal: &Journal,
+    commit: Oid,
+    message: &str,
+) -> Result<HashMap<Oid, Oid>> {
+    let committer = signature(repo)?;
+    let mut rewritten = HashMap::new();
+
+    let old = repo.find_commit(commit)?;
+    let parents: Vec<_> = old.parents().collect();
+    let new = repo.commit(
+        None,
+        &old.author(),
+        &committer,
+        message,
+        &old.tree()?,
+        &parents.iter().collect::<Vec<_>>(),
+    )?;
+    info!("Reworded {commit} as {new}.");
+    rewritten.insert(commit, new);
+
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    for tip in tips(repo)? {
+        if repo.graph_descendant_of(tip, commit)? {
+            walk.push(tip)?;
+        }
+    }
+    walk.hide(commit)?;
+    for id in walk {
+        let descendant = repo.find_commit(id?)?;
+        // A merge can bring in commits that don't descend from `commit`,
+        // which stay as they are.
+        if !descendant.parent_ids().any(|p| rewritten.contains_key(&p)) {
+            continue;
+        }
+        let parents = descendant
+            .parent_ids()
+            .map(|p| repo.find_commit(*rewritten.get(&p).unwrap_or(&p)))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let new = repo.commit(
+            None,
+            &descendant.author(),
+            &committer,
+            descendant.message_raw().unwrap_or_default(),
+            &descendant.tree()?,
+            &parents.iter().collect::<Vec<_>>(),
+        )?;
+        info!("Moved {} to {new}.", descendant.id());
+        rewritten.insert(descendant.id(), new);
+    }
+
+    let mut moves = Vec::new();
+    for reference in repo.references_glob("refs/heads/*")? {
+        let reference = reference?;
+        if let (Some(name), Some(old)) = (reference.name(), reference.target())
+        {
+            if let Some(new) = rewritten.get(&old) {
+                moves.push((name.to_string(), old, *new));
+            }
+        }
+    }
+    git::update_refs(repo, REFLOG_MESSAGE, &moves)?;
+    for (name, old, new) in &moves {
+        journal.reference(name, Some(*old), *new)?;
+    }
+    if repo.head_detached()? {
+        if let Some(old) = repo.head()?.target() {
+            if let Some(new) = rewritten.get(&old) {
+                let head = [("HEAD".to_string(), old, *new)];
+                journal.move_head(repo, || {
+                    git::update_refs(repo, REFLOG_MESSAGE, &head)
+                })?;
+            }
+        }
+    }
+
+    journal.track(repo, notes::NOTES_REF, || {
+        for (old, new) in &rewritten {
+            if let Some(note) = notes::read(repo, *old) {
+                notes::write(repo, *new, &note)?;
+            }
+        }
+        Ok(())
+    })?;
+
+    let mut mapping: Vec<_> = rewritten.iter().collect();
+    mapping.sort();
+    let input: String = mapping
+        .iter()
+        .map(|(old, new)| format!("{old} {new}\n"))
+        .collect();
+    git::run_hook(repo, "post-rewrite", &["rebase"], &input)?;
+
+    Ok(rewritten)
+}
+
+/// The commits at the tips of the local branches, and HEAD.
+fn tips(repo: &Repository) -> Result<Vec<Oid>> {
+    let mut tips = Vec::new();
+    for entry in repo.branches(Some(BranchType::Local))? {
+        let (branch, _) = entry?;
+        tips.extend(branch.get().target());
+    }
+    if let Ok(head) = repo.head() {
+        tips.extend(head.target());
+    }
+    Ok(tips)
+}
+
+/// The local branches now pointing at one of the `rewritten` commits.
+fn moved_branches(
+    repo: &Repository,
+    rewritten: &HashMap<Oid, Oid>,
+) -> Result<Vec<String>> {
+    let mut moved = Vec::new();
+    for entry in repo.branches(Some(BranchType::Local))? {
+        let (branch, _) = entry?;
+        if branch
+            .get()
+            .target()
+            .is_some_and(|t| rewritten.values().any(|n| *n == t))
+        {
+            moved.push(branch_name(&branch)?);
+        }
+    }
+    Ok(moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::Signature;
+    use speculoos::prelude::*;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    const URL: &str = "https://github.com/owner/repo/pull/12";
+
+    #[test]
+    fn trailer_added_as_paragraph() {
+        assert_that!(with_trailer("Summary\n\nBody text.\n", URL)).is_equal_to(
+            format!("Summary\n\nBody text.\n\nPull-Request: {URL}\n"),
+        );
+        assert_that!(with_trailer("Summary", URL))
+            .is_equal_to(format!("Summary\n\nPull-Request: {URL}\n"));
+    }
+
+    #[test]
+    fn trailer_added_to_trailers() {
+        assert_that!(with_trailer(
+            "Summary\n\nBody.\n\nSigned-off-by: A <a@example.com>\n",
+            URL
+        ))
+        .is_equal_to(format!(
+            "Summary\n\nBody.\n\nSigned-off-by: A <a@example.com>\nPull-Request: {URL}\n"
+        ));
+    }
+
+    #[test]
+    fn trailer_not_repeated() {
+        let message = format!("Summary\n\nPull-Request: {URL}\n");
+        assert_that!(with_trailer(&message, URL)).is_equal_to(message);
+    }
+
+    #[test]
+    fn number_added_to_summary() {
+        assert_that!(with_number("Fix the thing\n\nBecause.\n", 12))
+            .is_equal_to("Fix the thing (#12)\n\nBecause.\n".to_string());
+        assert_that!(with_number("Fix the thing", 12))
+            .is_equal_to("Fix the thing (#12)\n".to_string());
+        assert_that!(with_number("Fix the thing (#12)\n", 12))
+            .is_equal_to("Fix the thing (#12)\n".to_string());
+    }
+
+    fn commit(repo: &Repository, parent: Option<Oid>, message: &str) -> Oid {
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let tree_id = repo.index().unwrap().write_tree().unwrap();
+        let tree = repo.find_tree(tree_id).unwrap();
+        let parent = parent.map(|p| repo.find_commit(p).unwrap());
+        let parents: Vec<_> = parent.iter().collect();
+        repo.commit(None, &signature, &signature, message, &tree, &parents)
+            .unwrap()
+    }
+
+    #[test]
+    fn descendants_moved() {
+        let dir = tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let root = commit(&repo, None, "Root\n");
+        let first = commit(&repo, Some(root), "First\n");
+        let second = commit(&repo, Some(first), "Second\n");
+        let third = commit(&repo, Some(second), "Third\n");
+        let other = commit(&repo, Some(root), "Other\n");
+        for (name, id) in [("second", second), ("other", other)] {
+            repo.branch(name, &repo.find_commit(id).unwrap(), false)
+                .unwrap();
+        }
+        repo.set_head_detached(third).unwrap();
+
+        let journal = Journal::begin(&repo, "test").unwrap();
+        let rewritten = reword(&repo, &journal, first, "First (#1)\n").unwrap();
+
+        assert_that!(rewritten.len()).is_equal_to(3);
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_that!(head.id()).is_equal_to(rewritten[&third]);
+        let new_second = head.parent(0).unwrap();
+        assert_that!(new_second.id()).is_equal_to(rewritten[&second]);
+        assert_that!(new_second.message()).is_equal_to(Some("Second\n"));
+        let new_first = new_second.parent(0).unwrap();
+        assert_that!(new_first.message()).is_equal_to(Some("First (#1)\n"));
+        assert_that!(new_first.parent_id(0).unwrap()).is_equal_to(root);
+        let branch = |name| {
+            repo.find_branch(name, BranchType::Local)
+                .unwrap()
+                .get()
+                .target()
+                .unwrap()
+        };
+        assert_that!(branch("second")).is_equal_to(rewritten[&second]);
+        assert_that!(branch("other")).is_equal_to(other);
+    }
+}
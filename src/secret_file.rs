@@ -0,0 +1,31 @@
+//! Writes files that hold secrets, like tokens, so that only the user can
+//! read them.
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// Writes `contents` to `path`, creating the parent directories if needed. On
+/// Unix the file is only readable and writable by the user.
+pub fn write(path: &Path, contents: &str) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+    // The mode only applies when the file is created, so an existing file is
+    // tightened up too.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(contents.as_bytes())
+}
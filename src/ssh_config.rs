@@ -0,0 +1,452 @@
+//! Reads just enough of the OpenSSH client configuration to resolve `Host`
+//! aliases to real host names. People with more than one Github account
+//! commonly use remote URLs like `git@github-work:owner/repo.git`, with an
+//! entry in `~/.ssh/config` mapping `github-work` to `github.com`.
+//!
+//! Only the `Host`, `HostName` and `Include` keywords matter here. `Match`
+//! blocks can't be evaluated without a connection, so their contents are
+//! ignored.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use directories::BaseDirs;
+use tracing::{debug, info};
+
+/// `ssh` limits the nesting of `Include` to the same depth.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+#[derive(Debug, Default)]
+pub struct SshConfig {
+    blocks: Vec<Block>,
+}
+
+#[derive(Debug)]
+struct Block {
+    /// The patterns from the `Host` line. `None` for `Match` blocks, which
+    /// never apply.
+    patterns: Option<Vec<String>>,
+    hostname: Option<String>,
+}
+
+impl SshConfig {
+    /// Loads the configuration from `~/.ssh/config`. A missing or unreadable
+    /// file is treated the same as an empty one.
+    pub fn load() -> Self {
+        let ssh_dir = match BaseDirs::new() {
+            Some(bd) => bd.home_dir().join(".ssh"),
+            None => return Self::default(),
+        };
+
+        let mut config = Self::default();
+        config.read_file(&ssh_dir.join("config"), &ssh_dir, 0);
+        config
+    }
+
+    /// Parses configuration text. `Include` directives are ignored, since
+    /// there is no directory to resolve them against.
+    #[cfg(test)]
+    pub fn parse(text: &str) -> Self {
+        let mut config = Self::default();
+        config.read(text, None, 0);
+        config
+    }
+
+    /// Resolves `host` to the name `ssh` would connect to. Returns `host`
+    /// unchanged when there is no matching `HostName`.
+    pub fn resolve_hostname(&self, host: &str) -> String {
+        let lowercase = host.to_lowercase();
+        let hostname = self
+            .blocks
+            .iter()
+            .filter(|b| b.matches(&lowercase))
+            // The first value found for a keyword is the one that is used.
+            .find_map(|b| b.hostname.as_deref());
+
+        match hostname {
+            Some(h) => expand_tokens(h, host),
+            None => host.to_string(),
+        }
+    }
+
+    fn read_file(&mut self, path: &Path, ssh_dir: &Path, depth: usize) {
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                info!("Reading ssh configuration from {:?}", path);
+                self.read(&text, Some(ssh_dir), depth);
+            }
+            Err(e) => debug!("Could not read {:?}: {}", path, e),
+        }
+    }
+
+    fn read(&mut self, text: &str, ssh_dir: Option<&Path>, depth: usize) {
+        for line in text.lines() {
+            let (keyword, arguments) = match split_line(line) {
+                Some(l) => l,
+                None => continue,
+            };
+
+            match keyword.as_str() {
+                "host" => self.blocks.push(Block {
+                    patterns: Some(
+                        arguments.iter().map(|a| a.to_lowercase()).collect(),
+                    ),
+                    hostname: None,
+                }),
+                "match" => self.blocks.push(Block {
+                    patterns: None,
+                    hostname: None,
+                }),
+                "hostname" => {
+                    if self.blocks.is_empty() {
+                        // Options before the first `Host` apply to all hosts.
+                        self.blocks.push(Block {
+                            patterns: Some(vec!["*".to_string()]),
+                            hostname: None,
+                        });
+                    }
+                    let block = self.blocks.last_mut().unwrap();
+                    if block.hostname.is_none() {
+                        block.hostname = arguments.first().cloned();
+                    }
+                }
+                "include" => {
+                    let ssh_dir = match ssh_dir {
+                        Some(d) => d,
+                        None => continue,
+                    };
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        debug!("Too many nested includes, ignoring {line}");
+                        continue;
+                    }
+                    for argument in &arguments {
+                        for path in expand_include(argument, ssh_dir) {
+                            self.read_file(&path, ssh_dir, depth + 1);
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+impl Block {
+    fn matches(&self, host: &str) -> bool {
+        let patterns = match &self.patterns {
+            Some(p) => p,
+            None => return false,
+        };
+
+        let mut matched = false;
+        for pattern in patterns {
+            if let Some(negated) = pattern.strip_prefix('!') {
+                if wildcard_match(negated, host) {
+                    return false;
+                }
+            } else if wildcard_match(pattern, host) {
+                matched = true;
+            }
+        }
+        matched
+    }
+}
+
+/// Splits a configuration line into a lowercase keyword and its arguments.
+/// Keywords and arguments are separated by whitespace or an optional `=`, and
+/// arguments can be quoted to include spaces. Returns `None` for blank lines
+/// and comments.
+fn split_line(line: &str) -> Option<(String, Vec<String>)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let end = line
+        .find(|c: char| c.is_whitespace() || c == '=')
+        .unwrap_or(line.len());
+    let keyword = line[..end].to_lowercase();
+    let rest = line[end..].trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest);
+
+    let mut arguments = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in rest.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    arguments.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        arguments.push(current);
+    }
+
+    Some((keyword, arguments))
+}
+
+/// Matches `text` against an ssh pattern, where `*` matches any run of
+/// characters and `?` matches exactly one.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // Classic backtracking matcher: remember the most recent `*` and retry from
+    // one character further along whenever a mismatch occurs.
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Expands the `%h` and `%%` tokens allowed in `HostName`.
+fn expand_tokens(hostname: &str, host: &str) -> String {
+    let mut expanded = String::new();
+    let mut chars = hostname.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('h') => expanded.push_str(host),
+            Some('%') => expanded.push('%'),
+            Some(other) => {
+                expanded.push('%');
+                expanded.push(other);
+            }
+            None => expanded.push('%'),
+        }
+    }
+    expanded
+}
+
+/// Resolves the argument of an `Include` to the files it refers to. Relative
+/// paths are relative to `~/.ssh`, and wildcards are allowed in the file name.
+fn expand_include(argument: &str, ssh_dir: &Path) -> Vec<PathBuf> {
+    let path = match argument.strip_prefix("~/") {
+        Some(rest) => match BaseDirs::new() {
+            Some(bd) => bd.home_dir().join(rest),
+            None => return Vec::new(),
+        },
+        None => ssh_dir.join(argument),
+    };
+
+    let file_name = match path.file_name().and_then(|n| n.to_str()) {
+        Some(n) => n.to_string(),
+        None => return Vec::new(),
+    };
+    if !file_name.contains(['*', '?']) {
+        return vec![path];
+    }
+
+    let directory = match path.parent() {
+        Some(d) => d,
+        None => return Vec::new(),
+    };
+    let mut paths: Vec<PathBuf> = match fs::read_dir(directory) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| {
+                p.file_name()
+                    .and_then(|n| n.to_str())
+                    .map(|n| wildcard_match(&file_name, n))
+                    .unwrap_or(false)
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    // `ssh` reads wildcard includes in lexical order.
+    paths.sort();
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn alias_resolves_to_hostname() {
+        let config = SshConfig::parse(
+            "Host github-work\n    HostName github.com\n    User git\n",
+        );
+
+        assert_that!(config.resolve_hostname("github-work"))
+            .is_equal_to("github.com".to_string());
+    }
+
+    #[test]
+    fn unknown_host_is_unchanged() {
+        let config =
+            SshConfig::parse("Host github-work\n    HostName github.com\n");
+
+        assert_that!(config.resolve_hostname("github.example.com"))
+            .is_equal_to("github.example.com".to_string());
+    }
+
+    #[test]
+    fn empty_configuration() {
+        let config = SshConfig::parse("");
+
+        assert_that!(config.resolve_hostname("github.com"))
+            .is_equal_to("github.com".to_string());
+    }
+
+    #[test]
+    fn first_match_wins() {
+        let config = SshConfig::parse(
+            r#"
+Host github-*
+    HostName first.example.com
+
+Host github-work
+    HostName second.example.com
+"#,
+        );
+
+        assert_that!(config.resolve_hostname("github-work"))
+            .is_equal_to("first.example.com".to_string());
+    }
+
+    #[test]
+    fn multiple_patterns_on_one_line() {
+        let config =
+            SshConfig::parse("Host gh-one gh-two\n  HostName github.com\n");
+
+        assert_that!(config.resolve_hostname("gh-two"))
+            .is_equal_to("github.com".to_string());
+    }
+
+    #[test]
+    fn negated_pattern_excludes_host() {
+        let config = SshConfig::parse(
+            r#"
+Host gh-* !gh-personal
+    HostName github.example.com
+
+Host *
+    HostName fallback.example.com
+"#,
+        );
+
+        assert_that!(config.resolve_hostname("gh-work"))
+            .is_equal_to("github.example.com".to_string());
+        assert_that!(config.resolve_hostname("gh-personal"))
+            .is_equal_to("fallback.example.com".to_string());
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        let config = SshConfig::parse("Host gh?\n  HostName github.com\n");
+
+        assert_that!(config.resolve_hostname("gh1"))
+            .is_equal_to("github.com".to_string());
+        assert_that!(config.resolve_hostname("gh12"))
+            .is_equal_to("gh12".to_string());
+    }
+
+    #[test]
+    fn keywords_are_case_insensitive_and_accept_equals() {
+        let config =
+            SshConfig::parse("HOST github-work\n  hostname=github.com\n");
+
+        assert_that!(config.resolve_hostname("github-work"))
+            .is_equal_to("github.com".to_string());
+    }
+
+    #[test]
+    fn host_matching_is_case_insensitive() {
+        let config =
+            SshConfig::parse("Host GitHub-Work\n  HostName github.com\n");
+
+        assert_that!(config.resolve_hostname("github-WORK"))
+            .is_equal_to("github.com".to_string());
+    }
+
+    #[test]
+    fn hostname_token_expansion() {
+        let config = SshConfig::parse("Host gh-*\n  HostName %h.example.com\n");
+
+        assert_that!(config.resolve_hostname("gh-work"))
+            .is_equal_to("gh-work.example.com".to_string());
+    }
+
+    #[test]
+    fn quoted_arguments() {
+        let config = SshConfig::parse(
+            "Host \"github-work\"\n  HostName \"github.com\"\n",
+        );
+
+        assert_that!(config.resolve_hostname("github-work"))
+            .is_equal_to("github.com".to_string());
+    }
+
+    #[test]
+    fn comments_are_ignored() {
+        let config = SshConfig::parse(
+            "# Host github-work\n# HostName wrong.example.com\nHost github-work\n  HostName github.com\n",
+        );
+
+        assert_that!(config.resolve_hostname("github-work"))
+            .is_equal_to("github.com".to_string());
+    }
+
+    #[test]
+    fn match_blocks_are_ignored() {
+        let config = SshConfig::parse(
+            r#"
+Match host github-work exec "true"
+    HostName wrong.example.com
+
+Host github-work
+    HostName github.com
+"#,
+        );
+
+        assert_that!(config.resolve_hostname("github-work"))
+            .is_equal_to("github.com".to_string());
+    }
+
+    #[test]
+    fn options_before_first_host_apply_to_everything() {
+        let config = SshConfig::parse("HostName github.com\n");
+
+        assert_that!(config.resolve_hostname("anything"))
+            .is_equal_to("github.com".to_string());
+    }
+
+    #[test]
+    fn wildcards() {
+        assert_that!(wildcard_match("*", "")).is_true();
+        assert_that!(wildcard_match("*", "github.com")).is_true();
+        assert_that!(wildcard_match("*.com", "github.com")).is_true();
+        assert_that!(wildcard_match("git*b.com", "github.com")).is_true();
+        assert_that!(wildcard_match("*.org", "github.com")).is_false();
+        assert_that!(wildcard_match("github", "github.com")).is_false();
+        assert_that!(wildcard_match("g?thub.com", "github.com")).is_true();
+        assert_that!(wildcard_match("*a*b", "xaxxb")).is_true();
+        assert_that!(wildcard_match("*a*b", "xaxxbc")).is_false();
+    }
+}
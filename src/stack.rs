@@ -0,0 +1,524 @@
+//! Keeps a table of all the pull requests in a stack in the body of each of
+//! them, so reviewers can see what a pull request depends on, and what depends
+//! on it. The table is kept between marker comments, and only that part of the
+//! body is ever changed.
+//!
+//! The order of the stack comes from following base branches, the same way the
+//! base branch of a pull request is found.
+use std::collections::{HashMap, HashSet};
+
+use git2::{BranchType, Repository};
+use tracing::info;
+
+use crate::common::{
+    find_stack_base_branch, get_branch_for_commit_without_asking,
+    get_main_branch_commit,
+};
+use crate::configuration::Configuration;
+use crate::github::{Client, PullRequest};
+use crate::patch_id::Upstream;
+use crate::remote::GithubRepository;
+use crate::result::Result;
+
+const START: &str = "<!-- git-ghpr stack -->";
+const END: &str = "<!-- /git-ghpr stack -->";
+
+/// The branches below `branch` in its stack, from the one on the main branch
+/// upwards. The main branch itself isn't included. Commits already merged, in
+/// `upstream`, are passed over, and so are commits with several branches that
+/// are equally likely to be the one in the stack.
+pub fn ancestors(
+    repo: &Repository,
+    branch: &str,
+    upstream: &Upstream,
+    options: &Configuration,
+    branch_name_parameters: &HashMap<String, String>,
+    open_pull_requests: &HashSet<String>,
+) -> Result<Vec<String>> {
+    let (_, main_branch) = get_main_branch_commit(repo)?;
+    let main_name = main_branch.name()?.map(String::from);
+
+    let mut ancestors = Vec::new();
+    let mut commit = repo
+        .find_branch(branch, BranchType::Local)?
+        .get()
+        .peel_to_commit()?;
+    loop {
+        let base = find_stack_base_branch(
+            repo,
+            &commit,
+            upstream,
+            &options.branch_name_template,
+            branch_name_parameters,
+            open_pull_requests,
+        )?;
+        let base_name = base.name()?.map(String::from);
+        if base_name == main_name {
+            break;
+        }
+        commit = base.get().peel_to_commit()?;
+        ancestors.extend(base_name);
+    }
+
+    ancestors.reverse();
+    Ok(ancestors)
+}
+
+/// The branches stacked on top of `branch`, each followed by the ones stacked
+/// on it. Where several branches are stacked on the same one, they are in
+/// order of name. A branch that is one of several equally likely branches for
+/// its commit is left out.
+pub fn descendants(
+    repo: &Repository,
+    branch: &str,
+    upstream: &Upstream,
+    options: &Configuration,
+    branch_name_parameters: &HashMap<String, String>,
+    open_pull_requests: &HashSet<String>,
+) -> Result<Vec<String>> {
+    let commit = repo
+        .find_branch(branch, BranchType::Local)?
+        .get()
+        .peel_to_commit()?;
+
+    // Only branches with commits after `branch` can be stacked on it, which
+    // saves working out the base of every branch in the repository.
+    let mut children: HashMap<String, Vec<String>> = HashMap::new();
+    for entry in repo.branches(Some(BranchType::Local))? {
+        let (candidate, _) = entry?;
+        let candidate_commit = candidate.get().peel_to_commit()?;
+        if !repo.graph_descendant_of(candidate_commit.id(), commit.id())? {
+            continue;
+        }
+        let name = match candidate.name()? {
+            Some(n) => n.to_string(),
+            None => continue,
+        };
+        let likely = get_branch_for_commit_without_asking(
+            repo,
+            &candidate_commit,
+            &options.branch_name_template,
+            branch_name_parameters,
+            open_pull_requests,
+        )?;
+        if likely.and_then(|b| b.name().ok().flatten().map(String::from))
+            != Some(name.clone())
+        {
+            continue;
+        }
+        let base = find_stack_base_branch(
+            repo,
+            &candidate_commit,
+            upstream,
+            &options.branch_name_template,
+            branch_name_parameters,
+            open_pull_requests,
+        )?;
+        if let Some(base_name) = base.name()? {
+            children
+                .entry(base_name.to_string())
+                .or_default()
+                .push(name);
+        }
+    }
+
+    let mut descendants = Vec::new();
+    let mut pending = vec![branch.to_string()];
+    while let Some(parent) = pending.pop() {
+        if parent != branch {
+            descendants.push(parent.clone());
+        }
+        if let Some(mut names) = children.remove(&parent) {
+            // Reversed, so they come off the end of `pending` in order.
+            names.sort_by(|a, b| b.cmp(a));
+            pending.extend(names);
+        }
+    }
+    Ok(descendants)
+}
+
+/// Refreshes the stack table in the body of each of the pull requests. A pull
+/// request on its own isn't a stack, so any table left from before is removed
+/// instead.
+pub async fn update(
+    client: &Client,
+    repo: &GithubRepository,
+    pull_requests: &[PullRequest],
+) -> Result<()> {
+    for pr in pull_requests {
+        let table = match pull_requests.len() {
+            0 | 1 => None,
+            _ => Some(table(pull_requests, pr.number)),
+        };
+        let body = pr.body.clone().unwrap_or_default();
+        let updated = replace_section(&body, table.as_deref());
+        if updated != body {
+            info!("Updating the stack in pull request #{}.", pr.number);
+            client
+                .update_pull_request_body(repo, pr.number, &updated)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// The table of the pull requests in the stack, with `current` marked.
+fn table(pull_requests: &[PullRequest], current: u64) -> String {
+    let mut table = String::from(
+        "**Stack**\n\n| | Pull request | State |\n| --- | --- | --- |\n",
+    );
+    for pr in pull_requests {
+        let marker = if pr.number == current { "👉" } else { "" };
+        table.push_str(&format!(
+            "| {marker} | [#{}]({}) {} | {} |\n",
+            pr.number,
+            pr.html_url,
+            pr.title.replace('|', "\\|"),
+            state(pr)
+        ));
+    }
+    table
+}
+
+fn state(pr: &PullRequest) -> &'static str {
+    if pr.merged_at.is_some() {
+        "Merged"
+    } else if pr.state == "closed" {
+        "Closed"
+    } else if pr.draft {
+        "Draft"
+    } else {
+        "Open"
+    }
+}
+
+/// Replaces the text between the markers in `body` with `section`, or adds it
+/// at the end when there are no markers yet. With no `section`, the markers and
+/// what is between them are removed.
+fn replace_section(body: &str, section: Option<&str>) -> String {
+    let marked = section.map(|s| format!("{START}\n{}\n{END}", s.trim_end()));
+
+    let existing = body.find(START).and_then(|start| {
+        let end = body[start..].find(END)? + start + END.len();
+        Some((start, end))
+    });
+
+    match (existing, marked) {
+        (Some((start, end)), Some(marked)) => {
+            format!("{}{marked}{}", &body[..start], &body[end..])
+        }
+        (Some((start, end)), None) => {
+            let before = body[..start].trim_end();
+            let after = body[end..].trim_start();
+            match (before.is_empty(), after.is_empty()) {
+                (true, _) => after.to_string(),
+                (false, true) => before.to_string(),
+                (false, false) => format!("{before}\n\n{after}"),
+            }
+        }
+        (None, Some(marked)) if body.trim().is_empty() => marked,
+        (None, Some(marked)) => format!("{}\n\n{marked}", body.trim_end()),
+        (None, None) => body.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::{Oid, Signature};
+    use speculoos::prelude::*;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::common::{
+        find_base_branch_skipping_upstream, main_branch_changes,
+        AlreadyUpstream,
+    };
+
+    fn commit(repo: &Repository, parent: Option<Oid>, branch: &str) -> Oid {
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let tree_id = repo.index().unwrap().write_tree().unwrap();
+        let tree = repo.find_tree(tree_id).unwrap();
+        let parents: Vec<_> = parent
+            .map(|p| repo.find_commit(p).unwrap())
+            .into_iter()
+            .collect();
+        let parents: Vec<_> = parents.iter().collect();
+        let id = repo
+            .commit(None, &signature, &signature, branch, &tree, &parents)
+            .unwrap();
+        repo.branch(branch, &repo.find_commit(id).unwrap(), false)
+            .unwrap();
+        id
+    }
+
+    /// ◇ main
+    /// ┣━◯ first
+    /// ┃ ┣━◯ second
+    /// ┃ ┃ ┗━◯ fourth
+    /// ┃ ┗━◯ third
+    /// ┗━◯ other
+    #[test]
+    fn stack_order() {
+        let dir = tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let main = commit(&repo, None, "main");
+        let first = commit(&repo, Some(main), "first");
+        let second = commit(&repo, Some(first), "second");
+        commit(&repo, Some(first), "third");
+        commit(&repo, Some(second), "fourth");
+        commit(&repo, Some(main), "other");
+        let options = Configuration::for_test();
+        let parameters = HashMap::new();
+        let open_pull_requests = HashSet::new();
+        let upstream =
+            main_branch_changes(&repo, &repo.find_commit(second).unwrap())
+                .unwrap();
+
+        assert_that!(ancestors(
+            &repo,
+            "fourth",
+            &upstream,
+            &options,
+            &parameters,
+            &open_pull_requests
+        ))
+        .is_ok()
+        .is_equal_to(vec!["first".to_string(), "second".to_string()]);
+        assert_that!(ancestors(
+            &repo,
+            "first",
+            &upstream,
+            &options,
+            &parameters,
+            &open_pull_requests
+        ))
+        .is_ok()
+        .is_empty();
+        assert_that!(descendants(
+            &repo,
+            "first",
+            &upstream,
+            &options,
+            &parameters,
+            &open_pull_requests
+        ))
+        .is_ok()
+        .is_equal_to(vec![
+            "second".to_string(),
+            "fourth".to_string(),
+            "third".to_string(),
+        ]);
+        assert_that!(descendants(
+            &repo,
+            "fourth",
+            &upstream,
+            &options,
+            &parameters,
+            &open_pull_requests
+        ))
+        .is_ok()
+        .is_empty();
+    }
+
+    /// ◇ main
+    /// ┗━◯ first
+    ///   ┗━◯ copy, spare
+    ///     ┗━◯ third
+    ///
+    /// `copy` and `spare` are equally likely to be the branch in the stack,
+    /// which would need asking about, so they are passed over.
+    #[test]
+    fn ambiguous_branches_skipped() {
+        let dir = tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let main = commit(&repo, None, "main");
+        let first = commit(&repo, Some(main), "first");
+        let second = commit(&repo, Some(first), "second");
+        for name in ["copy", "spare"] {
+            repo.branch(name, &repo.find_commit(second).unwrap(), false)
+                .unwrap();
+        }
+        repo.find_branch("second", BranchType::Local)
+            .unwrap()
+            .delete()
+            .unwrap();
+        commit(&repo, Some(second), "third");
+        let options = Configuration::for_test();
+        let parameters = HashMap::new();
+        let open_pull_requests = HashSet::new();
+        let upstream =
+            main_branch_changes(&repo, &repo.find_commit(second).unwrap())
+                .unwrap();
+
+        assert_that!(ancestors(
+            &repo,
+            "third",
+            &upstream,
+            &options,
+            &parameters,
+            &open_pull_requests
+        ))
+        .is_ok()
+        .is_equal_to(vec!["first".to_string()]);
+        assert_that!(descendants(
+            &repo,
+            "first",
+            &upstream,
+            &options,
+            &parameters,
+            &open_pull_requests
+        ))
+        .is_ok()
+        .is_equal_to(vec!["third".to_string()]);
+    }
+
+    /// Commits `files` on top of `parent`, and points `branch` at it.
+    fn commit_files(
+        repo: &Repository,
+        parent: Oid,
+        branch: &str,
+        files: &[(&str, &str)],
+    ) -> Oid {
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let parent = repo.find_commit(parent).unwrap();
+        let mut builder =
+            repo.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+        for (path, content) in files {
+            let blob = repo.blob(content.as_bytes()).unwrap();
+            builder.insert(path, blob, 0o100644).unwrap();
+        }
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let id = repo
+            .commit(None, &signature, &signature, branch, &tree, &[&parent])
+            .unwrap();
+        repo.branch(branch, &repo.find_commit(id).unwrap(), true)
+            .unwrap();
+        id
+    }
+
+    /// ◇ main, with first squash merged
+    /// ┗━◯ first
+    ///   ┗━◯ second
+    ///     ┗━◯ third
+    #[test]
+    fn squash_merged_commits_skipped() {
+        let dir = tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let root = commit(&repo, None, "main");
+        let first = commit_files(&repo, root, "first", &[("a.txt", "A\n")]);
+        let second = commit_files(&repo, first, "second", &[("b.txt", "B\n")]);
+        commit_files(&repo, second, "third", &[("c.txt", "C\n")]);
+        let squashed = commit_files(&repo, root, "main", &[("a.txt", "A\n")]);
+        let options = Configuration::for_test();
+        let parameters = HashMap::new();
+        let open_pull_requests = HashSet::new();
+        let second = repo.find_commit(second).unwrap();
+        let upstream = main_branch_changes(&repo, &second).unwrap();
+
+        assert_that!(ancestors(
+            &repo,
+            "third",
+            &upstream,
+            &options,
+            &parameters,
+            &open_pull_requests
+        ))
+        .is_ok()
+        .is_equal_to(vec!["second".to_string()]);
+
+        let (base, dropped) = find_base_branch_skipping_upstream(
+            &repo,
+            &second,
+            &upstream,
+            &options.branch_name_template,
+            &parameters,
+            &open_pull_requests,
+        )
+        .unwrap();
+        assert_that!(base.name().unwrap()).is_equal_to(Some("main"));
+        assert_that!(dropped).is_equal_to(vec![AlreadyUpstream {
+            commit: first,
+            upstream: squashed,
+        }]);
+    }
+
+    fn pull_request(number: u64, title: &str, state: &str) -> PullRequest {
+        PullRequest {
+            number,
+            html_url: format!("https://github.com/owner/repo/pull/{number}"),
+            title: title.to_string(),
+            state: state.to_string(),
+            draft: false,
+            merged_at: None,
+            body: None,
+            node_id: String::new(),
+            head: Default::default(),
+            base: Default::default(),
+        }
+    }
+
+    #[test]
+    fn stack_table() {
+        let mut merged = pull_request(1, "First | one", "closed");
+        merged.merged_at = Some("2024-01-01T00:00:00Z".to_string());
+        let mut draft = pull_request(3, "Third", "open");
+        draft.draft = true;
+        let pull_requests =
+            vec![merged, pull_request(2, "Second", "open"), draft];
+
+        assert_that!(table(&pull_requests, 2)).is_equal_to(
+            r#"**Stack**
+
+| | Pull request | State |
+| --- | --- | --- |
+|  | [#1](https://github.com/owner/repo/pull/1) First \| one | Merged |
+| 👉 | [#2](https://github.com/owner/repo/pull/2) Second | Open |
+|  | [#3](https://github.com/owner/repo/pull/3) Third | Draft |
+"#
+            .to_string(),
+        );
+    }
+
+    #[test]
+    fn section_added() {
+        assert_that!(replace_section("Some text.\n", Some("Table\n")))
+            .is_equal_to(format!("Some text.\n\n{START}\nTable\n{END}"));
+        assert_that!(replace_section("", Some("Table")))
+            .is_equal_to(format!("{START}\nTable\n{END}"));
+    }
+
+    #[test]
+    fn section_replaced() {
+        let body = format!("Before.\n\n{START}\nOld\n{END}\n\nAfter.\n");
+
+        assert_that!(replace_section(&body, Some("New")))
+            .is_equal_to(format!("Before.\n\n{START}\nNew\n{END}\n\nAfter.\n"));
+    }
+
+    #[test]
+    fn section_removed() {
+        let body = format!("Before.\n\n{START}\nOld\n{END}\n\nAfter.\n");
+
+        assert_that!(replace_section(&body, None))
+            .is_equal_to("Before.\n\nAfter.\n".to_string());
+        assert_that!(replace_section(&format!("{START}\nOld\n{END}"), None))
+            .is_equal_to(String::new());
+    }
+
+    #[test]
+    fn section_unchanged() {
+        let body = format!("Before.\n\n{START}\nSame\n{END}");
+
+        assert_that!(replace_section(&body, Some("Same"))).is_equal_to(body);
+        assert_that!(replace_section("Just text.", None))
+            .is_equal_to("Just text.".to_string());
+    }
+
+    #[test]
+    fn unterminated_section() {
+        let body = format!("Before.\n\n{START}\nOld");
+
+        assert_that!(replace_section(&body, Some("New")))
+            .is_equal_to(format!("{body}\n\n{START}\nNew\n{END}"));
+    }
+}
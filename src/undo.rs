@@ -0,0 +1,448 @@
+//! Undoes the last operation from the journal. Local references are put back
+//! the way they were, and with `--remote`, pushes are reverted and pull
+//! requests closed.
+use std::future::Future;
+use std::task::Poll;
+
+use git2::{Oid, Repository};
+use tracing::{info, warn};
+
+use crate::auth;
+use crate::configuration::Configuration;
+use crate::git;
+use crate::github::Client;
+use crate::journal::{self, Entry, Journal};
+use crate::remote::GithubRepository;
+use crate::result::Error;
+use crate::result::Message;
+use crate::result::Result;
+
+/// Undoes the changes of the last operation, newest first. A reference that
+/// has moved since is left alone, rather than losing whatever moved it. What
+/// is left on the remote without `remote` stays in the journal, so it can be
+/// undone later.
+pub async fn undo(options: &Configuration, remote: bool) -> Result<Message> {
+    info!("Opening the local git repository.");
+    let repo = Repository::discover(".")?;
+
+    let (command, entries) =
+        journal::last_operation(&repo)?.ok_or(Error::NothingToUndo)?;
+
+    let mut undone = Vec::new();
+    let mut kept = Vec::new();
+    let mut remaining = Vec::new();
+    for entry in entries.into_iter().rev() {
+        let result = match &entry {
+            Entry::Begin { .. } => continue,
+            Entry::Reference { name, old, new } => {
+                undo_reference(&repo, name, old.as_deref(), new)
+            }
+            Entry::Head { old } => undo_head(&repo, old),
+            Entry::Push { .. } | Entry::PullRequest { .. } if !remote => {
+                kept.push(format!(
+                    "{} was left as it is. Undo it with `git ghpr undo --remote`.",
+                    describe(&entry)
+                ));
+                remaining.push(entry);
+                continue;
+            }
+            Entry::Push { remote, name, old } => {
+                undo_push(&repo, remote, name, old.as_deref())
+            }
+            Entry::PullRequest {
+                host,
+                owner,
+                repository,
+                number,
+                url,
+            } => {
+                let repository = GithubRepository {
+                    host: host.clone(),
+                    owner: owner.clone(),
+                    name: repository.clone(),
+                };
+                close_pull_request(options, &repository, *number, url).await
+            }
+        };
+        match result {
+            Ok(Ok(done)) => undone.push(done),
+            Ok(Err(reason)) => kept.push(reason),
+            Err(e) => kept
+                .push(format!("Could not undo that {}: {e}", describe(&entry))),
+        }
+    }
+
+    remaining.reverse();
+    journal::replace_last_operation(&repo, &command, remaining)?;
+
+    Ok(Message::Undone {
+        command,
+        undone,
+        kept,
+    })
+}
+
+/// Runs `operation`, journaled in `journal`, and puts the local branches and
+/// HEAD back the way they were if it fails, or `interrupt` finishes first.
+/// The pushes and pull requests are left for `git ghpr undo --remote`.
+pub async fn rolling_back<T>(
+    repo: &Repository,
+    journal: &Journal,
+    operation: impl Future<Output = Result<T>>,
+    interrupt: impl Future,
+) -> Result<T> {
+    tokio::pin!(interrupt);
+    // `interrupt` is polled before the operation starts, because a signal
+    // handler behind it, like `ctrl_c`, is only installed by its first poll.
+    // Until then Ctrl-C would end the process, with nothing rolled back.
+    let interrupted = std::future::poll_fn(|cx| {
+        Poll::Ready(interrupt.as_mut().poll(cx).is_ready())
+    })
+    .await;
+    let result = if interrupted {
+        Err(Error::Interrupted)
+    } else {
+        tokio::select! {
+            biased;
+            _ = &mut interrupt => Err(Error::Interrupted),
+            result = operation => result,
+        }
+    };
+    result.map_err(|error| match roll_back(repo, journal) {
+        Ok((false, false)) => error,
+        Ok((_, remote)) => Error::RolledBack {
+            error: Box::new(error),
+            remote,
+        },
+        Err(e) => {
+            warn!("Could not roll back the local changes: {e}");
+            error
+        }
+    })
+}
+
+/// Puts back the local references and HEAD changed by the operation in
+/// progress, `journal`, after it failed. Its pushes and pull requests stay in
+/// the journal, for `undo --remote`. Returns whether anything was put back,
+/// and whether anything was left on the remote.
+pub fn roll_back(repo: &Repository, journal: &Journal) -> Result<(bool, bool)> {
+    // Without any changes, the last operation in the journal is an earlier
+    // one, which has to be left alone.
+    if !journal.has_changes() {
+        return Ok((false, false));
+    }
+    let (command, entries) = match journal::last_operation(repo)? {
+        Some(operation) => operation,
+        None => return Ok((false, false)),
+    };
+
+    let mut rolled_back = false;
+    let mut remaining = Vec::new();
+    for entry in entries.into_iter().rev() {
+        let result = match &entry {
+            Entry::Begin { .. } => continue,
+            Entry::Reference { name, old, new } => {
+                undo_reference(repo, name, old.as_deref(), new)
+            }
+            Entry::Head { old } => undo_head(repo, old),
+            Entry::Push { .. } | Entry::PullRequest { .. } => {
+                remaining.push(entry);
+                continue;
+            }
+        };
+        match result {
+            Ok(Ok(done)) => {
+                info!("{done}");
+                rolled_back = true;
+            }
+            Ok(Err(reason)) => warn!("{reason}"),
+            Err(e) => warn!("Could not undo that {}: {e}", describe(&entry)),
+        }
+    }
+
+    remaining.reverse();
+    let remote = !remaining.is_empty();
+    journal::replace_last_operation(repo, &command, remaining)?;
+    Ok((rolled_back, remote))
+}
+
+/// What `entry` did, for telling the user about it.
+fn describe(entry: &Entry) -> String {
+    match entry {
+        Entry::Begin { command } => format!("Running {command}"),
+        Entry::Reference {
+            name, old: None, ..
+        } => format!("Creating {name}"),
+        Entry::Reference { name, .. } => format!("Moving {name}"),
+        Entry::Head { .. } => "Moving HEAD".to_string(),
+        Entry::Push { remote, name, .. } => {
+            format!("Pushing {name} to {remote}")
+        }
+        Entry::PullRequest { url, .. } => {
+            format!("Opening pull request {url}")
+        }
+    }
+}
+
+/// Undoing an entry either works, saying what was done, or is left alone for
+/// a reason, or fails.
+type Undone = Result<std::result::Result<String, String>>;
+
+fn undo_reference(
+    repo: &Repository,
+    name: &str,
+    old: Option<&str>,
+    new: &str,
+) -> Undone {
+    let mut reference = match repo.find_reference(name) {
+        Ok(r) => r,
+        Err(_) => return Ok(Err(format!("{name} is already gone."))),
+    };
+    if reference.target().map(|t| t.to_string()).as_deref() != Some(new) {
+        return Ok(Err(format!(
+            "{name} has moved since, so it was left alone."
+        )));
+    }
+    match old {
+        None => {
+            let head = repo.find_reference("HEAD")?;
+            if head.symbolic_target() == Some(name) {
+                return Ok(Err(format!(
+                    "{name} is checked out, so it wasn't deleted."
+                )));
+            }
+            reference.delete()?;
+            Ok(Ok(format!("Deleted {name}")))
+        }
+        Some(old) => {
+            reference.set_target(Oid::from_str(old)?, "git-ghpr: undo")?;
+            Ok(Ok(format!("Moved {name} back to {old:.7}")))
+        }
+    }
+}
+
+/// Moves HEAD back to `old`. The operations only move HEAD between commits
+/// with the same tree, so there is nothing to check out.
+fn undo_head(repo: &Repository, old: &str) -> Undone {
+    let head = repo.find_reference("HEAD")?;
+    let current = match head.symbolic_target() {
+        Some(name) => name.to_string(),
+        None => head.target().map(|t| t.to_string()).unwrap_or_default(),
+    };
+    if current == old {
+        return Ok(Err(format!("HEAD is already at {old}.")));
+    }
+    if old.starts_with("refs/") {
+        repo.set_head(old)?;
+        Ok(Ok(format!("Moved HEAD back to {old}")))
+    } else {
+        repo.set_head_detached(Oid::from_str(old)?)?;
+        Ok(Ok(format!("Moved HEAD back to {old:.7}")))
+    }
+}
+
+fn undo_push(
+    repo: &Repository,
+    remote: &str,
+    name: &str,
+    old: Option<&str>,
+) -> Undone {
+    match old {
+        None => {
+            git::delete_remote_branch(repo, remote, name)?;
+            Ok(Ok(format!("Deleted {name} from {remote}")))
+        }
+        Some(old) => {
+            git::push_commit(repo, remote, old, name)?;
+            Ok(Ok(format!("Moved {name} on {remote} back to {old:.7}")))
+        }
+    }
+}
+
+async fn close_pull_request(
+    options: &Configuration,
+    repository: &GithubRepository,
+    number: u64,
+    url: &str,
+) -> Undone {
+    let credential = auth::get_credential(options, &repository.host).await?;
+    let client =
+        Client::new(&options.host(&repository.host), &credential.token)?;
+    client.close_pull_request(repository, number).await?;
+    Ok(Ok(format!("Closed pull request {url}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use git2::Signature;
+    use speculoos::prelude::*;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn repository() -> (tempfile::TempDir, Repository, Oid) {
+        let dir = tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let tree_id = repo.index().unwrap().write_tree().unwrap();
+        let commit = {
+            let tree = repo.find_tree(tree_id).unwrap();
+            repo.commit(None, &signature, &signature, "First", &tree, &[])
+                .unwrap()
+        };
+        repo.set_head_detached(commit).unwrap();
+        (dir, repo, commit)
+    }
+
+    /// Stands in for Ctrl-C, which only reaches the handler from `ctrl_c` once
+    /// it has been polled.
+    #[derive(Default)]
+    struct Signal {
+        installed: Cell<bool>,
+        caught: Cell<bool>,
+    }
+
+    impl Signal {
+        fn send(&self) {
+            if self.installed.get() {
+                self.caught.set(true);
+            }
+        }
+
+        fn handler(&self) -> impl Future<Output = ()> + '_ {
+            std::future::poll_fn(|_| {
+                self.installed.set(true);
+                if self.caught.get() {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            })
+        }
+    }
+
+    /// Creates the branch `name` on `commit` and checks it out, the way
+    /// `create` does.
+    fn create_branch(
+        repo: &Repository,
+        journal: &Journal,
+        name: &str,
+        commit: Oid,
+    ) -> Result<()> {
+        repo.branch(name, &repo.find_commit(commit)?, false)?;
+        journal.reference(&format!("refs/heads/{name}"), None, commit)?;
+        journal.move_head(repo, || {
+            Ok(repo.set_head(&format!("refs/heads/{name}"))?)
+        })
+    }
+
+    #[tokio::test]
+    async fn rolled_back_on_failure() {
+        let (_dir, repo, commit) = repository();
+        let journal = Journal::begin(&repo, "create").unwrap();
+
+        let result = rolling_back(
+            &repo,
+            &journal,
+            async {
+                create_branch(&repo, &journal, "first", commit)?;
+                Err::<(), _>(Error::Generic)
+            },
+            std::future::pending::<()>(),
+        )
+        .await;
+
+        assert_that!(matches!(
+            result,
+            Err(Error::RolledBack { ref error, remote: false })
+                if matches!(**error, Error::Generic)
+        ))
+        .is_true();
+        assert_that!(repo
+            .find_branch("first", git2::BranchType::Local)
+            .is_err())
+        .is_true();
+        assert_that!(repo.head_detached()).is_ok().is_true();
+        assert_that!(repo.head().unwrap().target()).is_equal_to(Some(commit));
+        assert_that!(journal::last_operation(&repo))
+            .is_ok()
+            .is_none();
+    }
+
+    #[tokio::test]
+    async fn rolled_back_when_interrupted() {
+        let (_dir, repo, commit) = repository();
+        let journal = Journal::begin(&repo, "create").unwrap();
+        let interrupt = Signal::default();
+
+        // The interruption comes before the operation first waits.
+        let result = rolling_back(
+            &repo,
+            &journal,
+            async {
+                create_branch(&repo, &journal, "first", commit)?;
+                interrupt.send();
+                tokio::task::yield_now().await;
+                Ok(())
+            },
+            interrupt.handler(),
+        )
+        .await;
+
+        assert_that!(matches!(
+            result,
+            Err(Error::RolledBack { ref error, remote: false })
+                if matches!(**error, Error::Interrupted)
+        ))
+        .is_true();
+        assert_that!(repo
+            .find_branch("first", git2::BranchType::Local)
+            .is_err())
+        .is_true();
+        assert_that!(repo.head_detached()).is_ok().is_true();
+    }
+
+    #[tokio::test]
+    async fn interrupted_before_starting() {
+        let (_dir, repo, commit) = repository();
+        let journal = Journal::begin(&repo, "create").unwrap();
+
+        let result = rolling_back(
+            &repo,
+            &journal,
+            async { create_branch(&repo, &journal, "first", commit) },
+            std::future::ready(()),
+        )
+        .await;
+
+        assert_that!(matches!(result, Err(Error::Interrupted))).is_true();
+        assert_that!(repo
+            .find_branch("first", git2::BranchType::Local)
+            .is_err())
+        .is_true();
+    }
+
+    #[tokio::test]
+    async fn earlier_operation_left_alone() {
+        let (_dir, repo, commit) = repository();
+        let earlier = Journal::begin(&repo, "create").unwrap();
+        create_branch(&repo, &earlier, "earlier", commit).unwrap();
+        let journal = Journal::begin(&repo, "create").unwrap();
+
+        let result = rolling_back(
+            &repo,
+            &journal,
+            async { Err::<(), _>(Error::Generic) },
+            std::future::pending::<()>(),
+        )
+        .await;
+
+        assert_that!(matches!(result, Err(Error::Generic))).is_true();
+        assert_that!(repo
+            .find_branch("earlier", git2::BranchType::Local)
+            .is_ok())
+        .is_true();
+    }
+}
@@ -0,0 +1,402 @@
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::Result;
+use httpmock::prelude::*;
+use serde_json::json;
+use speculoos::prelude::*;
+use tempfile::tempdir;
+
+use crate::common::write_config;
+use crate::common::TEST_BINARY;
+use crate::common::TOKEN_VARIABLES;
+
+mod common;
+
+/// Tests that a token in the environment is used ahead of one from the `gh`
+/// CLI.
+#[test]
+fn token_from_environment() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let home = tempdir()?;
+    write_gh_hosts(home.path(), "github.com:\n    oauth_token: gho_ghcli\n")?;
+
+    //
+    // Act.
+    //
+    let output = auth_status(home.path())
+        .env("GH_TOKEN", "ghp_environment")
+        .output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(String::from_utf8(output.stderr)?).is_empty();
+    assert_that!(String::from_utf8(output.stdout)?).is_equal_to(
+        "github.com\n  Token: ghp_********\n  Source: GH_TOKEN environment variable\n"
+            .to_string(),
+    );
+    assert_that!(output.status.success()).is_true();
+
+    Ok(())
+}
+
+/// Tests that `GH_PR_TOKEN` is used ahead of the `gh` CLI.
+#[test]
+fn token_from_gh_pr_token() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let home = tempdir()?;
+    write_gh_hosts(home.path(), "github.com:\n    oauth_token: gho_ghcli\n")?;
+
+    //
+    // Act.
+    //
+    let output = auth_status(home.path())
+        .env("GH_PR_TOKEN", "ghp_ghprtoken")
+        .output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(String::from_utf8(output.stdout)?).is_equal_to(
+        "github.com\n  Token: ghp_********\n  Source: GH_PR_TOKEN environment variable\n"
+            .to_string(),
+    );
+    assert_that!(output.status.success()).is_true();
+
+    Ok(())
+}
+
+/// Tests that the token of a logged in `gh` CLI is used.
+#[test]
+fn token_from_gh_cli() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let home = tempdir()?;
+    write_gh_hosts(
+        home.path(),
+        "github.example.com:\n    user: octocat\n    oauth_token: gho_ghcli0123\n",
+    )?;
+
+    //
+    // Act.
+    //
+    let output = auth_status(home.path())
+        .args(["--hostname", "github.example.com"])
+        .output()?;
+
+    //
+    // Assert.
+    //
+    let hosts_yml = home.path().join(".config").join("gh").join("hosts.yml");
+    assert_that!(String::from_utf8(output.stdout)?).is_equal_to(format!(
+        "github.example.com\n  Token: gho_********\n  Source: gh CLI ({})\n",
+        hosts_yml.display()
+    ));
+    assert_that!(output.status.success()).is_true();
+
+    Ok(())
+}
+
+/// Tests that the credentials git has stored for the host are used when
+/// nothing else has a token.
+#[test]
+fn token_from_git_credential() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let home = tempdir()?;
+    std::fs::write(
+        home.path().join(".gitconfig"),
+        r#"[credential]
+    helper = "!f() { test \"$1\" = get && echo username=octocat && echo password=ghp_credential; }; f"
+"#,
+    )?;
+
+    //
+    // Act.
+    //
+    let output = auth_status(home.path()).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(String::from_utf8(output.stdout)?).is_equal_to(
+        "github.com\n  Token: ghp_********\n  Source: git credential fill\n"
+            .to_string(),
+    );
+    assert_that!(output.status.success()).is_true();
+
+    Ok(())
+}
+
+/// Tests that a Github App configured for the host is authenticated as, and
+/// that the installation token is cached for the next command.
+#[test]
+fn token_from_github_app() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let home = tempdir()?;
+    let github = MockServer::start();
+    let private_key = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("resources")
+        .join("github_app_key.pem");
+    write_config(
+        home.path(),
+        &format!(
+            r#"
+[hosts."github.com"]
+api_url = "{}"
+app_id = 1234
+installation_id = 42
+private_key_path = "{}"
+"#,
+            github.base_url(),
+            private_key.display()
+        ),
+    )?;
+    let exchange = github.mock(|when, then| {
+        when.method(POST)
+            .path("/app/installations/42/access_tokens")
+            .matches(|request| {
+                // A JWT is three base64url sections separated by dots.
+                request.headers.iter().flatten().any(|(name, value)| {
+                    name.eq_ignore_ascii_case("authorization")
+                        && value
+                            .strip_prefix("Bearer ")
+                            .is_some_and(|jwt| jwt.split('.').count() == 3)
+                })
+            });
+        then.status(201).json_body(json!({
+            "token": "ghs_installation",
+            "expires_at": "2099-01-01T00:00:00Z",
+        }));
+    });
+
+    //
+    // Act.
+    //
+    let first = auth_status(home.path()).output()?;
+    let second = auth_status(home.path()).output()?;
+
+    //
+    // Assert.
+    //
+    let expected =
+        "github.com\n  Token: ghs_********\n  Source: Github App 1234 (installation 42)\n";
+    assert_that!(String::from_utf8(first.stderr)?).is_empty();
+    assert_that!(String::from_utf8(first.stdout)?)
+        .is_equal_to(expected.to_string());
+    assert_that!(String::from_utf8(second.stdout)?)
+        .is_equal_to(expected.to_string());
+    assert_that!(first.status.success()).is_true();
+    assert_that!(second.status.success()).is_true();
+    exchange.assert_hits(1);
+
+    Ok(())
+}
+
+/// Tests logging in with the device flow, and that the saved token is used
+/// afterwards.
+#[test]
+fn login_with_device_flow() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let home = tempdir()?;
+    let github = MockServer::start();
+    write_login_config(home.path(), &github)?;
+    let device_code = github.mock(|when, then| {
+        when.method(POST)
+            .path("/login/device/code")
+            .x_www_form_urlencoded_tuple("client_id", "test-client");
+        then.status(200).json_body(json!({
+            "device_code": "device-1234",
+            "user_code": "WDJB-MJHT",
+            "verification_uri": "https://github.com/login/device",
+            "expires_in": 900,
+            "interval": 0,
+        }));
+    });
+    let access_token = github.mock(|when, then| {
+        when.method(POST)
+            .path("/login/oauth/access_token")
+            .x_www_form_urlencoded_tuple("device_code", "device-1234");
+        then.status(200).json_body(json!({
+            "access_token": "gho_devicelogin",
+            "token_type": "bearer",
+            "scope": "repo",
+        }));
+    });
+
+    //
+    // Act.
+    //
+    let login = auth_login(home.path()).output()?;
+    let status = auth_status(home.path()).output()?;
+
+    //
+    // Assert.
+    //
+    let credentials = home
+        .path()
+        .join(".config")
+        .join("github-pull-request")
+        .join("credentials.toml");
+    assert_that!(String::from_utf8(login.stderr)?)
+        .contains("First copy your one-time code: WDJB-MJHT");
+    assert_that!(String::from_utf8(login.stdout)?).is_equal_to(format!(
+        "Logged in to github.com.\nThe token is saved in {}.\n",
+        credentials.display()
+    ));
+    assert_that!(login.status.success()).is_true();
+    device_code.assert();
+    access_token.assert();
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&credentials)?.permissions().mode();
+        assert_that!(mode & 0o777).is_equal_to(0o600);
+    }
+
+    assert_that!(String::from_utf8(status.stdout)?).is_equal_to(format!(
+        "github.com\n  Token: gho_********\n  Source: auth login ({})\n",
+        credentials.display()
+    ));
+
+    Ok(())
+}
+
+/// Tests that login fails when the user declines in the browser.
+#[test]
+fn login_denied() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let home = tempdir()?;
+    let github = MockServer::start();
+    write_login_config(home.path(), &github)?;
+    github.mock(|when, then| {
+        when.method(POST).path("/login/device/code");
+        then.status(200).json_body(json!({
+            "device_code": "device-1234",
+            "user_code": "WDJB-MJHT",
+            "verification_uri": "https://github.com/login/device",
+            "expires_in": 900,
+            "interval": 0,
+        }));
+    });
+    github.mock(|when, then| {
+        when.method(POST).path("/login/oauth/access_token");
+        then.status(200).json_body(json!({
+            "error": "access_denied",
+            "error_description": "The authorization request was denied.",
+        }));
+    });
+
+    //
+    // Act.
+    //
+    let output = auth_login(home.path()).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(String::from_utf8(output.stdout)?).is_empty();
+    assert_that!(String::from_utf8(output.stderr)?)
+        .ends_with("Could not log in: Access was denied.\n");
+    assert_that!(output.status.success()).is_false();
+    assert_that!(home
+        .path()
+        .join(".config")
+        .join("github-pull-request")
+        .join("credentials.toml")
+        .exists())
+    .is_false();
+
+    Ok(())
+}
+
+/// Tests the error when there is no token anywhere.
+#[test]
+fn no_token() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let home = tempdir()?;
+
+    //
+    // Act.
+    //
+    let output = auth_status(home.path()).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(String::from_utf8(output.stdout)?).is_empty();
+    assert_that!(String::from_utf8(output.stderr)?)
+        .starts_with("No Github token found for github.com.");
+    assert_that!(output.status.success()).is_false();
+
+    Ok(())
+}
+
+/// Builds an `auth status` command isolated from the configuration of the
+/// user running the tests.
+fn auth_status(home: &Path) -> Command {
+    auth_command(home, "status")
+}
+
+fn auth_login(home: &Path) -> Command {
+    auth_command(home, "login")
+}
+
+fn auth_command(home: &Path, subcommand: &str) -> Command {
+    let bin_under_test = escargot::CargoBuild::new()
+        .bin(TEST_BINARY)
+        .current_release()
+        .current_target()
+        .run()
+        .unwrap();
+
+    let mut command = bin_under_test.command();
+    command
+        .current_dir(home)
+        .env("HOME", home)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .args(["auth", subcommand]);
+    for variable in TOKEN_VARIABLES {
+        command.env_remove(variable);
+    }
+    command
+}
+
+/// Points the device flow for github.com at the mock server.
+fn write_login_config(home: &Path, github: &MockServer) -> Result<()> {
+    write_config(
+        home,
+        &format!(
+            r#"
+[hosts."github.com"]
+oauth_url = "{}"
+client_id = "test-client"
+"#,
+            github.base_url()
+        ),
+    )
+}
+
+fn write_gh_hosts(home: &Path, contents: &str) -> Result<()> {
+    let gh_config = home.join(".config").join("gh");
+    std::fs::create_dir_all(&gh_config)?;
+    std::fs::write(gh_config.join("hosts.yml"), contents)?;
+    Ok(())
+}
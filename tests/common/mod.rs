@@ -1,3 +1,5 @@
+#![allow(dead_code)]
+
 use std::fs::File;
 use std::path::Path;
 use std::path::PathBuf;
@@ -11,6 +13,19 @@ use tempfile::{tempdir, TempDir};
 
 pub const TEST_BINARY: &str = env!("CARGO_PKG_NAME");
 
+/// Environment variables that would otherwise leak a token, or the
+/// configuration it is found in, from the environment running the tests.
+pub const TOKEN_VARIABLES: &[&str] = &[
+    "GH_TOKEN",
+    "GITHUB_TOKEN",
+    "GH_ENTERPRISE_TOKEN",
+    "GITHUB_ENTERPRISE_TOKEN",
+    "GH_PR_TOKEN",
+    "GH_CONFIG_DIR",
+    "XDG_CONFIG_HOME",
+    "XDG_CACHE_HOME",
+];
+
 pub fn restore_git_repo(tar_gz: &str) -> Result<(TempDir, PathBuf)> {
     let mut repo_tar_gz = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     repo_tar_gz.push("tests");
@@ -70,3 +85,44 @@ pub fn current_branch_name(repository_path: &Path) -> Result<String> {
         Err(anyhow!("No branch selected."))
     }
 }
+
+/// Points `remote` at the Github repository `url`, so the command under test
+/// treats it as a Github remote, while pushes still go to the local repository
+/// `push_repo`.
+pub fn use_github_remote(
+    repository_path: &Path,
+    remote: &str,
+    url: &str,
+    push_repo: &Path,
+) -> Result<()> {
+    let repo = Repository::open(repository_path)?;
+
+    repo.remote_set_url(remote, url)?;
+    let push_repo = match push_repo.to_str() {
+        Some(p) => p,
+        None => return Err(anyhow!("Push repository path isn't valid UTF-8.")),
+    };
+    repo.remote_set_pushurl(remote, Some(push_repo))?;
+
+    Ok(())
+}
+
+/// Writes the user configuration file in the home directory `home`.
+pub fn write_config(home: &Path, contents: &str) -> Result<()> {
+    std::fs::write(home.join(".gh-pull-request.toml"), contents)?;
+    Ok(())
+}
+
+/// Checks whether the repository has a local branch called `name`.
+pub fn has_branch(repository_path: &Path, name: &str) -> Result<bool> {
+    let repo = Repository::open(repository_path)?;
+    let found = repo.find_branch(name, git2::BranchType::Local).is_ok();
+    Ok(found)
+}
+
+/// Checks whether HEAD is detached at the commit of the local branch `name`.
+pub fn is_detached_at(repository_path: &Path, name: &str) -> Result<bool> {
+    let repo = Repository::open(repository_path)?;
+    let branch = repo.find_branch(name, git2::BranchType::Local)?;
+    Ok(repo.head_detached()? && repo.head()?.target() == branch.get().target())
+}
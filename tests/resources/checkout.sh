@@ -0,0 +1,39 @@
+#! /bin/bash
+
+cd $(dirname $0)
+mkdir tmp
+cd tmp
+
+#
+# Create the remote repository, with a stack of two pull requests. Github keeps
+# the head of each pull request as refs/pull/<number>/head.
+#
+mkdir remote_repo
+(
+    cd remote_repo
+
+    git init -b main .
+    echo "Some text" > README.md
+    git add README.md
+    git commit -m "Initial commit."
+    git checkout -b stack-1
+    echo "More text" > file1.txt
+    git add file1.txt
+    git commit -m "Commit 2."
+    git update-ref refs/pull/9/head HEAD
+    git checkout -b stack-2
+    echo "Even more text" > file2.txt
+    git add file2.txt
+    git commit -m "Commit 3."
+    git update-ref refs/pull/10/head HEAD
+    git checkout main
+)
+
+#
+# Clone the remote repository.
+#
+git clone remote_repo local_repo
+
+tar -zcf ../$(echo $(basename $0) | sed 's|\.sh||').tar.gz .
+cd ..
+rm -Rf tmp
@@ -0,0 +1,63 @@
+#! /bin/bash
+
+cd $(dirname $0)
+mkdir tmp
+cd tmp
+
+#
+# Create the remote repository.
+#
+mkdir remote_repo
+(
+    cd remote_repo
+
+    git init -b main .
+    echo "Some text" > README.md
+    git add README.md
+    git commit -m "Initial commit."
+)
+
+#
+# Clone the remote repository, and leave branches in all the states cleanup
+# looks for.
+#
+git clone remote_repo local_repo
+(
+    cd local_repo
+
+    # In the main branch already.
+    git branch old
+
+    # Squash merged into the main branch.
+    git checkout -b squashed
+    echo "Squashed 1" > squashed1.txt
+    git add squashed1.txt
+    git commit -m "Squashed 1."
+    echo "Squashed 2" > squashed2.txt
+    git add squashed2.txt
+    git commit -m "Squashed 2."
+    git checkout main
+    git merge --squash squashed
+    git commit -m "Squashed (#3)."
+
+    # With a merged pull request.
+    git checkout -b commit-2 main
+    echo "More text" > file1.txt
+    git add file1.txt
+    git commit -m "Commit 2."
+    git push -u origin commit-2
+
+    # With an open pull request.
+    git checkout -b wip main
+    echo "Work in progress" > file3.txt
+    git add file3.txt
+    git commit -m "Work in progress."
+    git push -u origin wip
+
+    # In the main branch, but checked out.
+    git checkout -b current main
+)
+
+tar -zcf ../$(echo $(basename $0) | sed 's|\.sh||').tar.gz .
+cd ..
+rm -Rf tmp
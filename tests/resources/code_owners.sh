@@ -0,0 +1,39 @@
+#! /bin/bash
+
+cd $(dirname $0)
+mkdir tmp
+cd tmp
+
+#
+# Create the remote repository, with code owners.
+#
+mkdir remote_repo
+(
+    cd remote_repo
+
+    git init -b main .
+    echo "Some text" > README.md
+    mkdir .github docs
+    printf '* @owner\ndocs/ @writer @owner/docs\n' > .github/CODEOWNERS
+    echo "Documentation" > docs/guide.md
+    git add README.md .github docs
+    git commit -m "Initial commit."
+)
+
+#
+# Clone the remote repository.
+#
+git clone remote_repo local_repo
+(
+    cd local_repo
+
+    # Switch from the branch name to the hash as the currently selected pointer.
+    git checkout $(git rev-parse HEAD)
+    echo "More documentation" >> docs/guide.md
+    git add docs/guide.md
+    git commit -m "Commit 2."
+)
+
+tar -zcf ../$(echo $(basename $0) | sed 's|\.sh||').tar.gz .
+cd ..
+rm -Rf tmp
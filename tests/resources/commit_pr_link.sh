@@ -0,0 +1,51 @@
+#! /bin/bash
+
+cd $(dirname $0)
+mkdir tmp
+cd tmp
+
+#
+# Create the remote repository.
+#
+mkdir remote_repo
+(
+    cd remote_repo
+
+    git init -b main .
+    echo "Some text" > README.md
+    git add README.md
+    git commit -m "Initial commit."
+)
+
+#
+# Clone the remote repository, and stack a pushed commit on the one to create
+# the pull request for. The post-rewrite and reference-transaction hooks keep
+# what they are given, the way git-branchless would record it.
+#
+git clone remote_repo local_repo
+(
+    cd local_repo
+
+    # Switch from the branch name to the hash as the currently selected pointer.
+    git checkout $(git rev-parse HEAD)
+    echo "More text" > file1.txt
+    git add file1.txt
+    git commit -m "Commit 2."
+    git branch commit-2
+    echo "Even more text" > file2.txt
+    git add file2.txt
+    git commit -m "Commit 3."
+    git branch commit-3
+    git push -u origin commit-3
+    git checkout commit-2
+    git checkout $(git rev-parse HEAD)
+
+    printf '#! /bin/sh\ncat > .git/rewritten\n' > .git/hooks/post-rewrite
+    chmod +x .git/hooks/post-rewrite
+    printf '#! /bin/sh\n[ "$1" = committed ] && cat >> .git/transactions\nexit 0\n' > .git/hooks/reference-transaction
+    chmod +x .git/hooks/reference-transaction
+)
+
+tar -zcf ../$(echo $(basename $0) | sed 's|\.sh||').tar.gz .
+cd ..
+rm -Rf tmp
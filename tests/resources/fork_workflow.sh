@@ -0,0 +1,44 @@
+#! /bin/bash
+
+cd $(dirname $0)
+mkdir tmp
+cd tmp
+
+#
+# Create the upstream repository.
+#
+mkdir remote_repo
+(
+    cd remote_repo
+
+    git init -b main .
+    echo "Some text" > README.md
+    git add README.md
+    git commit -m "Initial commit."
+)
+
+#
+# Create the fork of the upstream repository.
+#
+git clone --bare remote_repo fork_repo
+
+#
+# Clone the upstream repository, and add the fork as `origin`.
+#
+git clone --origin upstream remote_repo local_repo
+(
+    cd local_repo
+
+    git remote add origin ../fork_repo
+    git fetch origin
+
+    # Switch from the branch name to the hash as the currently selected pointer.
+    git checkout $(git rev-parse HEAD)
+    echo "More text" > file1.txt
+    git add file1.txt
+    git commit -m "Commit 2."
+)
+
+tar -zcf ../$(echo $(basename $0) | sed 's|\.sh||').tar.gz .
+cd ..
+rm -Rf tmp
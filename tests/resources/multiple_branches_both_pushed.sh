@@ -0,0 +1,40 @@
+#! /bin/bash
+
+cd $(dirname $0)
+mkdir tmp
+cd tmp
+
+#
+# Create the remote repository.
+#
+mkdir remote_repo
+(
+    cd remote_repo
+
+    git init -b main .
+    echo "Some text" > README.md
+    git add README.md
+    git commit -m "Initial commit."
+)
+
+#
+# Clone the remote repository.
+#
+git clone remote_repo local_repo
+(
+    cd local_repo
+
+    # Switch from the branch name to the hash as the currently selected pointer.
+    git checkout $(git rev-parse HEAD)
+    echo "More text" > file1.txt
+    git add file1.txt
+    git commit -m "Commit 2."
+    git branch first-branch
+    git branch second-branch
+    git push -u origin first-branch
+    git push -u origin second-branch
+)
+
+tar -zcf ../$(echo $(basename $0) | sed 's|\.sh||').tar.gz .
+cd ..
+rm -Rf tmp
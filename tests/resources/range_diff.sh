@@ -0,0 +1,42 @@
+#! /bin/bash
+
+cd $(dirname $0)
+mkdir tmp
+cd tmp
+
+#
+# Create the remote repository.
+#
+mkdir remote_repo
+(
+    cd remote_repo
+
+    git init -b main .
+    echo "Some text" > README.md
+    git add README.md
+    git commit -m "Initial commit."
+)
+
+#
+# Clone the remote repository, push a branch, and then amend its commit.
+#
+git clone remote_repo local_repo
+(
+    cd local_repo
+
+    # Switch from the branch name to the hash as the currently selected pointer.
+    git checkout $(git rev-parse HEAD)
+    printf "One\nTwo\nThree\nFour\nFive\n" > file1.txt
+    git add file1.txt
+    git commit -m "Commit 2."
+    git branch commit-2
+    git push -u origin commit-2
+    printf "One\nTwo\nChanged\nFour\nFive\n" > file1.txt
+    git add file1.txt
+    git commit --amend --no-edit
+    git branch -f commit-2
+)
+
+tar -zcf ../$(echo $(basename $0) | sed 's|\.sh||').tar.gz .
+cd ..
+rm -Rf tmp
@@ -1,11 +1,22 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
 use anyhow::Result;
-use escargot::error::CargoResult;
 use escargot::CargoRun;
+use httpmock::prelude::*;
+use httpmock::Method::PATCH;
+use serde_json::json;
 use speculoos::prelude::*;
+use tempfile::TempDir;
 
 use crate::common::current_branch_name;
+use crate::common::has_branch;
+use crate::common::is_detached_at;
 use crate::common::restore_git_repo;
+use crate::common::use_github_remote;
+use crate::common::write_config;
 use crate::common::TEST_BINARY;
+use crate::common::TOKEN_VARIABLES;
 
 mod common;
 
@@ -29,17 +40,6 @@ macro_rules! tar_gz {
     }};
 }
 
-/// Provides some syntactic sugar for running the `ghpr` binary.
-macro_rules! run {
-    ($local_repo:ident -> $ghpr:ident $command:ident) => {
-        $ghpr
-            .command()
-            .current_dir(&$local_repo)
-            .arg(stringify!($command))
-            .output()?
-    };
-}
-
 macro_rules! stdout {
     ($output:ident) => {
         String::from_utf8($output.stdout.clone())
@@ -59,13 +59,13 @@ fn initialized_no_commits() -> Result<()> {
     //
     // Arrange.
     //
-    let (_temp_dir, local_repo) = restore_git_repo(&tar_gz!())?;
-    let ghpr = get_test_binary()?;
+    let (temp_dir, local_repo) = restore_git_repo(&tar_gz!())?;
+    let ghpr = Ghpr::new(temp_dir.path(), &local_repo)?;
 
     //
     // Act.
     //
-    let output = run!(local_repo -> ghpr create);
+    let output = ghpr.command("create", &[]).output()?;
 
     //
     // Assert.
@@ -84,13 +84,13 @@ fn existing_branch() -> Result<()> {
     //
     // Arrange.
     //
-    let (_temp_dir, local_repo) = restore_git_repo(&tar_gz!())?;
-    let ghpr = get_test_binary()?;
+    let (temp_dir, local_repo) = restore_git_repo(&tar_gz!())?;
+    let ghpr = Ghpr::new(temp_dir.path(), &local_repo)?;
 
     //
     // Act.
     //
-    let output = run!(local_repo -> ghpr create);
+    let output = ghpr.command("create", &[]).output()?;
 
     //
     // Assert.
@@ -110,29 +110,498 @@ fn existing_branch() -> Result<()> {
 /// ● e9f4920 22d Commit 2.
 ///
 /// This should result in the creation of a new branch with a name based on the
-/// configured branch name template.
+/// configured branch name template, which is pushed, and a pull request opened
+/// for it.
 #[test]
 fn no_branch() -> Result<()> {
     //
     // Arrange.
     //
-    let (_temp_dir, local_repo) = restore_git_repo(&tar_gz!())?;
-    let ghpr = get_test_binary()?;
+    let (_temp_dir, local_repo, remote_repo, github, ghpr) =
+        github_repo(&tar_gz!())?;
+
+    let find = github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("head", "owner:commit-2")
+            .header("authorization", "Bearer test-token");
+        then.status(200).json_body(json!([]));
+    });
+    let children = github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("base", "commit-2");
+        then.status(200).json_body(json!([]));
+    });
+    let create = github.mock(|when, then| {
+        when.method(POST)
+            .path("/repos/owner/repo/pulls")
+            .json_body_partial(
+                r#"{"head": "owner:commit-2", "base": "main", "title": "Commit 2."}"#,
+            );
+        then.status(201).json_body(json!({
+            "number": 1,
+            "html_url": "https://github.com/owner/repo/pull/1",
+        }));
+    });
 
     //
     // Act.
     //
-    let output = run!(local_repo -> ghpr create);
+    let output = ghpr.command("create", &[]).output()?;
 
     //
     // Assert.
     //
-    assert_that!(stdout!(output)?).is_empty();
     assert_that!(stderr!(output)?).is_empty();
+    assert_that!(stdout!(output)?).is_equal_to(
+        "Created pull request https://github.com/owner/repo/pull/1\n"
+            .to_string(),
+    );
     assert_that!(output.status.success()).is_true();
     assert_that!(current_branch_name(local_repo.as_path()))
         .is_ok()
         .is_equal_to("refs/heads/commit-2".to_string());
+    assert_that!(has_branch(&remote_repo, "commit-2"))
+        .is_ok()
+        .is_true();
+    find.assert();
+    children.assert();
+    create.assert();
+
+    Ok(())
+}
+
+/// Tests that `undo --remote` after `create` closes the pull request, deletes
+/// the branch from the remote and locally, and detaches HEAD again.
+#[test]
+fn undo() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (_temp_dir, local_repo, remote_repo, github, ghpr) =
+        github_repo("no_branch.tar.gz")?;
+
+    github.mock(|when, then| {
+        when.method(GET).path("/repos/owner/repo/pulls");
+        then.status(200).json_body(json!([]));
+    });
+    github.mock(|when, then| {
+        when.method(POST).path("/repos/owner/repo/pulls");
+        then.status(201).json_body(json!({
+            "number": 1,
+            "html_url": "https://github.com/owner/repo/pull/1",
+        }));
+    });
+    let close = github.mock(|when, then| {
+        when.method(PATCH)
+            .path("/repos/owner/repo/pulls/1")
+            .json_body(json!({"state": "closed"}));
+        then.status(200).json_body(json!({
+            "number": 1,
+            "html_url": "https://github.com/owner/repo/pull/1",
+        }));
+    });
+    let created = ghpr.command("create", &[]).output()?;
+    assert_that!(created.status.success()).is_true();
+
+    //
+    // Act.
+    //
+    let output = ghpr.command("undo", &["--remote"]).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).is_empty();
+    let stdout = stdout!(output)?;
+    assert_that!(stdout).starts_with("Undid create.\n");
+    assert_that!(stdout)
+        .contains("Closed pull request https://github.com/owner/repo/pull/1\n");
+    assert_that!(stdout).contains("Deleted refs/heads/commit-2 from origin\n");
+    assert_that!(stdout).ends_with("Deleted refs/heads/commit-2\n");
+    assert_that!(output.status.success()).is_true();
+    assert_that!(has_branch(&local_repo, "commit-2"))
+        .is_ok()
+        .is_false();
+    assert_that!(has_branch(&remote_repo, "commit-2"))
+        .is_ok()
+        .is_false();
+    assert_that!(git2::Repository::open(&local_repo)?.head_detached()?)
+        .is_true();
+    close.assert();
+
+    let again = ghpr.command("undo", &[]).output()?;
+    assert_that!(stderr!(again)?)
+        .is_equal_to("There is nothing to undo.\n".to_string());
+
+    Ok(())
+}
+
+/// Tests that `undo --remote` puts back a remote branch that was there before
+/// `create` pushed over it, rather than deleting it.
+#[test]
+fn undo_restores_remote_branch() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (_temp_dir, local_repo, remote_repo, github, ghpr) =
+        github_repo("no_branch.tar.gz")?;
+    let main = git2::Repository::open(&remote_repo)?
+        .revparse_single("main")?
+        .id();
+    git2::Repository::open(&remote_repo)?.reference(
+        "refs/heads/commit-2",
+        main,
+        false,
+        "test",
+    )?;
+    git2::Repository::open(&local_repo)?.reference(
+        "refs/remotes/origin/commit-2",
+        main,
+        false,
+        "test",
+    )?;
+
+    github.mock(|when, then| {
+        when.method(GET).path("/repos/owner/repo/pulls");
+        then.status(200).json_body(json!([]));
+    });
+    github.mock(|when, then| {
+        when.method(POST).path("/repos/owner/repo/pulls");
+        then.status(201).json_body(json!({
+            "number": 1,
+            "html_url": "https://github.com/owner/repo/pull/1",
+        }));
+    });
+    github.mock(|when, then| {
+        when.method(PATCH).path("/repos/owner/repo/pulls/1");
+        then.status(200).json_body(json!({
+            "number": 1,
+            "html_url": "https://github.com/owner/repo/pull/1",
+        }));
+    });
+    let created = ghpr.command("create", &[]).output()?;
+    assert_that!(created.status.success()).is_true();
+
+    //
+    // Act.
+    //
+    let output = ghpr.command("undo", &["--remote"]).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).is_empty();
+    assert_that!(stdout!(output)?).contains(format!(
+        "Moved refs/heads/commit-2 on origin back to {:.7}\n",
+        main.to_string()
+    ));
+    assert_that!(git2::Repository::open(&remote_repo)?
+        .revparse_single("commit-2")?
+        .id())
+    .is_equal_to(main);
+
+    Ok(())
+}
+
+/// Sets up the `no_branch` repository for a `create` that fails after the
+/// branch has been pushed, with `then` for the response to opening the pull
+/// request.
+fn failing_create(
+    then: impl FnOnce(httpmock::Then),
+) -> Result<(TempDir, PathBuf, PathBuf, MockServer, Ghpr)> {
+    let (temp_dir, local_repo, remote_repo, github, ghpr) =
+        github_repo("no_branch.tar.gz")?;
+    github.mock(|when, then| {
+        when.method(GET).path("/repos/owner/repo/pulls");
+        then.status(200).json_body(json!([]));
+    });
+    github.mock(|when, then_| {
+        when.method(POST).path("/repos/owner/repo/pulls");
+        then(then_);
+    });
+    Ok((temp_dir, local_repo, remote_repo, github, ghpr))
+}
+
+/// Tests that when opening the pull request fails, the branch `create` made
+/// is deleted and HEAD detached again, while the push is left for
+/// `undo --remote`.
+#[test]
+fn rolled_back_when_create_fails() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (_temp_dir, local_repo, remote_repo, _github, ghpr) =
+        failing_create(|then| {
+            then.status(422)
+                .json_body(json!({"message": "Validation Failed"}));
+        })?;
+
+    //
+    // Act.
+    //
+    let output = ghpr.command("create", &[]).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(output.status.success()).is_false();
+    assert_that!(stderr!(output)?).ends_with(
+        "\nThe local branches and HEAD were put back as they were. What was \
+         pushed, and any pull request opened, can be undone with `git ghpr \
+         undo --remote`.\n",
+    );
+    assert_that!(has_branch(&local_repo, "commit-2"))
+        .is_ok()
+        .is_false();
+    assert_that!(git2::Repository::open(&local_repo)?.head_detached()?)
+        .is_true();
+    assert_that!(has_branch(&remote_repo, "commit-2"))
+        .is_ok()
+        .is_true();
+
+    let undone = ghpr.command("undo", &["--remote"]).output()?;
+    assert_that!(stdout!(undone)?).is_equal_to(
+        "Undid create.\nDeleted refs/heads/commit-2 from origin\n".to_string(),
+    );
+    assert_that!(has_branch(&remote_repo, "commit-2"))
+        .is_ok()
+        .is_false();
+
+    Ok(())
+}
+
+/// Tests that when the push is rejected, because the remote branch exists and
+/// hasn't been fetched, rolling back leaves the remote branch alone.
+#[test]
+fn rolled_back_when_push_rejected() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (_temp_dir, local_repo, remote_repo, _github, ghpr) =
+        failing_create(|then| {
+            then.status(201).json_body(json!({
+                "number": 1,
+                "html_url": "https://github.com/owner/repo/pull/1",
+            }));
+        })?;
+    let remote = git2::Repository::open(&remote_repo)?;
+    let main = remote.revparse_single("main")?.id();
+    remote.reference("refs/heads/commit-2", main, false, "test")?;
+
+    //
+    // Act.
+    //
+    let output = ghpr.command("create", &[]).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).starts_with("Could not push commit-2");
+    assert_that!(stderr!(output)?).ends_with(
+        "\nThe local branches and HEAD were put back as they were.\n",
+    );
+    assert_that!(output.status.success()).is_false();
+    assert_that!(has_branch(&local_repo, "commit-2"))
+        .is_ok()
+        .is_false();
+    assert_that!(remote.revparse_single("commit-2")?.id()).is_equal_to(main);
+
+    Ok(())
+}
+
+/// Tests that when HEAD can't be moved onto the branch `create` made, the
+/// branch isn't left behind.
+#[test]
+fn rolled_back_when_head_locked() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (_temp_dir, local_repo, remote_repo, _github, ghpr) =
+        failing_create(|then| {
+            then.status(201).json_body(json!({
+                "number": 1,
+                "html_url": "https://github.com/owner/repo/pull/1",
+            }));
+        })?;
+    std::fs::write(local_repo.join(".git/HEAD.lock"), "")?;
+
+    //
+    // Act.
+    //
+    let output = ghpr.command("create", &[]).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).is_equal_to(
+        "Could not switch to branch 'commit-2'.\nThe local branches and HEAD \
+         were put back as they were.\n"
+            .to_string(),
+    );
+    assert_that!(output.status.success()).is_false();
+    assert_that!(has_branch(&local_repo, "commit-2"))
+        .is_ok()
+        .is_false();
+    assert_that!(has_branch(&remote_repo, "commit-2"))
+        .is_ok()
+        .is_false();
+
+    Ok(())
+}
+
+/// Tests that a repository on a Github Enterprise Server host uses the API
+/// endpoint and token configured for that host.
+#[test]
+fn enterprise_host() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (temp_dir, local_repo, remote_repo, github, ghpr) =
+        github_repo("no_branch.tar.gz")?;
+    use_github_remote(
+        &local_repo,
+        "origin",
+        "https://github.example.com/owner/repo.git",
+        &remote_repo,
+    )?;
+    write_config(
+        temp_dir.path(),
+        &format!(
+            r#"
+token = "github-token"
+
+[hosts."github.example.com"]
+api_url = "{}"
+token = "enterprise-token"
+"#,
+            github.url("/api/v3")
+        ),
+    )?;
+    let find = github.mock(|when, then| {
+        when.method(GET)
+            .path("/api/v3/repos/owner/repo/pulls")
+            .query_param_exists("head")
+            .header("authorization", "Bearer enterprise-token");
+        then.status(200).json_body(json!([]));
+    });
+    let children = github.mock(|when, then| {
+        when.method(GET)
+            .path("/api/v3/repos/owner/repo/pulls")
+            .query_param("base", "commit-2")
+            .header("authorization", "Bearer enterprise-token");
+        then.status(200).json_body(json!([]));
+    });
+    let create = github.mock(|when, then| {
+        when.method(POST)
+            .path("/api/v3/repos/owner/repo/pulls")
+            .header("authorization", "Bearer enterprise-token");
+        then.status(201).json_body(json!({
+            "number": 2,
+            "html_url": "https://github.example.com/owner/repo/pull/2",
+        }));
+    });
+
+    //
+    // Act.
+    //
+    let output = ghpr.command("create", &[]).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).is_empty();
+    assert_that!(stdout!(output)?).is_equal_to(
+        "Created pull request https://github.example.com/owner/repo/pull/2\n"
+            .to_string(),
+    );
+    assert_that!(output.status.success()).is_true();
+    find.assert();
+    children.assert();
+    create.assert();
+
+    Ok(())
+}
+
+/// Tests that a commit is pushed to a fork, and the pull request opened
+/// against the upstream repository, when the push and pull request remotes
+/// are configured differently.
+///
+/// ◇ 018debe (main) Initial commit.
+/// ┃
+/// ● 1b21037 Commit 2.
+#[test]
+fn fork_workflow() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (temp_dir, local_repo, remote_repo, github, ghpr) =
+        github_repo(&tar_gz!())?;
+    let fork_repo = temp_dir.path().join("fork_repo");
+    use_github_remote(
+        &local_repo,
+        "upstream",
+        "https://github.com/owner/repo.git",
+        &remote_repo,
+    )?;
+    use_github_remote(
+        &local_repo,
+        "origin",
+        "git@github.com:contributor/repo.git",
+        &fork_repo,
+    )?;
+    let find = github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("head", "contributor:commit-2");
+        then.status(200).json_body(json!([]));
+    });
+    let children = github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("base", "commit-2");
+        then.status(200).json_body(json!([]));
+    });
+    let create = github.mock(|when, then| {
+        when.method(POST)
+            .path("/repos/owner/repo/pulls")
+            .json_body_partial(
+                r#"{"head": "contributor:commit-2", "base": "main"}"#,
+            );
+        then.status(201).json_body(json!({
+            "number": 5,
+            "html_url": "https://github.com/owner/repo/pull/5",
+        }));
+    });
+
+    //
+    // Act.
+    //
+    let output = ghpr
+        .command("create", &[])
+        .env("GH_PR_PUSH_REMOTE", "origin")
+        .env("GH_PR_PR_REMOTE", "upstream")
+        .output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).is_empty();
+    assert_that!(stdout!(output)?).is_equal_to(
+        "Created pull request https://github.com/owner/repo/pull/5\n"
+            .to_string(),
+    );
+    assert_that!(output.status.success()).is_true();
+    assert_that!(has_branch(&fork_repo, "commit-2"))
+        .is_ok()
+        .is_true();
+    assert_that!(has_branch(&remote_repo, "commit-2"))
+        .is_ok()
+        .is_false();
+    find.assert();
+    children.assert();
+    create.assert();
 
     Ok(())
 }
@@ -144,13 +613,13 @@ fn unknown_main_branch() -> Result<()> {
     //
     // Arrange.
     //
-    let (_temp_dir, local_repo) = restore_git_repo(&tar_gz!())?;
-    let ghpr = get_test_binary()?;
+    let (temp_dir, local_repo) = restore_git_repo(&tar_gz!())?;
+    let ghpr = Ghpr::new(temp_dir.path(), &local_repo)?;
 
     //
     // Act.
     //
-    let output = run!(local_repo -> ghpr create);
+    let output = ghpr.command("create", &[]).output()?;
 
     //
     // Assert.
@@ -175,13 +644,13 @@ fn base_branch_not_remote() -> Result<()> {
     //
     // Arrange.
     //
-    let (_temp_dir, local_repo) = restore_git_repo(&tar_gz!())?;
-    let ghpr = get_test_binary()?;
+    let (temp_dir, local_repo) = restore_git_repo(&tar_gz!())?;
+    let ghpr = Ghpr::new(temp_dir.path(), &local_repo)?;
 
     //
     // Act.
     //
-    let output = run!(local_repo -> ghpr create);
+    let output = ghpr.command("create", &[]).output()?;
 
     //
     // Assert.
@@ -198,10 +667,1832 @@ fn base_branch_not_remote() -> Result<()> {
     Ok(())
 }
 
-fn get_test_binary() -> CargoResult<CargoRun> {
-    escargot::CargoBuild::new()
-        .bin(TEST_BINARY)
-        .current_release()
-        .current_target()
-        .run()
+/// Tests what happens for `create` on a commit that has two branches, with
+/// nothing to choose between them, and no terminal to ask the user.
+///
+/// ◇ 744b880 (main) Initial commit.
+/// ┃
+/// ● 346908e (first-branch, second-branch) Commit 2.
+#[test]
+fn multiple_branches() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (temp_dir, local_repo) = restore_git_repo(&tar_gz!())?;
+    let ghpr = Ghpr::new(temp_dir.path(), &local_repo)?;
+
+    //
+    // Act.
+    //
+    let output = ghpr.command("create", &[]).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stdout!(output)?).is_empty();
+    assert_that!(stderr!(output)?).starts_with(
+        "Commit 346908e271a410fb3ecdfacfde417b863be31592 has multiple branches: first-branch, second-branch.",
+    );
+    assert_that!(output.status.success()).is_false();
+
+    Ok(())
+}
+
+/// Tests that when a commit has two branches, the one that has already been
+/// pushed is used for the pull request.
+#[test]
+fn multiple_branches_one_pushed() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (_temp_dir, _, _, github, ghpr) = github_repo(&tar_gz!())?;
+
+    let find = github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("head", "owner:second-branch");
+        then.status(200).json_body(json!([{
+            "number": 7,
+            "html_url": "https://github.com/owner/repo/pull/7",
+        }]));
+    });
+
+    //
+    // Act.
+    //
+    let output = ghpr.command("create", &[]).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).is_empty();
+    assert_that!(stdout!(output)?).is_equal_to(
+        "Updated pull request https://github.com/owner/repo/pull/7\n"
+            .to_string(),
+    );
+    assert_that!(output.status.success()).is_true();
+    find.assert();
+
+    Ok(())
+}
+
+/// Tests that when a commit has two pushed branches, the one with an open pull
+/// request is used for it.
+#[test]
+fn multiple_branches_one_open() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (_temp_dir, _, _, github, ghpr) =
+        github_repo("multiple_branches_both_pushed.tar.gz")?;
+    let closed = github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("head", "owner:first-branch");
+        then.status(200).json_body(json!([]));
+    });
+    github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("head", "owner:second-branch");
+        then.status(200).json_body(json!([{
+            "number": 7,
+            "html_url": "https://github.com/owner/repo/pull/7",
+        }]));
+    });
+
+    //
+    // Act.
+    //
+    let output = ghpr.command("create", &[]).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).is_empty();
+    assert_that!(stdout!(output)?).is_equal_to(
+        "Updated pull request https://github.com/owner/repo/pull/7\n"
+            .to_string(),
+    );
+    assert_that!(output.status.success()).is_true();
+    closed.assert();
+
+    Ok(())
+}
+
+/// Tests that when a commit has two branches, the one matching the branch name
+/// template is used for the pull request.
+#[test]
+fn multiple_branches_one_templated() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (_temp_dir, _, remote_repo, github, ghpr) = github_repo(&tar_gz!())?;
+
+    let find = github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("head", "owner:commit-2");
+        then.status(200).json_body(json!([{
+            "number": 8,
+            "html_url": "https://github.com/owner/repo/pull/8",
+        }]));
+    });
+
+    //
+    // Act.
+    //
+    let output = ghpr.command("create", &[]).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).is_empty();
+    assert_that!(stdout!(output)?).is_equal_to(
+        "Updated pull request https://github.com/owner/repo/pull/8\n"
+            .to_string(),
+    );
+    assert_that!(output.status.success()).is_true();
+    assert_that!(has_branch(&remote_repo, "commit-2"))
+        .is_ok()
+        .is_true();
+    find.assert();
+
+    Ok(())
+}
+
+/// Tests that when the commit of an existing pull request has been amended, a
+/// range-diff from the pushed commit is added to the pull request.
+#[test]
+fn range_diff() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (_temp_dir, _, remote_repo, github, ghpr) = github_repo(&tar_gz!())?;
+    let pushed = git2::Repository::open(&remote_repo)?
+        .find_branch("commit-2", git2::BranchType::Local)?
+        .get()
+        .peel_to_commit()?
+        .id();
+
+    github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("head", "owner:commit-2");
+        then.status(200).json_body(json!([{
+            "number": 8,
+            "html_url": "https://github.com/owner/repo/pull/8",
+            "head": {"ref": "commit-2", "sha": pushed.to_string()},
+        }]));
+    });
+    let comment = github.mock(|when, then| {
+        when.method(POST)
+            .path("/repos/owner/repo/issues/8/comments")
+            .body_contains(format!("Changes from {:.7}", pushed.to_string()))
+            .body_contains("-+Three")
+            .body_contains("++Changed");
+        then.status(201).json_body(json!({"id": 1}));
+    });
+
+    //
+    // Act.
+    //
+    let output = ghpr.command("create", &[]).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).is_empty();
+    assert_that!(stdout!(output)?).is_equal_to(
+        "Updated pull request https://github.com/owner/repo/pull/8\n"
+            .to_string(),
+    );
+    assert_that!(output.status.success()).is_true();
+    comment.assert();
+
+    Ok(())
+}
+
+/// Tests that the commit pushed for a pull request is recorded as a revision,
+/// and pushed when configured to be, and that `revisions` lists it.
+#[test]
+fn revisions() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (temp_dir, local_repo, remote_repo, github, ghpr) =
+        github_repo("range_diff.tar.gz")?;
+    let amended = git2::Repository::open(&local_repo)?
+        .find_branch("commit-2", git2::BranchType::Local)?
+        .get()
+        .peel_to_commit()?
+        .id();
+
+    write_github_config(temp_dir.path(), &github, "push_revisions = true\n")?;
+    github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("head", "owner:commit-2");
+        then.status(200).json_body(json!([{
+            "number": 8,
+            "html_url": "https://github.com/owner/repo/pull/8",
+        }]));
+    });
+
+    //
+    // Act.
+    //
+    let create = ghpr.command("create", &[]).output()?;
+    let list = ghpr.command("revisions", &["8"]).output()?;
+    let diff = ghpr
+        .command("revisions", &["8", "--diff", "1", "2"])
+        .output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(create)?).is_empty();
+    assert_that!(create.status.success()).is_true();
+    assert_that!(git2::Repository::open(&remote_repo)?
+        .find_reference("refs/ghpr/revisions/8/1")?
+        .peel_to_commit()?
+        .id())
+    .is_equal_to(amended);
+
+    assert_that!(stderr!(list)?).is_empty();
+    assert_that!(stdout!(list)?)
+        .starts_with(format!("Revision 1: {:.7} pushed ", amended.to_string()));
+    assert_that!(stdout!(list)?.lines().count()).is_equal_to(1);
+
+    assert_that!(stderr!(diff)?).is_equal_to(
+        "Pull request #8 has no revision 2. `git ghpr revisions 8` lists them.\n"
+            .to_string(),
+    );
+    assert_that!(diff.status.success()).is_false();
+
+    Ok(())
+}
+
+/// Tests that the pull request is noted on the commit pushed for it, that git
+/// is set up to keep the note when the commit is rewritten, and that the notes
+/// are pushed when configured to be.
+#[test]
+fn notes() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (temp_dir, local_repo, remote_repo, github, ghpr) =
+        github_repo("range_diff.tar.gz")?;
+
+    github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("head", "owner:commit-2");
+        then.status(200).json_body(json!([{
+            "number": 8,
+            "html_url": "https://github.com/owner/repo/pull/8",
+        }]));
+    });
+
+    //
+    // Act.
+    //
+    write_github_config(temp_dir.path(), &github, "sync_notes = true\n")?;
+    let output = ghpr
+        .command("create", &[])
+        // Fetches go to the local stand-in for the remote too.
+        .env("GIT_CONFIG_COUNT", "1")
+        .env(
+            "GIT_CONFIG_KEY_0",
+            format!("url.{}.insteadOf", remote_repo.display()),
+        )
+        .env("GIT_CONFIG_VALUE_0", "https://github.com/owner/repo.git")
+        .output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).is_empty();
+    assert_that!(output.status.success()).is_true();
+
+    let repo = git2::Repository::open(&local_repo)?;
+    let commit = repo
+        .find_branch("commit-2", git2::BranchType::Local)?
+        .get()
+        .peel_to_commit()?
+        .id();
+    let note = repo.find_note(Some("refs/notes/ghpr"), commit)?;
+    assert_that!(note.message()).is_equal_to(Some(
+        r#"Pull-Request: #8
+URL: https://github.com/owner/repo/pull/8
+Branch: commit-2
+"#,
+    ));
+    assert_that!(repo.config()?.get_string("notes.rewriteRef")?)
+        .is_equal_to("refs/notes/ghpr".to_string());
+    assert_that!(git2::Repository::open(&remote_repo)?
+        .find_note(Some("refs/notes/ghpr"), commit)
+        .is_ok())
+    .is_true();
+
+    Ok(())
+}
+
+/// Tests that with `commit_pr_link`, the commit is reworded to link to the pull
+/// request once it is created, the commit stacked on it is moved onto the new
+/// commit, both branches are pushed again, and the rewrite is passed to the
+/// post-rewrite hook, and the moved branches to the reference-transaction
+/// hook.
+///
+/// ◇ (main) Initial commit.
+/// ┃
+/// ● (commit-2) Commit 2.
+/// ┃
+/// ◯ (commit-3) Commit 3.
+#[test]
+fn commit_pr_link() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (temp_dir, local_repo, remote_repo, github, ghpr) =
+        github_repo(&tar_gz!())?;
+
+    write_github_config(
+        temp_dir.path(),
+        &github,
+        "commit_pr_link = \"summary\"\n",
+    )?;
+    github.mock(|when, then| {
+        when.method(GET).path("/repos/owner/repo/pulls");
+        then.status(200).json_body(json!([]));
+    });
+    let create = github.mock(|when, then| {
+        when.method(POST)
+            .path("/repos/owner/repo/pulls")
+            .json_body_partial(r#"{"head": "owner:commit-2", "base": "main"}"#);
+        then.status(201).json_body(json!({
+            "number": 1,
+            "html_url": "https://github.com/owner/repo/pull/1",
+        }));
+    });
+
+    //
+    // Act.
+    //
+    let output = ghpr.command("create", &[]).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).is_empty();
+    assert_that!(stdout!(output)?).is_equal_to(
+        "Created pull request https://github.com/owner/repo/pull/1\n"
+            .to_string(),
+    );
+    assert_that!(output.status.success()).is_true();
+    create.assert();
+
+    let local = git2::Repository::open(&local_repo)?;
+    let remote = git2::Repository::open(&remote_repo)?;
+    let tip = |repo: &git2::Repository, name| -> Result<git2::Oid> {
+        Ok(repo
+            .find_branch(name, git2::BranchType::Local)?
+            .get()
+            .peel_to_commit()?
+            .id())
+    };
+    let commit_2 = local.find_commit(tip(&local, "commit-2")?)?;
+    let commit_3 = local.find_commit(tip(&local, "commit-3")?)?;
+    assert_that!(commit_2.message()).is_equal_to(Some("Commit 2. (#1)\n"));
+    assert_that!(commit_3.message()).is_equal_to(Some("Commit 3.\n"));
+    assert_that!(commit_3.parent_id(0)?).is_equal_to(commit_2.id());
+    assert_that!(local.head()?.target()).is_equal_to(Some(commit_2.id()));
+    assert_that!(tip(&remote, "commit-2")?).is_equal_to(commit_2.id());
+    assert_that!(tip(&remote, "commit-3")?).is_equal_to(commit_3.id());
+
+    let rewritten = std::fs::read_to_string(local_repo.join(".git/rewritten"))?;
+    assert_that!(rewritten.lines().count()).is_equal_to(2);
+    assert_that!(rewritten).contains(format!(" {}\n", commit_2.id()));
+    assert_that!(rewritten).contains(format!(" {}\n", commit_3.id()));
+
+    let transactions =
+        std::fs::read_to_string(local_repo.join(".git/transactions"))?;
+    assert_that!(transactions)
+        .contains(format!(" {} refs/heads/commit-2\n", commit_2.id()));
+    assert_that!(transactions)
+        .contains(format!(" {} refs/heads/commit-3\n", commit_3.id()));
+    assert_that!(transactions).contains(format!(" {} HEAD\n", commit_2.id()));
+
+    Ok(())
+}
+
+/// Tests that the pull request title and body are rendered from the configured
+/// templates, with the stack the pull request is in, and that the stack table
+/// is added to both pull requests in the stack.
+///
+/// ◇ 88defec (main) Initial commit.
+/// ┃
+/// ◯ f8abd0c (commit-2) Commit 2.
+/// ┃
+/// ● 6bb6443 Commit 3.
+#[test]
+fn stacked_with_templates() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (temp_dir, local_repo, _, github, ghpr) = github_repo(&tar_gz!())?;
+    std::fs::write(
+        local_repo.join("pr_body.md"),
+        "{{ body }}\n\nPart {{ stack.position }}, after #{{ stack.parent.number }}.\n",
+    )?;
+
+    write_github_config(
+        temp_dir.path(),
+        &github,
+        r#"
+pr_title_template = "[{{ branch }}] {{ summary }}"
+pr_body_template_file = "pr_body.md"
+"#,
+    )?;
+    let find = github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("head", "owner:commit-3");
+        then.status(200).json_body(json!([]));
+    });
+    let parent = github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("head", "owner:commit-2");
+        then.status(200).json_body(json!([{
+            "number": 9,
+            "html_url": "https://github.com/owner/repo/pull/9",
+            "title": "Commit 2.",
+            "state": "open",
+            "body": "The second commit.",
+        }]));
+    });
+    let children = github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("base", "commit-3");
+        then.status(200).json_body(json!([]));
+    });
+    let create = github.mock(|when, then| {
+        when.method(POST)
+            .path("/repos/owner/repo/pulls")
+            .json_body_partial(
+                r#"{
+                "head": "owner:commit-3",
+                "base": "commit-2",
+                "title": "[commit-3] Commit 3.",
+                "body": "The third commit.\n\nPart 2, after #9."
+            }"#,
+            );
+        then.status(201).json_body(json!({
+            "number": 10,
+            "html_url": "https://github.com/owner/repo/pull/10",
+            "title": "[commit-3] Commit 3.",
+            "state": "open",
+            "body": "The third commit.\n\nPart 2, after #9.",
+        }));
+    });
+    let table = |current: u64| {
+        let marker = |number: u64| if number == current { "👉" } else { "" };
+        format!(
+            "<!-- git-ghpr stack -->\n**Stack**\n\n| | Pull request | State |\n| --- | --- | --- |\n\
+             | {} | [#9](https://github.com/owner/repo/pull/9) Commit 2. | Open |\n\
+             | {} | [#10](https://github.com/owner/repo/pull/10) [commit-3] Commit 3. | Open |\n\
+             <!-- /git-ghpr stack -->",
+            marker(9),
+            marker(10)
+        )
+    };
+    let update_parent = github.mock(|when, then| {
+        when.method(PATCH)
+            .path("/repos/owner/repo/pulls/9")
+            .json_body(json!({
+                "body": format!("The second commit.\n\n{}", table(9)),
+            }));
+        then.status(200).json_body(json!({
+            "number": 9,
+            "html_url": "https://github.com/owner/repo/pull/9",
+        }));
+    });
+    let update_created = github.mock(|when, then| {
+        when.method(PATCH)
+            .path("/repos/owner/repo/pulls/10")
+            .json_body(json!({
+                "body": format!(
+                    "The third commit.\n\nPart 2, after #9.\n\n{}",
+                    table(10)
+                ),
+            }));
+        then.status(200).json_body(json!({
+            "number": 10,
+            "html_url": "https://github.com/owner/repo/pull/10",
+        }));
+    });
+
+    //
+    // Act.
+    //
+    let output = ghpr.command("create", &[]).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).is_empty();
+    assert_that!(stdout!(output)?).is_equal_to(
+        "Created pull request https://github.com/owner/repo/pull/10\n"
+            .to_string(),
+    );
+    assert_that!(output.status.success()).is_true();
+    find.assert();
+    parent.assert();
+    children.assert();
+    create.assert();
+    update_parent.assert();
+    update_created.assert();
+
+    Ok(())
+}
+
+/// Tests that the commit message is merged into the pull request template
+/// picked with `--template`, from the repository's `PULL_REQUEST_TEMPLATE`
+/// directory.
+#[test]
+fn repository_template() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (temp_dir, local_repo, _, github, ghpr) =
+        github_repo("no_branch.tar.gz")?;
+    let templates = local_repo.join(".github").join("PULL_REQUEST_TEMPLATE");
+    std::fs::create_dir_all(&templates)?;
+    std::fs::write(
+        templates.join("bugfix.md"),
+        "## Summary\n\n## Checklist\n\n- [ ] Regression test added\n",
+    )?;
+    std::fs::write(templates.join("feature.md"), "## Summary\n")?;
+
+    write_github_config(
+        temp_dir.path(),
+        &github,
+        r#"
+pr_body_template = "Fixes the {{ summary }}"
+pr_template_section = "Summary"
+"#,
+    )?;
+    github.mock(|when, then| {
+        when.method(GET).path("/repos/owner/repo/pulls");
+        then.status(200).json_body(json!([]));
+    });
+    let create = github.mock(|when, then| {
+        when.method(POST).path("/repos/owner/repo/pulls").json_body_partial(
+            r###"{
+                "body": "## Summary\n\nFixes the Commit 2.\n\n## Checklist\n\n- [ ] Regression test added\n"
+            }"###,
+        );
+        then.status(201).json_body(json!({
+            "number": 3,
+            "html_url": "https://github.com/owner/repo/pull/3",
+        }));
+    });
+
+    //
+    // Act.
+    //
+    let output = ghpr.command("create", &["--template", "bugfix"]).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).is_empty();
+    assert_that!(stdout!(output)?).is_equal_to(
+        "Created pull request https://github.com/owner/repo/pull/3\n"
+            .to_string(),
+    );
+    assert_that!(output.status.success()).is_true();
+    create.assert();
+
+    Ok(())
+}
+
+/// Tests the error when `--template` names a template that doesn't exist.
+#[test]
+fn unknown_repository_template() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (_temp_dir, local_repo, _, github, ghpr) =
+        github_repo("no_branch.tar.gz")?;
+    let templates = local_repo.join("PULL_REQUEST_TEMPLATE");
+    std::fs::create_dir_all(&templates)?;
+    std::fs::write(templates.join("bugfix.md"), "## Summary\n")?;
+    std::fs::write(templates.join("feature.md"), "## Summary\n")?;
+
+    github.mock(|when, then| {
+        when.method(GET).path("/repos/owner/repo/pulls");
+        then.status(200).json_body(json!([]));
+    });
+
+    //
+    // Act.
+    //
+    let output = ghpr.command("create", &["--template", "docs"]).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stdout!(output)?).is_empty();
+    assert_that!(stderr!(output)?).is_equal_to(
+        "There is no pull request template named docs. The templates are: bugfix.md, feature.md.
+The local branches and HEAD were put back as they were.\n"
+            .to_string(),
+    );
+    assert_that!(output.status.success()).is_false();
+
+    Ok(())
+}
+
+/// Tests that `--edit` opens the editor on the pull request message, and the
+/// edited message is used for the pull request.
+#[test]
+fn edit_message() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (_temp_dir, local_repo, _, github, ghpr) =
+        github_repo("no_branch.tar.gz")?;
+
+    github.mock(|when, then| {
+        when.method(GET).path("/repos/owner/repo/pulls");
+        then.status(200).json_body(json!([]));
+    });
+    let create = github.mock(|when, then| {
+        when.method(POST)
+            .path("/repos/owner/repo/pulls")
+            .json_body_partial(
+                r#"{"title": "Edited title", "body": "Edited body."}"#,
+            );
+        then.status(201).json_body(json!({
+            "number": 4,
+            "html_url": "https://github.com/owner/repo/pull/4",
+        }));
+    });
+
+    //
+    // Act.
+    //
+    let output = ghpr
+        .command("create", &["--edit"])
+        .env(
+            "GIT_EDITOR",
+            "sed -i -e 's/^Commit 2\\./Edited title\\n\\nEdited body./'",
+        )
+        .output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).is_empty();
+    assert_that!(stdout!(output)?).is_equal_to(
+        "Created pull request https://github.com/owner/repo/pull/4\n"
+            .to_string(),
+    );
+    assert_that!(output.status.success()).is_true();
+    assert_that!(local_repo.join(".git").join("GHPR_EDITMSG").exists())
+        .is_false();
+    create.assert();
+
+    Ok(())
+}
+
+/// Tests that emptying the message in the editor aborts the pull request,
+/// before anything is pushed.
+#[test]
+fn edit_empty_message() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (_temp_dir, _, remote_repo, github, ghpr) =
+        github_repo("no_branch.tar.gz")?;
+
+    github.mock(|when, then| {
+        when.method(GET).path("/repos/owner/repo/pulls");
+        then.status(200).json_body(json!([]));
+    });
+    let create = github.mock(|when, then| {
+        when.method(POST).path("/repos/owner/repo/pulls");
+        then.status(201);
+    });
+
+    //
+    // Act.
+    //
+    let output = ghpr
+        .command("create", &["--edit"])
+        .env("GIT_EDITOR", "truncate -s 0")
+        .output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stdout!(output)?).is_empty();
+    assert_that!(stderr!(output)?).is_equal_to(
+        "Aborting the pull request due to an empty message.\nThe local \
+         branches and HEAD were put back as they were.\n"
+            .to_string(),
+    );
+    assert_that!(output.status.success()).is_false();
+    assert_that!(has_branch(&remote_repo, "commit-2"))
+        .is_ok()
+        .is_false();
+    create.assert_hits(0);
+
+    Ok(())
+}
+
+/// Tests that an edited message is kept when creating the pull request fails,
+/// and used when it is tried again.
+#[test]
+fn edit_message_reused() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (_temp_dir, local_repo, _, github, ghpr) =
+        github_repo("no_branch.tar.gz")?;
+
+    github.mock(|when, then| {
+        when.method(GET).path("/repos/owner/repo/pulls");
+        then.status(200).json_body(json!([]));
+    });
+    let mut failed_create = github.mock(|when, then| {
+        when.method(POST).path("/repos/owner/repo/pulls");
+        then.status(502)
+            .json_body(json!({"message": "Server Error"}));
+    });
+    let scratch_file = local_repo.join(".git").join("GHPR_EDITMSG");
+
+    //
+    // Act.
+    //
+    let failed = ghpr
+        .command("create", &["--edit"])
+        .env("GIT_EDITOR", "sed -i -e 's/^Commit 2\\./Edited title/'")
+        .output()?;
+    let saved = scratch_file.exists();
+    failed_create.delete();
+    let create = github.mock(|when, then| {
+        when.method(POST)
+            .path("/repos/owner/repo/pulls")
+            .json_body_partial(r#"{"title": "Edited title"}"#);
+        then.status(201).json_body(json!({
+            "number": 6,
+            "html_url": "https://github.com/owner/repo/pull/6",
+        }));
+    });
+    let output = ghpr.command("create", &[]).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(failed)?).starts_with(
+        "Github request failed (502): Server Error\nThe local branches and \
+         HEAD were put back as they were.",
+    );
+    assert_that!(failed.status.success()).is_false();
+    assert_that!(saved).is_true();
+    assert_that!(stderr!(output)?).is_empty();
+    assert_that!(stdout!(output)?).is_equal_to(
+        "Created pull request https://github.com/owner/repo/pull/6\n"
+            .to_string(),
+    );
+    assert_that!(output.status.success()).is_true();
+    assert_that!(scratch_file.exists()).is_false();
+    create.assert();
+
+    Ok(())
+}
+
+/// Tests that the draft flag, reviewers, labels, assignees and milestone are
+/// set on a new pull request, with the reviewers coming from the configuration
+/// file, and that ones that can't be set are warnings.
+#[test]
+fn pull_request_options() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (temp_dir, _, _, github, ghpr) = github_repo("no_branch.tar.gz")?;
+
+    write_github_config(
+        temp_dir.path(),
+        &github,
+        "default_reviewers = [\"octocat\", \"owner/docs\", \"stranger\"]\n",
+    )?;
+    github.mock(|when, then| {
+        when.method(GET).path("/repos/owner/repo/pulls");
+        then.status(200).json_body(json!([]));
+    });
+    let create = github.mock(|when, then| {
+        when.method(POST)
+            .path("/repos/owner/repo/pulls")
+            .json_body_partial(r#"{"draft": true}"#);
+        then.status(201).json_body(json!({
+            "number": 4,
+            "html_url": "https://github.com/owner/repo/pull/4",
+        }));
+    });
+    let user_review = github.mock(|when, then| {
+        when.method(POST)
+            .path("/repos/owner/repo/pulls/4/requested_reviewers")
+            .json_body(json!({"reviewers": ["octocat"], "team_reviewers": []}));
+        then.status(201).json_body(json!({}));
+    });
+    let team_review = github.mock(|when, then| {
+        when.method(POST)
+            .path("/repos/owner/repo/pulls/4/requested_reviewers")
+            .json_body(json!({"reviewers": [], "team_reviewers": ["docs"]}));
+        then.status(201).json_body(json!({}));
+    });
+    github.mock(|when, then| {
+        when.method(POST)
+            .path("/repos/owner/repo/pulls/4/requested_reviewers")
+            .json_body(
+                json!({"reviewers": ["stranger"], "team_reviewers": []}),
+            );
+        then.status(422).json_body(json!({
+            "message": "Reviews may only be requested from collaborators."
+        }));
+    });
+    github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/labels/needs%20review");
+        then.status(200).json_body(json!({"name": "needs review"}));
+    });
+    github.mock(|when, then| {
+        when.method(GET).path("/repos/owner/repo/labels/nonsense");
+        then.status(404).json_body(json!({"message": "Not Found"}));
+    });
+    let labels = github.mock(|when, then| {
+        when.method(POST)
+            .path("/repos/owner/repo/issues/4/labels")
+            .json_body(json!({"labels": ["needs review"]}));
+        then.status(200).json_body(json!([]));
+    });
+    github.mock(|when, then| {
+        when.method(GET).path("/repos/owner/repo/assignees/hubot");
+        then.status(204);
+    });
+    let assignees = github.mock(|when, then| {
+        when.method(POST)
+            .path("/repos/owner/repo/issues/4/assignees")
+            .json_body(json!({"assignees": ["hubot"]}));
+        then.status(201).json_body(json!({}));
+    });
+    let first_page: Vec<_> = (1..=100)
+        .map(|n| json!({"number": n, "title": format!("v0.{n}")}))
+        .collect();
+    github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/milestones")
+            .query_param("state", "open")
+            .query_param("page", "1");
+        then.status(200).json_body(json!(first_page));
+    });
+    github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/milestones")
+            .query_param("state", "open")
+            .query_param("page", "2");
+        then.status(200)
+            .json_body(json!([{"number": 101, "title": "v2.0"}]));
+    });
+    let milestone = github.mock(|when, then| {
+        when.method(PATCH)
+            .path("/repos/owner/repo/issues/4")
+            .json_body(json!({"milestone": 101}));
+        then.status(200).json_body(json!({}));
+    });
+
+    //
+    // Act.
+    //
+    let output = ghpr
+        .command(
+            "create",
+            &[
+                "--draft",
+                "--label",
+                "needs review,nonsense",
+                "--assignee",
+                "hubot",
+                "--milestone",
+                "v2.0",
+            ],
+        )
+        .output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stdout!(output)?).is_equal_to(
+        "Created pull request https://github.com/owner/repo/pull/4\n"
+            .to_string(),
+    );
+    assert_that!(stderr!(output)?).is_equal_to(
+        r#"Warning: Could not request a review from stranger: Github request failed (422): Reviews may only be requested from collaborators.
+Warning: There is no label nonsense in github.com/owner/repo.
+"#
+        .to_string(),
+    );
+    assert_that!(output.status.success()).is_true();
+    create.assert();
+    user_review.assert();
+    team_review.assert();
+    labels.assert();
+    assignees.assert();
+    milestone.assert();
+
+    Ok(())
+}
+
+/// Tests that `--code-owners` requests reviews from the code owners of the
+/// changed files, leaving out the user opening the pull request.
+#[test]
+fn code_owners() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (_temp_dir, _, _, github, ghpr) = github_repo(&tar_gz!())?;
+
+    github.mock(|when, then| {
+        when.method(GET).path("/repos/owner/repo/pulls");
+        then.status(200).json_body(json!([]));
+    });
+    github.mock(|when, then| {
+        when.method(GET).path("/user");
+        then.status(200).json_body(json!({"login": "writer"}));
+    });
+    github.mock(|when, then| {
+        when.method(POST).path("/repos/owner/repo/pulls");
+        then.status(201).json_body(json!({
+            "number": 4,
+            "html_url": "https://github.com/owner/repo/pull/4",
+        }));
+    });
+    let user_review = github.mock(|when, then| {
+        when.method(POST)
+            .path("/repos/owner/repo/pulls/4/requested_reviewers")
+            .json_body(json!({"reviewers": ["octocat"], "team_reviewers": []}));
+        then.status(201).json_body(json!({}));
+    });
+    let team_review = github.mock(|when, then| {
+        when.method(POST)
+            .path("/repos/owner/repo/pulls/4/requested_reviewers")
+            .json_body(json!({"reviewers": [], "team_reviewers": ["docs"]}));
+        then.status(201).json_body(json!({}));
+    });
+
+    //
+    // Act.
+    //
+    let output = ghpr
+        .command("create", &["--code-owners", "--reviewer", "octocat"])
+        .output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).is_empty();
+    assert_that!(stdout!(output)?).is_equal_to(
+        "Created pull request https://github.com/owner/repo/pull/4\n"
+            .to_string(),
+    );
+    assert_that!(output.status.success()).is_true();
+    user_review.assert();
+    team_review.assert();
+
+    Ok(())
+}
+
+/// Tests that `land` merges the pull request at the bottom of the stack with
+/// the configured method, moves the one above it onto the main branch, and
+/// deletes the merged branch.
+#[test]
+fn land() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (temp_dir, _, remote_repo, github, ghpr) = github_repo(&tar_gz!())?;
+
+    write_github_config(
+        temp_dir.path(),
+        &github,
+        "merge_method = \"squash\"\n",
+    )?;
+    github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("head", "owner:commit-2");
+        then.status(200).json_body(json!([{
+            "number": 9,
+            "html_url": "https://github.com/owner/repo/pull/9",
+        }]));
+    });
+    let merge = github.mock(|when, then| {
+        when.method(PUT)
+            .path("/repos/owner/repo/pulls/9/merge")
+            .json_body_partial(r#"{"merge_method": "squash"}"#);
+        then.status(200).json_body(json!({
+            "sha": "6dcb09b5b57875f334f61aebed695e2e4193db5e",
+            "merged": true,
+        }));
+    });
+    github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("base", "commit-2");
+        then.status(200).json_body(json!([{
+            "number": 10,
+            "html_url": "https://github.com/owner/repo/pull/10",
+        }]));
+    });
+    let retarget = github.mock(|when, then| {
+        when.method(PATCH)
+            .path("/repos/owner/repo/pulls/10")
+            .json_body(json!({"base": "main"}));
+        then.status(200).json_body(json!({
+            "number": 10,
+            "html_url": "https://github.com/owner/repo/pull/10",
+        }));
+    });
+
+    //
+    // Act.
+    //
+    let output = ghpr
+        .command("land", &[])
+        // Fetches go to the local stand-in for the remote too.
+        .env("GIT_CONFIG_COUNT", "1")
+        .env(
+            "GIT_CONFIG_KEY_0",
+            format!("url.{}.insteadOf", remote_repo.display()),
+        )
+        .env("GIT_CONFIG_VALUE_0", "https://github.com/owner/repo.git")
+        .output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).is_empty();
+    assert_that!(stdout!(output)?).is_equal_to(
+        r#"Landed pull request https://github.com/owner/repo/pull/9
+Moved pull request https://github.com/owner/repo/pull/10 onto main
+Run `git branchless sync` to move the rest of the stack onto main.
+"#
+        .to_string(),
+    );
+    assert_that!(output.status.success()).is_true();
+    assert_that!(has_branch(&remote_repo, "commit-2"))
+        .is_ok()
+        .is_false();
+    assert_that!(has_branch(&remote_repo, "commit-3"))
+        .is_ok()
+        .is_true();
+    merge.assert();
+    retarget.assert();
+
+    Ok(())
+}
+
+/// Tests that `checkout` follows the bases of a pull request down to the main
+/// branch, fetches a branch for each pull request, and checks out the top.
+/// Checking out again after the stack is rebased moves the branches along.
+#[test]
+fn checkout() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (_temp_dir, local_repo, remote_repo, github, ghpr) =
+        github_repo(&tar_gz!())?;
+
+    github.mock(|when, then| {
+        when.method(GET).path("/repos/owner/repo/pulls/10");
+        then.status(200).json_body(json!({
+            "number": 10,
+            "html_url": "https://github.com/owner/repo/pull/10",
+            "head": {"ref": "stack-2"},
+            "base": {"ref": "stack-1"},
+        }));
+    });
+    github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("head", "owner:stack-1");
+        then.status(200).json_body(json!([{
+            "number": 9,
+            "html_url": "https://github.com/owner/repo/pull/9",
+            "head": {"ref": "stack-1"},
+            "base": {"ref": "main"},
+        }]));
+    });
+    github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("head", "owner:main");
+        then.status(200).json_body(json!([]));
+    });
+
+    let checkout = || {
+        ghpr.command("checkout", &["https://github.com/owner/repo/pull/10"])
+            // Fetches go to the local stand-in for the remote too.
+            .env("GIT_CONFIG_COUNT", "1")
+            .env(
+                "GIT_CONFIG_KEY_0",
+                format!("url.{}.insteadOf", remote_repo.display()),
+            )
+            .env("GIT_CONFIG_VALUE_0", "https://github.com/owner/repo.git")
+            .output()
+    };
+
+    //
+    // Act.
+    //
+    let output = checkout()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).is_empty();
+    assert_that!(stdout!(output)?).is_equal_to(
+        r#"Fetched pull request https://github.com/owner/repo/pull/9 into pr/9
+Fetched pull request https://github.com/owner/repo/pull/10 into pr/10
+Checked out pr/10
+"#
+        .to_string(),
+    );
+    assert_that!(output.status.success()).is_true();
+    assert_that!(has_branch(&local_repo, "pr/9"))
+        .is_ok()
+        .is_true();
+    assert_that!(is_detached_at(&local_repo, "pr/10"))
+        .is_ok()
+        .is_true();
+
+    // The author rebases the top of the stack straight onto main.
+    let remote = git2::Repository::open(&remote_repo)?;
+    let top = remote.revparse_single("stack-2")?.peel_to_commit()?;
+    let main = remote.revparse_single("main")?.peel_to_commit()?;
+    let rebased = remote.commit(
+        None,
+        &top.author(),
+        &top.committer(),
+        "Commit 3, rebased.",
+        &top.tree()?,
+        &[&main],
+    )?;
+    remote.reference("refs/pull/10/head", rebased, true, "test")?;
+
+    let again = checkout()?;
+    assert_that!(stderr!(again)?).is_empty();
+    assert_that!(again.status.success()).is_true();
+    assert_that!(git2::Repository::open(&local_repo)?
+        .revparse_single("pr/10")?
+        .id())
+    .is_equal_to(rebased);
+
+    Ok(())
+}
+
+/// Tests that `close` comments on and closes the pull request of a branch,
+/// moves the pull request stacked on it onto its base, and deletes the branch.
+#[test]
+fn close() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (_temp_dir, local_repo, remote_repo, github, ghpr) =
+        github_repo("land.tar.gz")?;
+
+    github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("head", "owner:commit-2");
+        then.status(200).json_body(json!([{
+            "number": 9,
+            "html_url": "https://github.com/owner/repo/pull/9",
+            "base": {"ref": "main"},
+        }]));
+    });
+    let comment = github.mock(|when, then| {
+        when.method(POST)
+            .path("/repos/owner/repo/issues/9/comments")
+            .json_body(json!({"body": "Not needed after all."}));
+        then.status(201).json_body(json!({"id": 1}));
+    });
+    let close = github.mock(|when, then| {
+        when.method(PATCH)
+            .path("/repos/owner/repo/pulls/9")
+            .json_body(json!({"state": "closed"}));
+        then.status(200).json_body(json!({
+            "number": 9,
+            "html_url": "https://github.com/owner/repo/pull/9",
+        }));
+    });
+    github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("base", "commit-2");
+        then.status(200).json_body(json!([{
+            "number": 10,
+            "html_url": "https://github.com/owner/repo/pull/10",
+        }]));
+    });
+    let retarget = github.mock(|when, then| {
+        when.method(PATCH)
+            .path("/repos/owner/repo/pulls/10")
+            .json_body(json!({"base": "main"}));
+        then.status(200).json_body(json!({
+            "number": 10,
+            "html_url": "https://github.com/owner/repo/pull/10",
+        }));
+    });
+
+    //
+    // Act.
+    //
+    let output = ghpr
+        .command(
+            "close",
+            &[
+                "--branch",
+                "commit-2",
+                "--comment",
+                "Not needed after all.",
+                "--delete-local",
+            ],
+        )
+        .output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).is_empty();
+    assert_that!(stdout!(output)?).is_equal_to(
+        r#"Closed pull request https://github.com/owner/repo/pull/9
+Moved pull request https://github.com/owner/repo/pull/10 onto main
+Deleted commit-2
+"#
+        .to_string(),
+    );
+    assert_that!(output.status.success()).is_true();
+    assert_that!(has_branch(&remote_repo, "commit-2"))
+        .is_ok()
+        .is_false();
+    assert_that!(has_branch(&local_repo, "commit-2"))
+        .is_ok()
+        .is_false();
+    comment.assert();
+    close.assert();
+    retarget.assert();
+
+    Ok(())
+}
+
+/// Tests that `close` moves every pull request stacked on the branch, even the
+/// ones past the first page of the list from Github.
+#[test]
+fn close_retargets_every_page() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (_temp_dir, _, _, github, ghpr) = github_repo("land.tar.gz")?;
+
+    github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("head", "owner:commit-2");
+        then.status(200).json_body(json!([{
+            "number": 9,
+            "html_url": "https://github.com/owner/repo/pull/9",
+            "base": {"ref": "main"},
+        }]));
+    });
+    github.mock(|when, then| {
+        when.method(PATCH)
+            .path("/repos/owner/repo/pulls/9")
+            .json_body(json!({"state": "closed"}));
+        then.status(200).json_body(json!({
+            "number": 9,
+            "html_url": "https://github.com/owner/repo/pull/9",
+        }));
+    });
+    let first_page: Vec<_> = (100..200)
+        .map(|n| {
+            json!({
+                "number": n,
+                "html_url": format!("https://github.com/owner/repo/pull/{n}"),
+            })
+        })
+        .collect();
+    github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("base", "commit-2")
+            .query_param("page", "1");
+        then.status(200).json_body(json!(first_page));
+    });
+    github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("base", "commit-2")
+            .query_param("page", "2");
+        then.status(200).json_body(json!([{
+            "number": 10,
+            "html_url": "https://github.com/owner/repo/pull/10",
+        }]));
+    });
+    let retarget = github.mock(|when, then| {
+        when.method(PATCH)
+            .path_matches(Regex::new(r"^/repos/owner/repo/pulls/\d+$").unwrap())
+            .json_body(json!({"base": "main"}));
+        then.status(200).json_body(json!({
+            "number": 10,
+            "html_url": "https://github.com/owner/repo/pull/10",
+        }));
+    });
+
+    //
+    // Act.
+    //
+    let output = ghpr.command("close", &["--branch", "commit-2"]).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).is_empty();
+    assert_that!(stdout!(output)?).contains(
+        "Moved pull request https://github.com/owner/repo/pull/10 onto main\n",
+    );
+    assert_that!(output.status.success()).is_true();
+    retarget.assert_hits(101);
+
+    Ok(())
+}
+
+/// Tests that `automerge` adds the pull request of the selected commit to the
+/// merge queue of its base branch, when there is one.
+#[test]
+fn automerge_merge_queue() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (_temp_dir, _, _, github, ghpr) = github_repo("land.tar.gz")?;
+
+    github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("head", "owner:commit-3");
+        then.status(200).json_body(json!([{
+            "number": 10,
+            "html_url": "https://github.com/owner/repo/pull/10",
+            "node_id": "PR_10",
+            "base": {"ref": "commit-2"},
+        }]));
+    });
+    github.mock(|when, then| {
+        when.method(POST)
+            .path("/graphql")
+            .body_contains("mergeQueue")
+            .json_body_partial(
+                r#"{"variables": {"owner": "owner", "name": "repo", "branch": "commit-2"}}"#,
+            );
+        then.status(200).json_body(json!({"data": {"repository": {
+            "autoMergeAllowed": false,
+            "mergeQueue": {"id": "MQ_1"},
+        }}}));
+    });
+    let enqueue = github.mock(|when, then| {
+        when.method(POST)
+            .path("/graphql")
+            .body_contains("enqueuePullRequest")
+            .json_body_partial(r#"{"variables": {"id": "PR_10"}}"#);
+        then.status(200).json_body(json!({"data": {
+            "enqueuePullRequest": {"clientMutationId": null},
+        }}));
+    });
+
+    //
+    // Act.
+    //
+    let output = ghpr.command("automerge", &[]).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).is_empty();
+    assert_that!(stdout!(output)?).is_equal_to(
+        "Added pull request https://github.com/owner/repo/pull/10 to the merge queue\n"
+            .to_string(),
+    );
+    assert_that!(output.status.success()).is_true();
+    enqueue.assert();
+
+    Ok(())
+}
+
+/// Tests that `automerge` explains when the repository allows neither
+/// auto-merge nor has a merge queue.
+#[test]
+fn automerge_not_allowed() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (_temp_dir, _, _, github, ghpr) = github_repo("land.tar.gz")?;
+
+    github.mock(|when, then| {
+        when.method(GET).path("/repos/owner/repo/pulls");
+        then.status(200).json_body(json!([{
+            "number": 10,
+            "html_url": "https://github.com/owner/repo/pull/10",
+            "node_id": "PR_10",
+            "base": {"ref": "commit-2"},
+        }]));
+    });
+    github.mock(|when, then| {
+        when.method(POST)
+            .path("/graphql")
+            .body_contains("mergeQueue");
+        then.status(200).json_body(json!({"data": {"repository": {
+            "autoMergeAllowed": false,
+            "mergeQueue": null,
+        }}}));
+    });
+    let mutation = github.mock(|when, then| {
+        when.method(POST).path("/graphql").body_contains("mutation");
+        then.status(200).json_body(json!({"data": {}}));
+    });
+
+    //
+    // Act.
+    //
+    let output = ghpr.command("automerge", &[]).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stdout!(output)?).is_empty();
+    assert_that!(stderr!(output)?).is_equal_to(
+        "github.com/owner/repo doesn't allow auto-merge, and has no merge queue for the base branch. Auto-merge can be allowed in the repository settings.\n"
+            .to_string(),
+    );
+    assert_that!(output.status.success()).is_false();
+    mutation.assert_hits(0);
+
+    Ok(())
+}
+
+/// Tests that `create --auto-merge` turns on auto-merge with the configured
+/// merge method, and that failing to is only a warning.
+#[test]
+fn create_with_auto_merge() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (temp_dir, _, _, github, ghpr) = github_repo("no_branch.tar.gz")?;
+
+    write_github_config(
+        temp_dir.path(),
+        &github,
+        "merge_method = \"rebase\"\n",
+    )?;
+    github.mock(|when, then| {
+        when.method(GET).path("/repos/owner/repo/pulls");
+        then.status(200).json_body(json!([]));
+    });
+    github.mock(|when, then| {
+        when.method(POST).path("/repos/owner/repo/pulls");
+        then.status(201).json_body(json!({
+            "number": 4,
+            "html_url": "https://github.com/owner/repo/pull/4",
+            "node_id": "PR_4",
+        }));
+    });
+    github.mock(|when, then| {
+        when.method(POST)
+            .path("/graphql")
+            .body_contains("mergeQueue")
+            .json_body_partial(r#"{"variables": {"branch": "main"}}"#);
+        then.status(200).json_body(json!({"data": {"repository": {
+            "autoMergeAllowed": true,
+            "mergeQueue": null,
+        }}}));
+    });
+    let auto_merge = github.mock(|when, then| {
+        when.method(POST)
+            .path("/graphql")
+            .body_contains("enablePullRequestAutoMerge")
+            .json_body_partial(
+                r#"{"variables": {"id": "PR_4", "method": "REBASE"}}"#,
+            );
+        then.status(200).json_body(json!({
+            "data": {"enablePullRequestAutoMerge": null},
+            "errors": [{"message": "Pull request is in clean status"}],
+        }));
+    });
+
+    //
+    // Act.
+    //
+    let output = ghpr.command("create", &["--auto-merge"]).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stdout!(output)?).is_equal_to(
+        "Created pull request https://github.com/owner/repo/pull/4\n"
+            .to_string(),
+    );
+    assert_that!(stderr!(output)?).is_equal_to(
+        "Warning: Could not turn on auto-merge: Github request failed: Pull request is in clean status\n"
+            .to_string(),
+    );
+    assert_that!(output.status.success()).is_true();
+    auto_merge.assert();
+
+    Ok(())
+}
+
+/// Sets up the stand-in for the Github API for the `cleanup` tests, where
+/// `commit-2` has a merged pull request and `wip` an open one.
+fn cleanup_github(github: &MockServer) {
+    github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("head", "owner:commit-2");
+        then.status(200).json_body(json!([{
+            "number": 2,
+            "html_url": "https://github.com/owner/repo/pull/2",
+            "state": "closed",
+            "merged_at": "2024-01-01T00:00:00Z",
+        }]));
+    });
+    github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("head", "owner:wip");
+        then.status(200).json_body(json!([{
+            "number": 4,
+            "html_url": "https://github.com/owner/repo/pull/4",
+            "state": "open",
+        }]));
+    });
+    github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("base", "commit-2");
+        then.status(200).json_body(json!([]));
+    });
+}
+
+/// Tests that `cleanup` deletes the branches that are in the main branch,
+/// including by squash merge, and the ones with merged pull requests, but not
+/// the one checked out.
+#[test]
+fn cleanup() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (_temp_dir, local_repo, remote_repo, github, ghpr) =
+        github_repo(&tar_gz!())?;
+    cleanup_github(&github);
+
+    //
+    // Act.
+    //
+    let output = ghpr.command("cleanup", &[]).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).is_empty();
+    assert_that!(stdout!(output)?).is_equal_to(
+        r#"Deleted commit-2: pull request #2 was merged
+Deleted old: it is in main
+Deleted squashed: it was squash merged into main
+Kept current: it is in main, but it is checked out
+"#
+        .to_string(),
+    );
+    assert_that!(output.status.success()).is_true();
+    for deleted in ["commit-2", "old", "squashed"] {
+        assert_that!(has_branch(&local_repo, deleted))
+            .is_ok()
+            .is_false();
+    }
+    for kept in ["current", "wip", "main"] {
+        assert_that!(has_branch(&local_repo, kept))
+            .is_ok()
+            .is_true();
+    }
+    assert_that!(has_branch(&remote_repo, "commit-2"))
+        .is_ok()
+        .is_false();
+    assert_that!(has_branch(&remote_repo, "wip"))
+        .is_ok()
+        .is_true();
+
+    Ok(())
+}
+
+/// Tests that `cleanup` moves the pull requests stacked on the branch of a
+/// closed pull request onto its base before deleting the branch, so Github
+/// doesn't close them too.
+#[test]
+fn cleanup_retargets_children() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (_temp_dir, _, remote_repo, github, ghpr) =
+        github_repo("cleanup.tar.gz")?;
+    github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("head", "owner:commit-2");
+        then.status(200).json_body(json!([{
+            "number": 2,
+            "html_url": "https://github.com/owner/repo/pull/2",
+            "state": "closed",
+            "base": {"ref": "main"},
+        }]));
+    });
+    github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("head", "owner:wip");
+        then.status(200).json_body(json!([{
+            "number": 4,
+            "html_url": "https://github.com/owner/repo/pull/4",
+            "state": "open",
+        }]));
+    });
+    github.mock(|when, then| {
+        when.method(GET)
+            .path("/repos/owner/repo/pulls")
+            .query_param("base", "commit-2");
+        then.status(200).json_body(json!([{
+            "number": 5,
+            "html_url": "https://github.com/owner/repo/pull/5",
+        }]));
+    });
+    let retarget = github.mock(|when, then| {
+        when.method(PATCH)
+            .path("/repos/owner/repo/pulls/5")
+            .json_body(json!({"base": "main"}));
+        then.status(200).json_body(json!({
+            "number": 5,
+            "html_url": "https://github.com/owner/repo/pull/5",
+        }));
+    });
+
+    //
+    // Act.
+    //
+    let output = ghpr.command("cleanup", &[]).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).is_empty();
+    assert_that!(stdout!(output)?)
+        .contains("Deleted commit-2: pull request #2 was closed\n");
+    assert_that!(stdout!(output)?).ends_with(
+        "Moved pull request https://github.com/owner/repo/pull/5 onto main\n",
+    );
+    assert_that!(output.status.success()).is_true();
+    assert_that!(has_branch(&remote_repo, "commit-2"))
+        .is_ok()
+        .is_false();
+    retarget.assert();
+
+    Ok(())
+}
+
+/// Tests that `cleanup --dry-run` lists the branches without deleting them.
+#[test]
+fn cleanup_dry_run() -> Result<()> {
+    //
+    // Arrange.
+    //
+    let (_temp_dir, local_repo, remote_repo, github, ghpr) =
+        github_repo("cleanup.tar.gz")?;
+    cleanup_github(&github);
+
+    //
+    // Act.
+    //
+    let output = ghpr.command("cleanup", &["--dry-run"]).output()?;
+
+    //
+    // Assert.
+    //
+    assert_that!(stderr!(output)?).is_empty();
+    assert_that!(stdout!(output)?).is_equal_to(
+        r#"Would delete commit-2: pull request #2 was merged
+Would delete old: it is in main
+Would delete squashed: it was squash merged into main
+Kept current: it is in main, but it is checked out
+"#
+        .to_string(),
+    );
+    assert_that!(output.status.success()).is_true();
+    for branch in ["commit-2", "old", "squashed"] {
+        assert_that!(has_branch(&local_repo, branch))
+            .is_ok()
+            .is_true();
+    }
+    assert_that!(has_branch(&remote_repo, "commit-2"))
+        .is_ok()
+        .is_true();
+
+    Ok(())
+}
+
+/// Restores the repository in `tar_gz` with `origin` pointing at
+/// `owner/repo` on github.com, pushes to which go to the returned remote
+/// repository, and github.com pointed at the returned stand-in for the Github
+/// API. The temporary directory the repository is in is the home directory
+/// for the commands built by the returned `Ghpr`.
+fn github_repo(
+    tar_gz: &str,
+) -> Result<(TempDir, PathBuf, PathBuf, MockServer, Ghpr)> {
+    let (temp_dir, local_repo) = restore_git_repo(tar_gz)?;
+    let remote_repo = temp_dir.path().join("remote_repo");
+    use_github_remote(
+        &local_repo,
+        "origin",
+        "https://github.com/owner/repo.git",
+        &remote_repo,
+    )?;
+    let github = MockServer::start();
+    write_github_config(temp_dir.path(), &github, "")?;
+    let ghpr = Ghpr::new(temp_dir.path(), &local_repo)?;
+    Ok((temp_dir, local_repo, remote_repo, github, ghpr))
+}
+
+/// Writes the configuration file in `home`, with `config` followed by
+/// `github.com` pointed at the stand-in for the Github API.
+fn write_github_config(
+    home: &Path,
+    github: &MockServer,
+    config: &str,
+) -> Result<()> {
+    write_config(
+        home,
+        &format!(
+            "{config}\n[hosts.\"github.com\"]\napi_url = \"{}\"\n",
+            github.base_url()
+        ),
+    )
+}
+
+/// Builds commands for the binary under test, run in a test repository. The
+/// home directory is moved, and tokens and configuration in the environment
+/// removed, so those of the user running the tests aren't picked up.
+struct Ghpr {
+    run: CargoRun,
+    home: PathBuf,
+    local_repo: PathBuf,
+}
+
+impl Ghpr {
+    fn new(home: &Path, local_repo: &Path) -> Result<Self> {
+        Ok(Ghpr {
+            run: escargot::CargoBuild::new()
+                .bin(TEST_BINARY)
+                .current_release()
+                .current_target()
+                .run()?,
+            home: home.to_path_buf(),
+            local_repo: local_repo.to_path_buf(),
+        })
+    }
+
+    /// Builds the `subcommand` command, with `args`.
+    fn command(&self, subcommand: &str, args: &[&str]) -> Command {
+        let mut command = self.run.command();
+        command
+            .current_dir(&self.local_repo)
+            .env("HOME", &self.home);
+        for variable in TOKEN_VARIABLES {
+            command.env_remove(variable);
+        }
+        command
+            .env("GH_PR_TOKEN", "test-token")
+            .arg(subcommand)
+            .args(args);
+        command
+    }
 }